@@ -0,0 +1,105 @@
+//! Benchmarks for the `Reactor` event loop.
+//!
+//! Run with `cargo bench --target wasm32-wasip2 --bench reactor`.
+//!
+//! Each benchmark keeps a number of idle tasks parked on timers which will
+//! not fire during the benchmark, and measures the cost of the event loop
+//! making progress on a single busy task. Ideally the per-iteration cost is
+//! dominated by the readiness changes of the busy task, rather than by the
+//! number of idle tasks.
+
+use std::hint::black_box;
+use wstd::runtime::{Reactor, block_on, spawn};
+use wstd::time::{Duration, Instant, Timer};
+
+const IDLE_TASKS: &[usize] = &[0, 10, 100, 1_000, 10_000];
+const ITERATIONS: u32 = 1_000;
+
+fn main() {
+    println!("{:<40} {:>12} {:>14}", "benchmark", "idle tasks", "ns/iter");
+    for &idle in IDLE_TASKS {
+        report(
+            "yield with idle waiters",
+            idle,
+            yield_with_idle_waiters(idle),
+        );
+        report(
+            "ready pollable with idle waiters",
+            idle,
+            ready_pollable_with_idle_waiters(idle),
+        );
+        report("register and drop waiters", idle, register_and_drop(idle));
+    }
+}
+
+fn report(name: &str, idle: usize, elapsed: Duration) {
+    let per_iter = elapsed.as_nanos() / ITERATIONS as u128;
+    println!("{name:<40} {idle:>12} {per_iter:>14}");
+}
+
+/// Spawn `count` tasks which each wait on a timer that won't fire for the
+/// duration of the benchmark. The tasks are cancelled when the returned
+/// handles are dropped.
+fn spawn_idle(count: usize) -> Vec<wstd::runtime::Task<()>> {
+    (0..count)
+        .map(|_| {
+            spawn(async {
+                Timer::after(Duration::from_secs(60 * 60)).wait().await;
+            })
+        })
+        .collect()
+}
+
+/// A busy task which yields to the event loop on every iteration, which
+/// causes a nonblocking check of all pending pollables each time.
+fn yield_with_idle_waiters(idle: usize) -> Duration {
+    block_on(async move {
+        let idle = spawn_idle(idle);
+        // Let every idle task register its waiter.
+        futures_lite::future::yield_now().await;
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            futures_lite::future::yield_now().await;
+        }
+        let elapsed = start.elapsed();
+        drop(idle);
+        elapsed
+    })
+}
+
+/// A busy task which waits on a pollable that is immediately ready, so each
+/// iteration goes through the reactor's readiness check.
+fn ready_pollable_with_idle_waiters(idle: usize) -> Duration {
+    block_on(async move {
+        let idle = spawn_idle(idle);
+        futures_lite::future::yield_now().await;
+        let reactor = Reactor::current();
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let pollable = reactor.schedule(wasip2::clocks::monotonic_clock::subscribe_duration(0));
+            pollable.wait_for().await;
+        }
+        let elapsed = start.elapsed();
+        drop(idle);
+        elapsed
+    })
+}
+
+/// A busy task which registers interest in a pollable that is not ready, and
+/// then drops it, as happens when a `timeout` or `race` resolves.
+fn register_and_drop(idle: usize) -> Duration {
+    block_on(async move {
+        let idle = spawn_idle(idle);
+        futures_lite::future::yield_now().await;
+        let timer = Timer::after(Duration::from_secs(60 * 60));
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let mut wait = std::pin::pin!(timer.wait());
+            let polled = futures_lite::future::poll_once(&mut wait).await;
+            black_box(polled);
+        }
+        let elapsed = start.elapsed();
+        drop(idle);
+        elapsed
+    })
+}
@@ -1,13 +1,14 @@
-use anyhow::{Context, Result};
 use futures_lite::stream::{once_future, unfold};
 use http_body_util::{BodyExt, StreamBody};
 use std::convert::Infallible;
 use wstd::http::body::{Body, Bytes, Frame};
-use wstd::http::{Error, HeaderMap, Request, Response, StatusCode};
+use wstd::http::error::Context;
+use wstd::http::sse::{Event, SseBody};
+use wstd::http::{Error, HeaderMap, Request, Response, Result, StatusCode};
 use wstd::time::{Duration, Instant};
 
 #[wstd::http_server]
-async fn main(request: Request<Body>) -> Result<Response<Body>, Error> {
+async fn main(request: Request<Body>) -> Result<Response<Body>> {
     let path = request.uri().path_and_query().unwrap().as_str();
     println!("serving {path}");
     match path {
@@ -15,6 +16,7 @@ async fn main(request: Request<Body>) -> Result<Response<Body>, Error> {
         "/wait-response" => http_wait_response(request).await,
         "/wait-body" => http_wait_body(request).await,
         "/stream-body" => http_stream_body(request).await,
+        "/events" => http_events(request).await,
         "/echo" => http_echo(request).await,
         "/echo-headers" => http_echo_headers(request).await,
         "/echo-trailers" => http_echo_trailers(request).await,
@@ -87,6 +89,22 @@ async fn http_stream_body(_request: Request<Body>) -> Result<Response<Body>> {
     Ok(Response::new(Body::from_try_stream(unfold(5, body))))
 }
 
+async fn http_events(_request: Request<Body>) -> Result<Response<Body>> {
+    // Send 3 Server-Sent Events, 100ms apart. A keep-alive comment is sent
+    // whenever no event has been sent for 1 second.
+    let events = unfold(0, |id| async move {
+        if id == 3 {
+            return None;
+        }
+        wstd::task::sleep(Duration::from_millis(100)).await;
+        let event = Event::new(format!("tick {id}")).id(id.to_string());
+        Some((event, id + 1))
+    });
+    Ok(SseBody::new(events)
+        .keep_alive(Duration::from_secs(1))
+        .into_response())
+}
+
 async fn http_echo(request: Request<Body>) -> Result<Response<Body>> {
     let (_parts, body) = request.into_parts();
     Ok(Response::new(body))
@@ -107,7 +125,7 @@ async fn http_echo_trailers(request: Request<Body>) -> Result<Response<Body>> {
     });
 
     let body = StreamBody::new(once_future(async move {
-        anyhow::Ok(Frame::<Bytes>::trailers(trailers))
+        Ok::<_, Error>(Frame::<Bytes>::trailers(trailers))
     }));
     Ok(Response::new(Body::from_http_body(body)))
 }
@@ -118,6 +136,7 @@ async fn http_response_status(request: Request<Body>) -> Result<Response<Body>>
             .to_str()
             .context("contents of x-response-status")?
             .parse::<u16>()
+            .map_err(Error::other)
             .context("u16 value from x-response-status")?
     } else {
         500
@@ -130,12 +149,12 @@ async fn http_response_status(request: Request<Body>) -> Result<Response<Body>>
 }
 
 async fn http_response_fail(_request: Request<Body>) -> Result<Response<Body>> {
-    Err(anyhow::anyhow!("error creating response"))
+    Err(Error::other("error creating response"))
 }
 
 async fn http_body_fail(_request: Request<Body>) -> Result<Response<Body>> {
     let body = StreamBody::new(once_future(async move {
-        Err::<Frame<Bytes>, _>(anyhow::anyhow!("error creating body"))
+        Err::<Frame<Bytes>, _>(Error::other("error creating body"))
     }));
 
     Ok(Response::new(Body::from_http_body(body)))
@@ -46,9 +46,24 @@ pub fn attr_macro_main(_attr: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 #[proc_macro_attribute]
-pub fn attr_macro_test(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn attr_macro_test(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemFn);
 
+    // `#[wstd::test(virtual_time)]` runs the test on the deterministic test
+    // runtime, with a virtual clock.
+    let block_on = if attr.is_empty() {
+        quote!(::wstd::runtime::block_on)
+    } else {
+        let flag = parse_macro_input!(attr as syn::Ident);
+        if flag != "virtual_time" {
+            return quote_spanned! { flag.span()=>
+                compile_error!("the only supported argument is `virtual_time`");
+            }
+            .into();
+        }
+        quote!(::wstd::runtime::test_runtime::block_on)
+    };
+
     if input.sig.asyncness.is_none() {
         return quote_spanned! { input.sig.fn_token.span()=>
             compile_error!("fn must be `async fn`");
@@ -76,7 +91,7 @@ pub fn attr_macro_test(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 #block
             }
 
-            ::wstd::runtime::block_on(async {
+            #block_on(async {
                 __run().await
             })
         }
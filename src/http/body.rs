@@ -4,7 +4,7 @@ use crate::http::{
     fields::{header_map_from_wasi, header_map_to_wasi},
 };
 use crate::io::{AsyncInputStream, AsyncOutputStream};
-use crate::runtime::{AsyncPollable, Reactor, WaitFor};
+use crate::runtime::{AsyncPollable, Reactor, WaitFor, budget};
 
 pub use ::http_body::{Body as HttpBody, Frame, SizeHint};
 pub use bytes::Bytes;
@@ -63,6 +63,9 @@ pub struct Body(BodyInner);
 enum BodyInner {
     // a boxed http_body::Body impl
     Boxed(UnsyncBoxBody<Bytes, Error>),
+    // a boxed http_body::Body impl which produces its data over time, such
+    // as Server-Sent Events, so is flushed whenever it waits for more
+    Streaming(UnsyncBoxBody<Bytes, Error>),
     // a body created from a wasi-http incoming-body (WasiIncomingBody)
     Incoming(Incoming),
     // a body in memory
@@ -76,37 +79,8 @@ impl Body {
     pub(crate) async fn send(self, outgoing_body: WasiOutgoingBody) -> Result<(), Error> {
         match self.0 {
             BodyInner::Incoming(incoming) => incoming.send(outgoing_body).await,
-            BodyInner::Boxed(box_body) => {
-                let out_stream = AsyncOutputStream::new(
-                    outgoing_body
-                        .write()
-                        .expect("outgoing body already written"),
-                );
-                let mut body = pin!(box_body);
-                let mut trailers = None;
-                loop {
-                    match poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
-                        Some(Ok(frame)) if frame.is_data() => {
-                            let data = frame.data_ref().unwrap();
-                            out_stream.write_all(data).await?;
-                        }
-                        Some(Ok(frame)) if frame.is_trailers() => {
-                            trailers =
-                                Some(header_map_to_wasi(frame.trailers_ref().unwrap()).map_err(
-                                    |e| Error::from(e).context("outoging trailers to wasi"),
-                                )?);
-                        }
-                        Some(Err(err)) => break Err(err.context("sending outgoing body")),
-                        None => {
-                            drop(out_stream);
-                            WasiOutgoingBody::finish(outgoing_body, trailers)
-                                .map_err(|e| Error::from(e).context("finishing outgoing body"))?;
-                            break Ok(());
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-            }
+            BodyInner::Boxed(box_body) => Self::send_boxed(box_body, outgoing_body, false).await,
+            BodyInner::Streaming(box_body) => Self::send_boxed(box_body, outgoing_body, true).await,
             BodyInner::Complete { data, trailers } => {
                 let out_stream = AsyncOutputStream::new(
                     outgoing_body
@@ -125,6 +99,65 @@ impl Body {
         }
     }
 
+    /// Send a boxed `http_body::Body`. If `flush_on_pending` is set, what
+    /// has been written is flushed whenever the body has to wait for more
+    /// data, so that it is delivered as it is produced.
+    async fn send_boxed(
+        box_body: UnsyncBoxBody<Bytes, Error>,
+        outgoing_body: WasiOutgoingBody,
+        flush_on_pending: bool,
+    ) -> Result<(), Error> {
+        let out_stream = AsyncOutputStream::new(
+            outgoing_body
+                .write()
+                .expect("outgoing body already written"),
+        );
+        let mut body = pin!(box_body);
+        let mut trailers = None;
+        loop {
+            let frame = if flush_on_pending {
+                match poll_fn(|cx| Poll::Ready(body.as_mut().poll_frame(cx))).await {
+                    Poll::Ready(frame) => frame,
+                    Poll::Pending => {
+                        out_stream.flush().await?;
+                        poll_fn(|cx| body.as_mut().poll_frame(cx)).await
+                    }
+                }
+            } else {
+                poll_fn(|cx| body.as_mut().poll_frame(cx)).await
+            };
+            match frame {
+                Some(Ok(frame)) if frame.is_data() => {
+                    let data = frame.data_ref().unwrap();
+                    out_stream.write_all(data).await?;
+                }
+                Some(Ok(frame)) if frame.is_trailers() => {
+                    trailers = Some(
+                        header_map_to_wasi(frame.trailers_ref().unwrap())
+                            .map_err(|e| e.context("outoging trailers to wasi"))?,
+                    );
+                }
+                Some(Err(err)) => break Err(err.context("sending outgoing body")),
+                None => {
+                    drop(out_stream);
+                    WasiOutgoingBody::finish(outgoing_body, trailers)
+                        .map_err(|e| Error::from(e).context("finishing outgoing body"))?;
+                    break Ok(());
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Mark this body as one which produces its data over time, so that
+    /// when it is sent, its data is flushed whenever it waits for more.
+    pub(crate) fn flush_when_pending(self) -> Self {
+        match self.0 {
+            BodyInner::Boxed(b) => Body(BodyInner::Streaming(b)),
+            inner => Body(inner),
+        }
+    }
+
     /// Convert this `Body` into an `UnsyncBoxBody<Bytes, Error>`, which
     /// exists to implement the `http_body::Body` trait. Consume the contents
     /// using `http_body_utils::BodyExt`, or anywhere else an impl of
@@ -139,7 +172,7 @@ impl Body {
                 .map_err(map_e)
                 .with_trailers(async move { Ok(trailers).transpose() })
                 .boxed_unsync(),
-            BodyInner::Boxed(b) => b,
+            BodyInner::Boxed(b) | BodyInner::Streaming(b) => b,
         }
     }
 
@@ -157,7 +190,7 @@ impl Body {
                 std::mem::swap(inner, &mut prev);
                 let boxed_body = match prev {
                     BodyInner::Incoming(i) => i.into_http_body().boxed_unsync(),
-                    BodyInner::Boxed(b) => b,
+                    BodyInner::Boxed(b) | BodyInner::Streaming(b) => b,
                     BodyInner::Complete { .. } => unreachable!(),
                 };
                 let collected = boxed_body.collect().await?;
@@ -184,6 +217,30 @@ impl Body {
         }
     }
 
+    /// Clone this `Body`, if its contents are in memory. A `Body` constructed
+    /// from bytes or a string, or whose contents have been collected, is in
+    /// memory, while a streaming `Body` is not.
+    ///
+    /// A `Body` which can be cloned can be sent more than once, which is
+    /// required, for example, to follow a redirect which preserves the
+    /// request body.
+    pub fn try_clone(&self) -> Option<Self> {
+        match &self.0 {
+            BodyInner::Complete { data, trailers } => Some(Body(BodyInner::Complete {
+                data: data.clone(),
+                trailers: trailers.clone(),
+            })),
+            _ => None,
+        }
+    }
+
+    /// Collect the entire contents of this `Body` into memory, so that it
+    /// can be cloned with [`Body::try_clone`], and sent more than once.
+    pub async fn buffered(mut self) -> Result<Self, Error> {
+        self.contents().await?;
+        Ok(self)
+    }
+
     /// Get a value for the length of this `Body`'s content, in bytes, if
     /// known. This value can come from either the Content-Length header
     /// received in the incoming request or response assocated with the body,
@@ -191,7 +248,7 @@ impl Body {
     /// is constructed from an `http_body::Body` impl.
     pub fn content_length(&self) -> Option<u64> {
         match &self.0 {
-            BodyInner::Boxed(b) => b.size_hint().exact(),
+            BodyInner::Boxed(b) | BodyInner::Streaming(b) => b.size_hint().exact(),
             BodyInner::Complete { data, .. } => Some(data.len() as u64),
             BodyInner::Incoming(i) => i.size_hint.content_length(),
         }
@@ -224,12 +281,32 @@ impl Body {
     /// Collect the entire contents of this `Body`, and deserialize them from
     /// json. Can fail if the body contents are not utf-8 encoded, are not
     /// valid json, or the json is not accepted by the `serde::Deserialize` impl.
+    ///
+    /// To decode a large body as it arrives, see [`Body::json_lines`] and
+    /// [`Body::json_array`].
     #[cfg(feature = "json")]
     pub async fn json<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Result<T, Error> {
         let str = self.str_contents().await?;
         serde_json::from_str(str).context("decoding body contents as json")
     }
 
+    /// Construct a `Body` by serializing a type to a url-encoded form, as
+    /// `application/x-www-form-urlencoded`. Can fail with a
+    /// `serde_qs::Error` if serialization fails.
+    #[cfg(feature = "form")]
+    pub fn from_form<T: serde::Serialize>(data: &T) -> Result<Self, serde_qs::Error> {
+        Ok(Self::from(serde_qs::to_string(data)?))
+    }
+
+    /// Collect the entire contents of this `Body`, and deserialize them from
+    /// a url-encoded form. Can fail if the body contents are not a valid
+    /// form, or the form is not accepted by the `serde::Deserialize` impl.
+    #[cfg(feature = "form")]
+    pub async fn form<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let bytes = self.contents().await?;
+        deserialize_form(bytes).context("decoding body contents as form")
+    }
+
     pub(crate) fn from_incoming(body: WasiIncomingBody, size_hint: BodyHint) -> Self {
         Body(BodyInner::Incoming(Incoming { body, size_hint }))
     }
@@ -258,7 +335,7 @@ impl Body {
     {
         use futures_lite::StreamExt;
         Self::from_http_body(http_body_util::StreamBody::new(
-            stream.map(|bs| Ok::<_, Error>(Frame::data(bs?.into()))),
+            stream.map(|bs| Ok::<_, E>(Frame::data(bs?.into()))),
         ))
     }
 
@@ -272,18 +349,28 @@ impl Body {
     where
         B: HttpBody + Send + 'static,
         <B as HttpBody>::Data: Into<Bytes>,
-        <B as HttpBody>::Error: Into<Error>,
+        <B as HttpBody>::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
     {
         use util::BodyExt;
         Body(BodyInner::Boxed(
             http_body
                 .map_frame(|f| f.map_data(Into::into))
-                .map_err(Into::into)
+                .map_err(Error::other)
                 .boxed_unsync(),
         ))
     }
 }
 
+/// Deserialize a url-encoded form or query string. Unlike the default
+/// `serde_qs` config, this accepts percent-encoded brackets in nested keys,
+/// such as `user%5Bname%5D=alice`, as browsers send them.
+#[cfg(feature = "form")]
+pub(crate) fn deserialize_form<T: serde::de::DeserializeOwned>(
+    input: &[u8],
+) -> Result<T, serde_qs::Error> {
+    serde_qs::Config::new(5, false).deserialize_bytes(input)
+}
+
 impl From<()> for Body {
     fn from(_: ()) -> Body {
         Body::empty()
@@ -361,10 +448,9 @@ impl Incoming {
         drop(in_stream);
         drop(out_stream);
         let future_in_trailers = WasiIncomingBody::finish(in_body);
-        Reactor::current()
-            .schedule(future_in_trailers.subscribe())
-            .wait_for()
-            .await;
+        // Don't hold the `Reactor` across the await, so this future stays `Send`.
+        let trailers_ready = Reactor::current().schedule(future_in_trailers.subscribe());
+        trailers_ready.wait_for().await;
         let in_trailers: Option<wasip2::http::types::Fields> = future_in_trailers
             .get()
             .expect("pollable ready")
@@ -521,6 +607,7 @@ impl BodyState {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        std::task::ready!(budget::poll_proceed(cx));
         loop {
             match self.stream.read(MAX_FRAME_SIZE) {
                 Ok(bs) if !bs.is_empty() => {
@@ -529,7 +616,7 @@ impl BodyState {
                 Err(StreamError::Closed) => return Poll::Ready(None),
                 Err(StreamError::LastOperationFailed(err)) => {
                     return Poll::Ready(Some(Err(
-                        Error::msg(err.to_debug_string()).context("reading incoming body stream")
+                        Error::other(err.to_debug_string()).context("reading incoming body stream")
                     )));
                 }
                 Ok(_empty) => {
@@ -620,3 +707,35 @@ impl TrailersState {
         }
     }
 }
+
+/// A body which yields `data` in chunks of `size` bytes, for testing that
+/// a decoder handles its input split at any point.
+#[cfg(test)]
+pub(crate) fn chunked(data: impl AsRef<[u8]>, size: usize) -> Body {
+    let chunks: Vec<Result<Bytes, std::io::Error>> = data
+        .as_ref()
+        .chunks(size)
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    Body::from_try_stream(futures_lite::stream::iter(chunks))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flush_when_pending() {
+        let stream = || futures_lite::stream::iter(["data"]);
+        assert!(matches!(Body::from_stream(stream()).0, BodyInner::Boxed(_)));
+        assert!(matches!(
+            Body::from_stream(stream()).flush_when_pending().0,
+            BodyInner::Streaming(_)
+        ));
+        // A body in memory is written all at once, so is left as it is.
+        assert!(matches!(
+            Body::from("data").flush_when_pending().0,
+            BodyInner::Complete { .. }
+        ));
+    }
+}
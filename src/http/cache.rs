@@ -0,0 +1,1291 @@
+//! A response cache for the HTTP [`Client`].
+//!
+//! A [`Client`] with a [`Cache`], set with [`Client::set_cache`], stores the
+//! responses to its `GET` requests, and answers later requests from the
+//! store when it can, as a private cache per RFC 9111:
+//!
+//! * A response is stored unless the request or the response has
+//!   `Cache-Control: no-store`, its `Vary` header is `*`, or its body is
+//!   larger than the cache's [maximum entry size](Cache::max_entry_size).
+//!   The body is stored as it is read, so a response is only stored once
+//!   its body has been read to the end.
+//!   Its `Set-Cookie` headers aren't stored, so a client's cookie jar
+//!   only sees the cookies the server sets in each response it sends.
+//! * A stored response is fresh for the `max-age` of its `Cache-Control`
+//!   header, or else until its `Expires` header, or else, if it has a
+//!   `Last-Modified` header, for a tenth of the time since then. A fresh
+//!   response is used without contacting the server, unless the request
+//!   has `Cache-Control: no-cache` or the response has `Cache-Control:
+//!   no-cache`. The request's `max-age`, `min-fresh`, `max-stale` and
+//!   `only-if-cached` directives are also respected.
+//! * A stored response which can't be used as is, but has an `ETag` or
+//!   `Last-Modified` header, is revalidated with a conditional request
+//!   carrying `If-None-Match` or `If-Modified-Since`. A `304 Not Modified`
+//!   response updates the stored response's headers, and it is then used.
+//! * A stored response is only used for a request whose headers named by
+//!   the response's `Vary` header match those of the request it was stored
+//!   for. One response is stored per uri.
+//! * If the server can't be reached, a stale response is used rather than
+//!   failing, unless it has `Cache-Control: must-revalidate`.
+//! * A successful `POST`, `PUT`, `PATCH` or `DELETE` request removes the
+//!   stored response for its uri.
+//!
+//! Requests with a `Range` header, or their own conditional headers, are
+//! sent to the server without consulting the cache. Every response to a
+//! `GET` request carries a [`CacheStatus`] in its extensions.
+//!
+//! Responses are kept in a [`Storage`]: in memory with [`MemoryStorage`],
+//! or with [`DirStorage`], in a `wasi:filesystem` directory, to keep them
+//! between component invocations.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::http::cache::{Cache, CacheStatus};
+//! use wstd::http::{Client, Error};
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let mut client = Client::new();
+//! client.set_cache(Cache::in_memory(100));
+//!
+//! let mut response = client.get("https://example.com/.well-known/jwks.json").send().await?;
+//! let jwks = response.body_mut().str_contents().await?;
+//! if response.extensions().get::<CacheStatus>() == Some(&CacheStatus::Hit) {
+//!     println!("used the cached keys");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`Client::set_cache`]: super::Client::set_cache
+
+use super::date::parse_http_date;
+use super::{Body, Client, Error, HeaderMap, HeaderName, HeaderValue, Method, Request, Response};
+use super::{StatusCode, Uri};
+use bytes::{Bytes, BytesMut};
+use http::header::{
+    AGE, CACHE_CONTROL, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    DATE, ETAG, EXPIRES, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE,
+    LAST_MODIFIED, PRAGMA, RANGE, SET_COOKIE, TRANSFER_ENCODING, VARY,
+};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::UnsyncBoxBody;
+use pin_project_lite::pin_project;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+/// A response cache, shared between its clones.
+#[derive(Clone)]
+pub struct Cache {
+    storage: Arc<dyn Storage>,
+    max_entry_size: usize,
+}
+
+/// Where a response given by a [`Client`] with a [`Cache`]
+/// came from. Responses to `GET` requests carry it in their extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// A fresh stored response was used, without contacting the server.
+    Hit,
+    /// A stale stored response was used, either because the request allowed
+    /// it with `max-stale`, or because the server couldn't be reached.
+    Stale,
+    /// A stored response was used after the server confirmed, with `304 Not
+    /// Modified`, that it is still valid.
+    Revalidated,
+    /// The response came from the server.
+    Miss,
+}
+
+impl Cache {
+    /// A cache which keeps its responses in `storage`.
+    pub fn new(storage: impl Storage + 'static) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            max_entry_size: 1024 * 1024,
+        }
+    }
+
+    /// A cache which keeps up to `capacity` responses in memory.
+    pub fn in_memory(capacity: usize) -> Self {
+        Self::new(MemoryStorage::new(capacity))
+    }
+
+    /// Set the size of the largest response body which is stored. The
+    /// default is 1 MiB.
+    pub fn max_entry_size(mut self, size: usize) -> Self {
+        self.max_entry_size = size;
+        self
+    }
+
+    /// Remove the stored response for `uri`, if any.
+    pub fn remove(&self, uri: &Uri) {
+        self.storage.remove(&key(uri));
+    }
+
+    /// Send `req` with `client`, using and storing responses in the cache.
+    pub(crate) async fn send(
+        &self,
+        client: &Client,
+        mut req: Request<Body>,
+    ) -> Result<Response<Body>, Error> {
+        let method = req.method().clone();
+        if method != Method::GET {
+            let uri = req.uri().clone();
+            let response = client.send_wasi(req).await?;
+            if invalidates(&method, response.status()) {
+                self.remove(&uri);
+            }
+            return Ok(response);
+        }
+        let directives = CacheControl::from_request(req.headers());
+        if directives.no_store || bypasses_cache(req.headers()) {
+            return Ok(with_status(client.send_wasi(req).await?, CacheStatus::Miss));
+        }
+
+        let key = key(req.uri());
+        let now = now();
+        let entry = self
+            .storage
+            .get(&key)
+            .filter(|entry| entry.matches(req.headers()));
+        if let Some(entry) = &entry {
+            match entry.usable(&directives, now) {
+                Usable::Fresh => return Ok(entry.to_response(now, CacheStatus::Hit)),
+                Usable::Stale => return Ok(entry.to_response(now, CacheStatus::Stale)),
+                Usable::Revalidate => {}
+            }
+        }
+        if directives.only_if_cached {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+            return Ok(with_status(response, CacheStatus::Miss));
+        }
+
+        if let Some(entry) = &entry {
+            entry.add_conditional_headers(req.headers_mut());
+        }
+        let request_headers = req.headers().clone();
+        let request_time = now;
+        let response = match client.send_wasi(req).await {
+            Ok(response) => response,
+            Err(e) => {
+                // A stale response may be used when the server can't be
+                // reached.
+                return match entry {
+                    Some(entry) if e.error_code().is_some() && !entry.must_revalidate() => {
+                        Ok(entry.to_response(now, CacheStatus::Stale))
+                    }
+                    _ => Err(e),
+                };
+            }
+        };
+        let response_time = self::now();
+
+        if response.status() == StatusCode::NOT_MODIFIED
+            && let Some(mut entry) = entry
+        {
+            entry.update(response.headers(), request_time, response_time);
+            let mut revalidated = entry.to_response(response_time, CacheStatus::Revalidated);
+            // Cookies aren't stored, but those set by the server now are
+            // passed on.
+            for value in response.headers().get_all(SET_COOKIE) {
+                revalidated.headers_mut().append(SET_COOKIE, value.clone());
+            }
+            if entry.is_storable() {
+                self.storage.put(&key, entry);
+            }
+            return Ok(revalidated);
+        }
+        if !directives.is_storable_with(&response) {
+            return Ok(with_status(response, CacheStatus::Miss));
+        }
+        let (parts, body) = response.into_parts();
+        if body
+            .content_length()
+            .is_some_and(|len| len > self.max_entry_size as u64)
+        {
+            let response = Response::from_parts(parts, body);
+            return Ok(with_status(response, CacheStatus::Miss));
+        }
+        let entry = Entry::new(&parts, &request_headers, request_time, response_time);
+        let body = Body::from_http_body(StoringBody {
+            inner: body.into_boxed_body(),
+            buffer: BytesMut::new(),
+            store: Some(Store {
+                storage: self.storage.clone(),
+                key,
+                entry,
+                max_size: self.max_entry_size,
+            }),
+        });
+        Ok(with_status(
+            Response::from_parts(parts, body),
+            CacheStatus::Miss,
+        ))
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("max_entry_size", &self.max_entry_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Storage for the responses of a [`Cache`], by key.
+///
+/// A storage is a best effort: it may fail to keep, or drop, any response,
+/// so its methods don't return errors.
+pub trait Storage: Send + Sync {
+    /// The response stored for `key`, if any.
+    fn get(&self, key: &str) -> Option<Entry>;
+
+    /// Store `entry` for `key`, replacing any response already stored.
+    fn put(&self, key: &str, entry: Entry);
+
+    /// Remove the response stored for `key`, if any.
+    fn remove(&self, key: &str);
+}
+
+/// A response stored in a [`Cache`].
+///
+/// An entry can be converted to and from bytes, with [`Entry::to_bytes`]
+/// and [`Entry::from_bytes`], for a [`Storage`] which keeps it outside
+/// memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// The request headers named by the response's `Vary` header.
+    vary: HeaderMap,
+    request_time: SystemTime,
+    response_time: SystemTime,
+}
+
+/// Whether a stored response can be used for a request.
+#[derive(Debug, PartialEq, Eq)]
+enum Usable {
+    Fresh,
+    Stale,
+    Revalidate,
+}
+
+impl Entry {
+    /// An entry, with an empty body, for the response with `parts` to a
+    /// request with `request_headers`. `Set-Cookie` headers are left out, as
+    /// they are meant for the client which received the response, once,
+    /// and not for every use of the stored response.
+    fn new(
+        parts: &http::response::Parts,
+        request_headers: &HeaderMap,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> Self {
+        let mut headers = parts.headers.clone();
+        headers.remove(SET_COOKIE);
+        Self {
+            status: parts.status,
+            vary: vary_headers(&parts.headers, request_headers),
+            headers,
+            body: Bytes::new(),
+            request_time,
+            response_time,
+        }
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The body of the response.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Encode the entry as bytes, which [`Entry::from_bytes`] decodes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.body.len() + 512);
+        out.extend_from_slice(
+            format!(
+                "wstd-cache/1 {} {} {}\n",
+                self.status.as_u16(),
+                unix_secs(self.request_time),
+                unix_secs(self.response_time),
+            )
+            .as_bytes(),
+        );
+        for headers in [&self.headers, &self.vary] {
+            for (name, value) in headers {
+                out.extend_from_slice(name.as_str().as_bytes());
+                out.extend_from_slice(b": ");
+                out.extend_from_slice(value.as_bytes());
+                out.push(b'\n');
+            }
+            out.push(b'\n');
+        }
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    /// Decode an entry encoded by [`Entry::to_bytes`], or `None` if `bytes`
+    /// isn't one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut rest = bytes;
+        let mut line = || {
+            let end = rest.iter().position(|&b| b == b'\n')?;
+            let line = &rest[..end];
+            rest = &rest[end + 1..];
+            Some(line)
+        };
+        let first = std::str::from_utf8(line()?).ok()?;
+        let mut fields = first.strip_prefix("wstd-cache/1 ")?.split(' ');
+        let status = StatusCode::from_u16(fields.next()?.parse().ok()?).ok()?;
+        let mut time = || {
+            SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(fields.next()?.parse().ok()?))
+        };
+        let request_time = time()?;
+        let response_time = time()?;
+        let mut sections = [HeaderMap::new(), HeaderMap::new()];
+        for headers in &mut sections {
+            loop {
+                let line = line()?;
+                if line.is_empty() {
+                    break;
+                }
+                let colon = line.iter().position(|&b| b == b':')?;
+                let name = HeaderName::from_bytes(&line[..colon]).ok()?;
+                let value = line[colon + 1..].strip_prefix(b" ")?;
+                headers.append(name, HeaderValue::from_bytes(value).ok()?);
+            }
+        }
+        let [headers, vary] = sections;
+        Some(Self {
+            status,
+            headers,
+            body: Bytes::copy_from_slice(rest),
+            vary,
+            request_time,
+            response_time,
+        })
+    }
+
+    fn cache_control(&self) -> CacheControl {
+        CacheControl::parse(&self.headers)
+    }
+
+    fn must_revalidate(&self) -> bool {
+        let cc = self.cache_control();
+        cc.must_revalidate || cc.no_cache
+    }
+
+    fn is_storable(&self) -> bool {
+        let cc = self.cache_control();
+        !cc.no_store && !varies_on_everything(&self.headers)
+    }
+
+    /// Whether the entry was stored for a request with the same headers
+    /// named by its `Vary` header as `request`.
+    fn matches(&self, request: &HeaderMap) -> bool {
+        vary_headers(&self.headers, request) == self.vary
+    }
+
+    /// How long the response is fresh for, per RFC 9111 section 4.2.1.
+    fn freshness_lifetime(&self) -> Duration {
+        if let Some(max_age) = self.cache_control().max_age {
+            return max_age;
+        }
+        let date = self.date();
+        if let Some(expires) = self.headers.get(EXPIRES) {
+            // An invalid date, such as `0`, is in the past.
+            return expires
+                .to_str()
+                .ok()
+                .and_then(parse_http_date)
+                .and_then(|expires| expires.duration_since(date).ok())
+                .unwrap_or_default();
+        }
+        let last_modified = header_date(&self.headers, LAST_MODIFIED);
+        match last_modified {
+            Some(last_modified) if is_heuristically_cacheable(self.status) => date
+                .duration_since(last_modified)
+                .map(|d| d / 10)
+                .unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// The age of the response at `now`, per RFC 9111 section 4.2.3.
+    fn age(&self, now: SystemTime) -> Duration {
+        let age_value = self
+            .headers
+            .get(AGE)
+            .and_then(|age| delta_seconds(age.to_str().ok()?))
+            .unwrap_or_default();
+        let apparent_age = since(self.response_time, self.date());
+        let response_delay = since(self.response_time, self.request_time);
+        let corrected_initial_age = apparent_age.max(age_value.saturating_add(response_delay));
+        corrected_initial_age.saturating_add(since(now, self.response_time))
+    }
+
+    fn date(&self) -> SystemTime {
+        header_date(&self.headers, DATE).unwrap_or(self.response_time)
+    }
+
+    /// Whether the entry can be used for a request with `request`
+    /// directives at `now`, per RFC 9111 section 4.
+    fn usable(&self, request: &CacheControl, now: SystemTime) -> Usable {
+        let response = self.cache_control();
+        if request.no_cache || response.no_cache {
+            return Usable::Revalidate;
+        }
+        let lifetime = self.freshness_lifetime();
+        let age = self.age(now);
+        if request.max_age.is_some_and(|max_age| age > max_age) {
+            return Usable::Revalidate;
+        }
+        if lifetime > age.saturating_add(request.min_fresh.unwrap_or_default()) {
+            return Usable::Fresh;
+        }
+        let stale_allowed = match request.max_stale {
+            None => false,
+            Some(None) => true,
+            Some(Some(max_stale)) => age - lifetime.min(age) <= max_stale,
+        };
+        if stale_allowed && !response.must_revalidate {
+            Usable::Stale
+        } else {
+            Usable::Revalidate
+        }
+    }
+
+    fn add_conditional_headers(&self, request: &mut HeaderMap) {
+        if let Some(etag) = self.headers.get(ETAG) {
+            request.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(LAST_MODIFIED) {
+            request.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    /// Update the entry with the headers of a `304 Not Modified` response,
+    /// per RFC 9111 section 3.2. As in [`Entry::new`], `Set-Cookie` headers
+    /// are left out.
+    fn update(&mut self, headers: &HeaderMap, request_time: SystemTime, response_time: SystemTime) {
+        for name in headers.keys() {
+            if [
+                CONNECTION,
+                CONTENT_ENCODING,
+                CONTENT_LENGTH,
+                CONTENT_RANGE,
+                CONTENT_TYPE,
+                SET_COOKIE,
+                TRANSFER_ENCODING,
+            ]
+            .contains(name)
+            {
+                continue;
+            }
+            self.headers.remove(name);
+            for value in headers.get_all(name) {
+                self.headers.append(name.clone(), value.clone());
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+
+    fn to_response(&self, now: SystemTime, status: CacheStatus) -> Response<Body> {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(self.age(now).as_secs()));
+        with_status(response, status)
+    }
+}
+
+/// The cache directives of a `Cache-Control` header.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<Duration>,
+    must_revalidate: bool,
+    /// `max-stale`, with or without a value.
+    max_stale: Option<Option<Duration>>,
+    min_fresh: Option<Duration>,
+    only_if_cached: bool,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let secs = || delta_seconds(value?);
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                // An invalid max-age makes the response stale.
+                "max-age" => cc.max_age = Some(secs().unwrap_or_default()),
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "max-stale" => cc.max_stale = Some(secs()),
+                "min-fresh" => cc.min_fresh = secs(),
+                "only-if-cached" => cc.only_if_cached = true,
+                _ => {}
+            }
+        }
+        cc
+    }
+
+    /// The directives of a request, including `Pragma: no-cache` in the
+    /// absence of `Cache-Control`.
+    fn from_request(headers: &HeaderMap) -> Self {
+        let mut cc = Self::parse(headers);
+        if !headers.contains_key(CACHE_CONTROL)
+            && headers
+                .get_all(PRAGMA)
+                .iter()
+                .any(|value| value.as_bytes().eq_ignore_ascii_case(b"no-cache"))
+        {
+            cc.no_cache = true;
+        }
+        cc
+    }
+
+    /// Whether `response`, to a request with these directives, may be
+    /// stored, per RFC 9111 section 3. Responses which could never be
+    /// used, being neither fresh nor revalidatable, aren't stored either.
+    fn is_storable_with(&self, response: &Response<Body>) -> bool {
+        let headers = response.headers();
+        let cc = CacheControl::parse(headers);
+        if self.no_store || cc.no_store || varies_on_everything(headers) {
+            return false;
+        }
+        let status = response.status();
+        let explicit = cc.max_age.is_some() || headers.contains_key(EXPIRES);
+        if !explicit && !is_heuristically_cacheable(status) {
+            return false;
+        }
+        let has_validator = headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED);
+        let fresh = !cc.no_cache
+            && (cc.max_age.is_some_and(|max_age| !max_age.is_zero())
+                || headers.contains_key(EXPIRES)
+                || headers.contains_key(LAST_MODIFIED));
+        fresh || has_validator
+    }
+}
+
+/// Whether a response with `status` may be stored and given a heuristic
+/// freshness lifetime, per RFC 9110 section 15.1. `206 Partial Content` is
+/// left out, as partial responses aren't combined.
+fn is_heuristically_cacheable(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+/// Whether a response to `method` with `status` invalidates the stored
+/// response for its uri, per RFC 9111 section 4.4.
+fn invalidates(method: &Method, status: StatusCode) -> bool {
+    let unsafe_method = !matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    unsafe_method && (status.is_success() || status.is_redirection())
+}
+
+/// Whether a request must be sent without consulting the cache, because
+/// it asks for part of a resource, or makes its own conditions.
+fn bypasses_cache(headers: &HeaderMap) -> bool {
+    [
+        RANGE,
+        IF_MATCH,
+        IF_NONE_MATCH,
+        IF_MODIFIED_SINCE,
+        IF_UNMODIFIED_SINCE,
+        IF_RANGE,
+    ]
+    .iter()
+    .any(|name| headers.contains_key(name))
+}
+
+fn varies_on_everything(headers: &HeaderMap) -> bool {
+    vary_names(headers).any(|name| name == "*")
+}
+
+fn vary_names(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// The headers of `request` named by the `Vary` header of `response`, with
+/// the values of each joined.
+fn vary_headers(response: &HeaderMap, request: &HeaderMap) -> HeaderMap {
+    let mut vary = HeaderMap::new();
+    for name in vary_names(response) {
+        let Ok(name) = HeaderName::try_from(name) else {
+            continue;
+        };
+        let values: Vec<&[u8]> = request
+            .get_all(&name)
+            .iter()
+            .map(|v| v.as_bytes())
+            .collect();
+        if !values.is_empty()
+            && let Ok(value) = HeaderValue::from_bytes(&values.join(&b", "[..]))
+        {
+            vary.insert(name, value);
+        }
+    }
+    vary
+}
+
+fn with_status(mut response: Response<Body>, status: CacheStatus) -> Response<Body> {
+    response.extensions_mut().insert(status);
+    response
+}
+
+fn key(uri: &Uri) -> String {
+    uri.to_string()
+}
+
+fn header_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    parse_http_date(headers.get(name)?.to_str().ok()?)
+}
+
+/// The time from `earlier` to `later`, or zero if `later` is earlier.
+/// Parse delta-seconds, clamped to 2^31 seconds as RFC 9111 section 1.2.2
+/// requires, so that adding them to other durations can't overflow.
+fn delta_seconds(value: &str) -> Option<Duration> {
+    const MAX: u64 = 1 << 31;
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Digits which don't fit in a u64 are beyond the maximum too.
+    let secs = value.parse::<u64>().map_or(MAX, |secs| secs.min(MAX));
+    Some(Duration::from_secs(secs))
+}
+
+fn since(later: SystemTime, earlier: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_default()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    since(time, SystemTime::UNIX_EPOCH).as_secs()
+}
+
+fn now() -> SystemTime {
+    crate::time::SystemTime::now().into()
+}
+
+/// The pending store of a response, once its body has been read.
+struct Store {
+    storage: Arc<dyn Storage>,
+    key: String,
+    entry: Entry,
+    max_size: usize,
+}
+
+pin_project! {
+    /// A response body which stores the response in the cache once it has
+    /// been read to the end.
+    struct StoringBody {
+        #[pin]
+        inner: UnsyncBoxBody<Bytes, Error>,
+        buffer: BytesMut,
+        store: Option<Store>,
+    }
+}
+
+impl HttpBody for StoringBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = self.project();
+        let frame = std::task::ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(store)) = (frame.data_ref(), &*this.store) {
+                    if this.buffer.len() + data.len() > store.max_size {
+                        *this.store = None;
+                        *this.buffer = BytesMut::new();
+                    } else {
+                        this.buffer.extend_from_slice(data);
+                    }
+                }
+            }
+            Some(Err(_)) => *this.store = None,
+            None => {
+                if let Some(mut store) = this.store.take() {
+                    store.entry.body = std::mem::take(this.buffer).freeze();
+                    store.storage.put(&store.key, store.entry);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // The end must be polled for, to store the response.
+        self.store.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A [`Storage`] which keeps responses in memory, dropping the least
+/// recently used when it is full.
+pub struct MemoryStorage {
+    inner: Mutex<Lru>,
+}
+
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (u64, Entry)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl MemoryStorage {
+    /// A storage for up to `capacity` responses.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Lru {
+                capacity,
+                tick: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// The number of stored responses.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Whether no responses are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str) -> u64 {
+        self.tick += 1;
+        if let Some((tick, _)) = self.entries.get_mut(key) {
+            self.order.remove(tick);
+            *tick = self.tick;
+        }
+        self.order.insert(self.tick, key.to_owned());
+        self.tick
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let mut lru = self.inner.lock().unwrap();
+        lru.entries.contains_key(key).then(|| lru.touch(key))?;
+        lru.entries.get(key).map(|(_, entry)| entry.clone())
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        let mut lru = self.inner.lock().unwrap();
+        if lru.capacity == 0 {
+            return;
+        }
+        let tick = lru.touch(key);
+        lru.entries.insert(key.to_owned(), (tick, entry));
+        while lru.entries.len() > lru.capacity {
+            let (_, oldest) = lru.order.pop_first().unwrap();
+            lru.entries.remove(&oldest);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        let mut lru = self.inner.lock().unwrap();
+        if let Some((tick, _)) = lru.entries.remove(key) {
+            lru.order.remove(&tick);
+        }
+    }
+}
+
+impl fmt::Debug for MemoryStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lru = self.inner.lock().unwrap();
+        f.debug_struct("MemoryStorage")
+            .field("capacity", &lru.capacity)
+            .field("len", &lru.entries.len())
+            .finish()
+    }
+}
+
+/// A [`Storage`] which keeps responses as files in a `wasi:filesystem`
+/// directory, one per uri, so that they outlive the component instance.
+///
+/// The `wasi:filesystem` interface isn't part of the `wasi:http/proxy`
+/// world, so a component using this storage must run in a host which
+/// provides it, with the directory preopened.
+#[derive(Debug)]
+pub struct DirStorage {
+    dir: wasip2::filesystem::types::Descriptor,
+}
+
+impl DirStorage {
+    /// A storage in the directory `dir`.
+    pub fn new(dir: wasip2::filesystem::types::Descriptor) -> Self {
+        Self { dir }
+    }
+
+    /// A storage in the directory at `path`, which is created if it doesn't
+    /// exist. `path` must be a preopened directory, or within one.
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        use wasip2::filesystem::types::{DescriptorFlags, OpenFlags, PathFlags};
+        let path = path.trim_end_matches('/');
+        let (preopen, rest) = wasip2::filesystem::preopens::get_directories()
+            .into_iter()
+            .filter_map(|(dir, name)| {
+                let name = name.trim_end_matches('/');
+                let rest = if path == name {
+                    ""
+                } else if name == "." || name.is_empty() {
+                    path.strip_prefix("./").unwrap_or(path)
+                } else {
+                    path.strip_prefix(name)?.strip_prefix('/')?
+                };
+                Some((dir, rest.to_owned()))
+            })
+            .min_by_key(|(_, rest)| rest.len())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no preopened directory contains {path}"),
+                )
+            })?;
+        if rest.is_empty() {
+            return Ok(Self::new(preopen));
+        }
+        let mut prefix = String::new();
+        for component in rest.split('/').filter(|c| !c.is_empty()) {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(component);
+            // The directory may already exist, which open_at finds out.
+            let _ = preopen.create_directory_at(&prefix);
+        }
+        let dir = preopen
+            .open_at(
+                PathFlags::empty(),
+                &rest,
+                OpenFlags::DIRECTORY,
+                DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY,
+            )
+            .map_err(|e| std::io::Error::other(format!("opening {path}: {e}")))?;
+        Ok(Self::new(dir))
+    }
+
+    /// The file name for `key`. Keys are uris, which may be too long, or
+    /// contain characters which aren't allowed, so the name is a hash of
+    /// the key, and the key is also stored in the file to tell collisions
+    /// apart.
+    fn file_name(key: &str) -> String {
+        // 64 bit FNV-1a.
+        let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+        });
+        format!("{hash:016x}.entry")
+    }
+
+    fn read(&self, name: &str) -> Option<Vec<u8>> {
+        use wasip2::filesystem::types::{DescriptorFlags, OpenFlags, PathFlags};
+        let file = self
+            .dir
+            .open_at(
+                PathFlags::empty(),
+                name,
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+            )
+            .ok()?;
+        let mut contents = Vec::new();
+        loop {
+            let (chunk, end) = file.read(64 * 1024, contents.len() as u64).ok()?;
+            contents.extend_from_slice(&chunk);
+            if end || chunk.is_empty() {
+                return Some(contents);
+            }
+        }
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> Option<()> {
+        use wasip2::filesystem::types::{DescriptorFlags, OpenFlags, PathFlags};
+        // Write to a temporary file and rename it, so that a reader never
+        // sees a partial entry.
+        let temp = format!("{name}.tmp");
+        let file = self
+            .dir
+            .open_at(
+                PathFlags::empty(),
+                &temp,
+                OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                DescriptorFlags::WRITE,
+            )
+            .ok()?;
+        let mut written = 0;
+        while written < contents.len() {
+            let n = file.write(&contents[written..], written as u64).ok()?;
+            written += n as usize;
+        }
+        drop(file);
+        self.dir.rename_at(&temp, &self.dir, name).ok()
+    }
+}
+
+impl Storage for DirStorage {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let contents = self.read(&Self::file_name(key))?;
+        let end = contents.iter().position(|&b| b == b'\n')?;
+        if &contents[..end] != key.as_bytes() {
+            return None;
+        }
+        Entry::from_bytes(&contents[end + 1..])
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        let mut contents = Vec::with_capacity(key.len() + 1);
+        contents.extend_from_slice(key.as_bytes());
+        contents.push(b'\n');
+        contents.extend_from_slice(&entry.to_bytes());
+        let _ = self.write(&Self::file_name(key), &contents);
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = self.dir.unlink_file_at(&Self::file_name(key));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// 2024-01-01T00:00:00Z, and the same as an HTTP-date.
+    const T0: u64 = 1_704_067_200;
+    const T0_DATE: &str = "Mon, 01 Jan 2024 00:00:00 GMT";
+
+    fn entry(headers: &[(&str, &str)]) -> Entry {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        Entry {
+            status: StatusCode::OK,
+            headers: map,
+            body: Bytes::from_static(b"{\"keys\":[]}"),
+            vary: HeaderMap::new(),
+            request_time: at(T0),
+            response_time: at(T0 + 1),
+        }
+    }
+
+    fn request(headers: &[(&str, &str)]) -> CacheControl {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        CacheControl::from_request(&map)
+    }
+
+    #[test]
+    fn parse_cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, MAX-AGE=\"60\""),
+        );
+        headers.append(
+            CACHE_CONTROL,
+            HeaderValue::from_static("must-revalidate, max-stale"),
+        );
+        let cc = CacheControl::parse(&headers);
+        assert_eq!(
+            cc,
+            CacheControl {
+                max_age: Some(Duration::from_secs(60)),
+                must_revalidate: true,
+                max_stale: Some(None),
+                ..Default::default()
+            }
+        );
+        assert!(request(&[("pragma", "no-cache")]).no_cache);
+        assert!(!request(&[("pragma", "no-cache"), ("cache-control", "max-age=5")]).no_cache);
+    }
+
+    #[test]
+    fn freshness_lifetime() {
+        let e = entry(&[("cache-control", "max-age=300"), ("expires", "0")]);
+        assert_eq!(e.freshness_lifetime(), Duration::from_secs(300));
+        let e = entry(&[
+            ("date", T0_DATE),
+            ("expires", "Mon, 01 Jan 2024 01:00:00 GMT"),
+        ]);
+        assert_eq!(e.freshness_lifetime(), Duration::from_secs(3600));
+        let e = entry(&[("date", T0_DATE), ("expires", "0")]);
+        assert_eq!(e.freshness_lifetime(), Duration::ZERO);
+        // A tenth of the time since it was last modified.
+        let e = entry(&[
+            ("date", T0_DATE),
+            ("last-modified", "Sun, 31 Dec 2023 00:00:00 GMT"),
+        ]);
+        assert_eq!(e.freshness_lifetime(), Duration::from_secs(8640));
+        assert_eq!(entry(&[]).freshness_lifetime(), Duration::ZERO);
+    }
+
+    #[test]
+    fn age() {
+        // Requested at T0, received at T0 + 1, the server says it is 10s
+        // old, and it has been in the cache for 5s.
+        let e = entry(&[("date", T0_DATE), ("age", "10")]);
+        assert_eq!(e.age(at(T0 + 6)), Duration::from_secs(16));
+        // A date in the past adds to the age.
+        let e = entry(&[("date", "Sun, 31 Dec 2023 23:59:00 GMT")]);
+        assert_eq!(e.age(at(T0 + 1)), Duration::from_secs(61));
+    }
+
+    #[test]
+    fn age_is_clamped() {
+        let max = Duration::from_secs(1 << 31);
+        for age in ["18446744073709551615", "99999999999999999999999"] {
+            let e = entry(&[("cache-control", "max-age=60"), ("age", age)]);
+            assert_eq!(e.age(at(T0 + 6)), max + Duration::from_secs(6), "{age}");
+            let min_fresh = request(&[("cache-control", "min-fresh=18446744073709551615")]);
+            assert_eq!(e.usable(&min_fresh, at(T0 + 6)), Usable::Revalidate);
+        }
+        let cc = request(&[(
+            "cache-control",
+            "max-age=99999999999999999999, min-fresh=-1",
+        )]);
+        assert_eq!(cc.max_age, Some(max));
+        assert_eq!(cc.min_fresh, None);
+    }
+
+    #[test]
+    fn usable() {
+        let e = entry(&[("date", T0_DATE), ("cache-control", "max-age=60")]);
+        assert_eq!(e.usable(&request(&[]), at(T0 + 30)), Usable::Fresh);
+        assert_eq!(e.usable(&request(&[]), at(T0 + 61)), Usable::Revalidate);
+        let no_cache = request(&[("cache-control", "no-cache")]);
+        assert_eq!(e.usable(&no_cache, at(T0 + 30)), Usable::Revalidate);
+        let max_age = request(&[("cache-control", "max-age=10")]);
+        assert_eq!(e.usable(&max_age, at(T0 + 30)), Usable::Revalidate);
+        let min_fresh = request(&[("cache-control", "min-fresh=40")]);
+        assert_eq!(e.usable(&min_fresh, at(T0 + 30)), Usable::Revalidate);
+        let max_stale = request(&[("cache-control", "max-stale=30")]);
+        assert_eq!(e.usable(&max_stale, at(T0 + 80)), Usable::Stale);
+        assert_eq!(e.usable(&max_stale, at(T0 + 100)), Usable::Revalidate);
+
+        let e = entry(&[("cache-control", "max-age=60, must-revalidate")]);
+        assert_eq!(e.usable(&max_stale, at(T0 + 80)), Usable::Revalidate);
+        let e = entry(&[("cache-control", "max-age=60, no-cache")]);
+        assert_eq!(e.usable(&request(&[]), at(T0 + 2)), Usable::Revalidate);
+    }
+
+    #[test]
+    fn storable() {
+        let storable = |status: u16, headers: &[(&str, &str)]| {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::from_u16(status).unwrap();
+            *response.headers_mut() = entry(headers).headers;
+            CacheControl::default().is_storable_with(&response)
+        };
+        assert!(storable(200, &[("cache-control", "max-age=60")]));
+        assert!(storable(200, &[("etag", "\"v1\"")]));
+        assert!(storable(
+            200,
+            &[("cache-control", "no-cache"), ("etag", "\"v1\"")]
+        ));
+        assert!(storable(404, &[("last-modified", T0_DATE)]));
+        assert!(storable(500, &[("cache-control", "max-age=60")]));
+        assert!(!storable(500, &[("etag", "\"v1\"")]));
+        assert!(!storable(200, &[]));
+        assert!(!storable(200, &[("cache-control", "no-store, max-age=60")]));
+        assert!(!storable(
+            200,
+            &[("cache-control", "max-age=60"), ("vary", "*")]
+        ));
+        assert!(!storable(200, &[("cache-control", "no-cache")]));
+        assert!(!storable(200, &[("cache-control", "max-age=0")]));
+    }
+
+    #[test]
+    fn vary() {
+        let mut e = entry(&[("vary", "Accept, accept-language")]);
+        let mut req = HeaderMap::new();
+        req.insert("accept", HeaderValue::from_static("application/json"));
+        e.vary = vary_headers(&e.headers, &req);
+        assert!(e.matches(&req));
+        req.insert("accept-language", HeaderValue::from_static("en"));
+        assert!(!e.matches(&req));
+        req.remove("accept-language");
+        req.insert("accept", HeaderValue::from_static("text/html"));
+        assert!(!e.matches(&req));
+        // Headers which aren't named don't matter.
+        req.insert("accept", HeaderValue::from_static("application/json"));
+        req.insert("user-agent", HeaderValue::from_static("wstd"));
+        assert!(e.matches(&req));
+    }
+
+    #[test]
+    fn update_from_not_modified() {
+        let mut e = entry(&[
+            ("content-type", "application/json"),
+            ("content-length", "11"),
+            ("etag", "\"v1\""),
+            ("cache-control", "max-age=60"),
+        ]);
+        let mut not_modified = HeaderMap::new();
+        not_modified.insert(CACHE_CONTROL, HeaderValue::from_static("max-age=120"));
+        not_modified.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        not_modified.insert(SET_COOKIE, HeaderValue::from_static("session=2"));
+        e.update(&not_modified, at(T0 + 100), at(T0 + 101));
+        assert!(!e.headers.contains_key(SET_COOKIE));
+        assert_eq!(e.headers[CACHE_CONTROL], "max-age=120");
+        assert_eq!(e.headers[CONTENT_LENGTH], "11");
+        assert_eq!(e.headers[ETAG], "\"v1\"");
+        assert_eq!(e.response_time, at(T0 + 101));
+
+        let mut req = HeaderMap::new();
+        e.add_conditional_headers(&mut req);
+        assert_eq!(req[IF_NONE_MATCH], "\"v1\"");
+        assert!(!req.contains_key(IF_MODIFIED_SINCE));
+    }
+
+    #[test]
+    fn entry_bytes() {
+        let mut e = entry(&[
+            ("cache-control", "max-age=60"),
+            ("link", "</a>; rel=preload"),
+            ("link", "</b>; rel=preload"),
+            ("x-empty", ""),
+        ]);
+        e.vary.insert("accept", HeaderValue::from_static("*/*"));
+        e.body = Bytes::from_static(b"line 1\n\nline 3\n");
+        let decoded = Entry::from_bytes(&e.to_bytes()).unwrap();
+        assert_eq!(decoded, e);
+        assert_eq!(Entry::from_bytes(b"not an entry"), None);
+        let far = format!("wstd-cache/1 200 0 {}\n\n\n", u64::MAX);
+        assert_eq!(Entry::from_bytes(far.as_bytes()), None);
+    }
+
+    #[test]
+    fn new_entry_leaves_out_cookies() {
+        let response = Response::builder()
+            .header(CACHE_CONTROL, "max-age=60")
+            .header(VARY, "accept")
+            .header(SET_COOKIE, "session=1")
+            .body(())
+            .unwrap();
+        let (parts, ()) = response.into_parts();
+        let mut request = HeaderMap::new();
+        request.insert(http::header::ACCEPT, HeaderValue::from_static("*/*"));
+        let e = Entry::new(&parts, &request, at(T0), at(T0 + 1));
+        assert_eq!(e.headers[CACHE_CONTROL], "max-age=60");
+        assert_eq!(e.vary[http::header::ACCEPT], "*/*");
+        assert!(!e.headers.contains_key(SET_COOKIE));
+    }
+
+    #[test]
+    fn memory_lru() {
+        let storage = MemoryStorage::new(2);
+        storage.put("a", entry(&[]));
+        storage.put("b", entry(&[]));
+        assert!(storage.get("a").is_some());
+        // "b" is the least recently used.
+        storage.put("c", entry(&[]));
+        assert!(storage.get("b").is_none());
+        assert!(storage.get("a").is_some());
+        assert!(storage.get("c").is_some());
+        storage.remove("a");
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn stores_body_once_read() {
+        let storage = Arc::new(MemoryStorage::new(10));
+        let store = |max_size| {
+            let body = Body::from_stream(futures_lite::stream::iter(["{\"keys\":", "[]}"]));
+            let body = Body::from_http_body(StoringBody {
+                inner: body.into_boxed_body(),
+                buffer: BytesMut::new(),
+                store: Some(Store {
+                    storage: storage.clone(),
+                    key: format!("max {max_size}"),
+                    entry: entry(&[]),
+                    max_size,
+                }),
+            });
+            crate::runtime::block_on(async move {
+                let mut body = body;
+                body.str_contents().await.unwrap().to_owned()
+            })
+        };
+        assert_eq!(store(100), "{\"keys\":[]}");
+        assert_eq!(store(5), "{\"keys\":[]}");
+        assert_eq!(storage.get("max 100").unwrap().body(), "{\"keys\":[]}");
+        assert!(storage.get("max 5").is_none());
+    }
+
+    #[test]
+    fn invalidation() {
+        assert!(invalidates(&Method::POST, StatusCode::CREATED));
+        assert!(invalidates(&Method::DELETE, StatusCode::SEE_OTHER));
+        assert!(!invalidates(&Method::POST, StatusCode::BAD_REQUEST));
+        assert!(!invalidates(&Method::HEAD, StatusCode::OK));
+    }
+}
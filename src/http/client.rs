@@ -1,4 +1,6 @@
-use super::{Body, Error, Request, Response};
+use super::deadline::{self, Deadline};
+use super::{Body, Error, Request, Response, cache::Cache, cookie::CookieJar, redirect, retry};
+use crate::future::FutureExt;
 use crate::http::request::try_into_outgoing;
 use crate::http::response::try_from_incoming;
 use crate::io::AsyncPollable;
@@ -9,6 +11,12 @@ use wasip2::http::types::RequestOptions as WasiRequestOptions;
 #[derive(Debug, Clone)]
 pub struct Client {
     options: Option<RequestOptions>,
+    timeout: Option<Duration>,
+    deadline_header: Option<deadline::Header>,
+    redirect: redirect::Policy,
+    retry: retry::Policy,
+    cookies: Option<CookieJar>,
+    cache: Option<Cache>,
 }
 
 impl Default for Client {
@@ -20,13 +28,105 @@ impl Default for Client {
 impl Client {
     /// Create a new instance of `Client`
     pub fn new() -> Self {
-        Self { options: None }
+        Self {
+            options: None,
+            timeout: None,
+            deadline_header: None,
+            redirect: redirect::Policy::none(),
+            retry: retry::Policy::none(),
+            cookies: None,
+            cache: None,
+        }
     }
 
     /// Send an HTTP request.
+    ///
+    /// Redirects are followed as allowed by the client's
+    /// [redirect policy](Client::set_redirect_policy), and failed requests
+    /// are retried as allowed by its [retry policy](Client::set_retry_policy).
+    /// If the request has a [`Deadline`], or the client a
+    /// [timeout](Client::set_timeout), the whole request, including the
+    /// response body, is limited to it.
     pub async fn send<B: Into<Body>>(&self, req: Request<B>) -> Result<Response<Body>, Error> {
+        let mut req = req.map(Into::into);
+        let deadline = Deadline::earliest(
+            req.extensions().get::<Deadline>().copied(),
+            self.timeout.map(Deadline::after),
+        );
+        let Some(deadline) = deadline else {
+            return self.send_retries(req).await;
+        };
+        if deadline.has_passed() {
+            return Err(Error::from(super::error::ErrorKind::Timeout));
+        }
+        req.extensions_mut().insert(deadline);
+        let response = self.send_retries(req).timeout(deadline.instant()).await??;
+        Ok(response.map(|body| deadline::limit_body(body, deadline)))
+    }
+
+    /// Send an HTTP request, following redirects and retrying.
+    async fn send_retries(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        if self.retry.is_none() {
+            self.send_redirects(req).await
+        } else {
+            retry::send(self, req).await
+        }
+    }
+
+    /// Send an HTTP request, following redirects but without retrying.
+    pub(crate) async fn send_redirects(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
+        if self.redirect.is_none() {
+            self.send_once(req).await
+        } else {
+            redirect::send(self, req).await
+        }
+    }
+
+    /// Send an HTTP request, without following redirects.
+    pub(crate) async fn send_once(&self, mut req: Request<Body>) -> Result<Response<Body>, Error> {
+        let uri = req.uri().clone();
+        if let Some(jar) = &self.cookies {
+            jar.add_cookie_header(&uri, req.headers_mut());
+        }
+        if let (Some(header), Some(deadline)) = (
+            &self.deadline_header,
+            req.extensions().get::<Deadline>().copied(),
+        ) {
+            header.insert(req.headers_mut(), deadline);
+        }
+        #[cfg(any(
+            feature = "gzip",
+            feature = "deflate",
+            feature = "brotli",
+            feature = "zstd"
+        ))]
+        let decompress = super::compression::add_accept_encoding(req.headers_mut());
+
+        let response = match &self.cache {
+            Some(cache) => cache.send(self, req).await?,
+            None => self.send_wasi(req).await?,
+        };
+
+        if let Some(jar) = &self.cookies {
+            jar.store(&uri, response.headers());
+        }
+        #[cfg(any(
+            feature = "gzip",
+            feature = "deflate",
+            feature = "brotli",
+            feature = "zstd"
+        ))]
+        let response = if decompress {
+            super::compression::decompress_response(response)
+        } else {
+            response
+        };
+        Ok(response)
+    }
+
+    /// Send an HTTP request with `wasi:http/outgoing-handler`.
+    pub(crate) async fn send_wasi(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
         let (wasi_req, body) = try_into_outgoing(req)?;
-        let body = body.into();
         let wasi_body = wasi_req.body().unwrap();
 
         // 1. Start sending the request head
@@ -67,6 +167,65 @@ impl Client {
         self.options_mut().between_bytes_timeout = Some(d.into());
     }
 
+    /// Limit the whole of every request, including following redirects,
+    /// retrying and receiving the response body, to `d`. Unlike the other
+    /// timeouts, this is enforced by wstd rather than the wasi-http host.
+    /// See [`deadline`](super::deadline) for details.
+    pub fn set_timeout(&mut self, d: impl Into<Duration>) {
+        self.timeout = Some(d.into());
+    }
+
+    /// Send the time remaining until each request's deadline in `header`,
+    /// so that the server can limit its own work to it. By default, the
+    /// deadline is not sent. See [`deadline`](super::deadline) for details.
+    pub fn set_deadline_header(&mut self, header: deadline::Header) {
+        self.deadline_header = Some(header);
+    }
+
+    /// Set the policy for following redirects. By default, redirects are
+    /// not followed. See [`redirect`] for details.
+    pub fn set_redirect_policy(&mut self, policy: redirect::Policy) {
+        self.redirect = policy;
+    }
+
+    pub(crate) fn redirect_policy(&self) -> &redirect::Policy {
+        &self.redirect
+    }
+
+    /// Set the policy for retrying failed requests. By default, requests
+    /// are not retried. See [`retry`] for details.
+    pub fn set_retry_policy(&mut self, policy: retry::Policy) {
+        self.retry = policy;
+    }
+
+    pub(crate) fn retry_policy(&self) -> &retry::Policy {
+        &self.retry
+    }
+
+    /// Store cookies from responses in `jar`, and send the matching cookies
+    /// with requests. By default, cookies are neither stored nor sent. See
+    /// [`cookie`](super::cookie) for details.
+    pub fn set_cookie_jar(&mut self, jar: CookieJar) {
+        self.cookies = Some(jar);
+    }
+
+    /// The cookie jar of the client, if one has been set.
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookies.as_ref()
+    }
+
+    /// Store responses in `cache`, and use them for later requests while
+    /// they are fresh. By default, responses aren't cached. See
+    /// [`cache`](super::cache) for details.
+    pub fn set_cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+
+    /// The response cache of the client, if one has been set.
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
     fn options_mut(&mut self) -> &mut RequestOptions {
         match &mut self.options {
             Some(o) => o,
@@ -97,22 +256,18 @@ impl RequestOptions {
         let wasi = WasiRequestOptions::new();
         if let Some(timeout) = self.connect_timeout {
             wasi.set_connect_timeout(Some(timeout.0)).map_err(|()| {
-                anyhow::Error::msg(
-                    "wasi-http implementation does not support connect timeout option",
-                )
+                Error::other("wasi-http implementation does not support connect timeout option")
             })?;
         }
         if let Some(timeout) = self.first_byte_timeout {
             wasi.set_first_byte_timeout(Some(timeout.0)).map_err(|()| {
-                anyhow::Error::msg(
-                    "wasi-http implementation does not support first byte timeout option",
-                )
+                Error::other("wasi-http implementation does not support first byte timeout option")
             })?;
         }
         if let Some(timeout) = self.between_bytes_timeout {
             wasi.set_between_bytes_timeout(Some(timeout.0))
                 .map_err(|()| {
-                    anyhow::Error::msg(
+                    Error::other(
                         "wasi-http implementation does not support between byte timeout option",
                     )
                 })?;
@@ -0,0 +1,756 @@
+//! Compression of HTTP bodies, with the `gzip`, `deflate`, `brotli` and
+//! `zstd` features.
+//!
+//! Each feature enables a content coding, implemented in pure Rust so that
+//! it builds for `wasm32-wasip2`. Bodies are compressed and decompressed as
+//! they stream, chunk by chunk, without buffering the whole body.
+//!
+//! When any of these features is enabled, a [`Client`] sends an
+//! `Accept-Encoding` header listing the enabled codings with each request
+//! which doesn't already have one, and decompresses the response body
+//! according to its `Content-Encoding` header. The `Content-Encoding` and
+//! `Content-Length` headers are removed from the decompressed response. A
+//! request which sets its own `Accept-Encoding` header gets its response
+//! body as it was sent.
+//!
+//! A server can compress its response according to the request's
+//! `Accept-Encoding` header with [`compress_response`]:
+//!
+//! ```no_run
+//! use wstd::http::compression::compress_response;
+//! use wstd::http::{Body, Error, Request, Response};
+//!
+//! #[wstd::http_server]
+//! async fn main(request: Request<Body>) -> Result<Response<Body>, Error> {
+//!     let response = Response::new("Hello, compressed world!\n".into());
+//!     Ok(compress_response(request.headers(), response))
+//! }
+//! ```
+//!
+//! [`Client`]: super::Client
+
+use super::body::{Frame, HttpBody, SizeHint};
+use super::{Body, Error, HeaderMap, HeaderValue, Response, StatusCode};
+
+use bytes::Bytes;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use http_body_util::combinators::UnsyncBoxBody;
+use std::fmt;
+use std::io;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A content coding, as used by the `Content-Encoding` and
+/// `Accept-Encoding` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// `gzip`, with the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// `deflate`, which is zlib-wrapped deflate data, with the `deflate`
+    /// feature. Raw deflate data is also accepted when decompressing, as
+    /// some servers send it.
+    #[cfg(feature = "deflate")]
+    Deflate,
+    /// `br`, with the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli,
+    /// `zstd`, with the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    /// The enabled encodings, in order of preference.
+    const ALL: &[Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd,
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip,
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate,
+    ];
+
+    /// The name of the encoding in HTTP headers.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The encoding with the given name, if it is enabled. Names are
+    /// case-insensitive, and `x-gzip` is accepted as `gzip`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        #[cfg(feature = "gzip")]
+        if name.eq_ignore_ascii_case("x-gzip") {
+            return Some(Encoding::Gzip);
+        }
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|e| e.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// The `Accept-Encoding` header value listing the enabled encodings.
+    pub fn accept_encoding() -> HeaderValue {
+        let names: Vec<&str> = Self::ALL.iter().map(|e| e.as_str()).collect();
+        HeaderValue::try_from(names.join(", ")).expect("encoding names are valid header values")
+    }
+
+    /// Choose the enabled encoding most preferred by an `Accept-Encoding`
+    /// header, if any is acceptable.
+    pub fn negotiate(accept_encoding: &HeaderMap) -> Option<Self> {
+        let mut weights = Vec::new();
+        let mut wildcard = None;
+        for value in accept_encoding.get_all(ACCEPT_ENCODING) {
+            let Ok(value) = value.to_str() else { continue };
+            for item in value.split(',') {
+                let mut params = item.split(';');
+                let name = params.next().unwrap_or("").trim();
+                let q = params
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(parse_qvalue)
+                    .unwrap_or(1000);
+                if name == "*" {
+                    wildcard = Some(q);
+                } else if let Some(encoding) = Encoding::from_name(name) {
+                    weights.push((encoding, q));
+                }
+            }
+        }
+        let mut best: Option<(Encoding, u16)> = None;
+        for &encoding in Self::ALL {
+            let q = weights
+                .iter()
+                .find(|(e, _)| *e == encoding)
+                .map(|(_, q)| *q)
+                .or(wildcard)
+                .unwrap_or(0);
+            if q > 0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn decoder(self) -> Box<dyn Codec> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Box::new(WriteCodec(flate2::write::GzDecoder::new(Vec::new()))),
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => Box::new(DeflateDecoder::Detecting(Vec::new())),
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(WriteCodec(brotli::DecompressorWriter::new(
+                Vec::new(),
+                BUFFER_SIZE,
+            ))),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Box::new(ZstdDecoder::default()),
+        }
+    }
+
+    fn encoder(self) -> Box<dyn Codec> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Box::new(WriteCodec(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => Box::new(WriteCodec(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(BrotliEncoder(Some(brotli::CompressorWriter::new(
+                Vec::new(),
+                BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_LGWIN,
+            )))),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Box::new(ZstdEncoder { wrote_frame: false }),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parse a qvalue, such as `0.5`, as thousandths.
+fn parse_qvalue(s: &str) -> Option<u16> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac: u16 = format!("{frac:0<3}").parse().ok()?;
+    match int {
+        "0" => Some(frac),
+        "1" if frac == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+impl Body {
+    /// Compress the body with `encoding`, as it streams.
+    pub fn compress(self, encoding: Encoding) -> Body {
+        Body::from_http_body(Coded::new(
+            self.into_boxed_body(),
+            encoding.encoder(),
+            encoding,
+            false,
+        ))
+    }
+
+    /// Decompress the body, which was compressed with `encoding`, as it
+    /// streams.
+    pub fn decompress(self, encoding: Encoding) -> Body {
+        Body::from_http_body(Coded::new(
+            self.into_boxed_body(),
+            encoding.decoder(),
+            encoding,
+            true,
+        ))
+    }
+}
+
+/// Compress `response` with the encoding most preferred by the
+/// `Accept-Encoding` header in `request_headers`, and set its
+/// `Content-Encoding` and `Vary` headers.
+///
+/// The response is unchanged if none of the enabled encodings is
+/// acceptable, if it already has a `Content-Encoding`, if it has no body,
+/// or if its `Content-Type` is an image, audio or video type, which are
+/// usually compressed already.
+pub fn compress_response(request_headers: &HeaderMap, response: Response<Body>) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let already_compressed = parts.headers.get(CONTENT_TYPE).is_some_and(|ct| {
+        let ct = ct.as_bytes();
+        (ct.starts_with(b"image/") && !ct.starts_with(b"image/svg"))
+            || ct.starts_with(b"audio/")
+            || ct.starts_with(b"video/")
+    });
+    let skip = already_compressed
+        || parts.headers.contains_key(CONTENT_ENCODING)
+        || matches!(
+            parts.status,
+            StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+        )
+        || body.content_length() == Some(0);
+    let encoding = if skip {
+        None
+    } else {
+        Encoding::negotiate(request_headers)
+    };
+    let Some(encoding) = encoding else {
+        return Response::from_parts(parts, body);
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    Response::from_parts(parts, body.compress(encoding))
+}
+
+/// Add an `Accept-Encoding` header to a client request, unless it has one.
+/// Returns whether the header was added, in which case the response should
+/// be decompressed.
+pub(crate) fn add_accept_encoding(headers: &mut HeaderMap) -> bool {
+    if headers.contains_key(ACCEPT_ENCODING) {
+        return false;
+    }
+    headers.insert(ACCEPT_ENCODING, Encoding::accept_encoding());
+    true
+}
+
+/// Decompress a client response according to its `Content-Encoding`, if
+/// it is a single enabled encoding.
+pub(crate) fn decompress_response(response: Response<Body>) -> Response<Body> {
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Encoding::from_name);
+    let Some(encoding) = encoding else {
+        return response;
+    };
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body.decompress(encoding))
+}
+
+#[cfg(any(feature = "deflate", feature = "brotli", feature = "zstd"))]
+const BUFFER_SIZE: usize = 8 * 1024;
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5;
+#[cfg(feature = "brotli")]
+const BROTLI_LGWIN: u32 = 22;
+
+/// A streaming compressor or decompressor.
+trait Codec: Send {
+    /// Process a chunk of input, appending any output to `out`.
+    fn push(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+    /// Finish the stream, appending any remaining output to `out`.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// A codec from a `Write` implementation which writes its output to a
+/// `Vec`.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+struct WriteCodec<W>(W);
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+trait WriteToVec: Write + Send {
+    fn output(&mut self) -> &mut Vec<u8>;
+    fn try_finish(&mut self) -> io::Result<()>;
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate"))]
+mod flate {
+    use super::WriteToVec;
+    use flate2::write::{GzDecoder, GzEncoder, ZlibEncoder};
+    use std::io;
+
+    macro_rules! write_to_vec {
+        ($($ty:ident),*) => {$(
+            impl WriteToVec for $ty<Vec<u8>> {
+                fn output(&mut self) -> &mut Vec<u8> {
+                    self.get_mut()
+                }
+                fn try_finish(&mut self) -> io::Result<()> {
+                    $ty::try_finish(self)
+                }
+            }
+        )*};
+    }
+    write_to_vec!(GzDecoder, GzEncoder, ZlibEncoder);
+}
+
+#[cfg(feature = "brotli")]
+impl WriteToVec for brotli::DecompressorWriter<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+impl<W: WriteToVec> Codec for WriteCodec<W> {
+    fn push(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.write_all(input)?;
+        // Flush, so that a compressor emits its output for each chunk.
+        self.0.flush()?;
+        out.append(self.0.output());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.try_finish()?;
+        out.append(self.0.output());
+        Ok(())
+    }
+}
+
+/// A `deflate` decoder, which detects whether the data is zlib-wrapped
+/// from its first two bytes.
+///
+/// This uses `flate2::Decompress`, rather than a writer, to detect a
+/// truncated stream: a writer can't tell whether the stream has ended.
+#[cfg(feature = "deflate")]
+enum DeflateDecoder {
+    Detecting(Vec<u8>),
+    Decoding {
+        decompress: flate2::Decompress,
+        ended: bool,
+    },
+}
+
+#[cfg(feature = "deflate")]
+impl Codec for DeflateDecoder {
+    fn push(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            DeflateDecoder::Detecting(buf) => {
+                buf.extend_from_slice(input);
+                if buf.len() < 2 {
+                    return Ok(());
+                }
+                let header = u16::from_be_bytes([buf[0], buf[1]]);
+                let zlib = buf[0] & 0x0f == 8 && header % 31 == 0;
+                let buf = std::mem::take(buf);
+                *self = DeflateDecoder::Decoding {
+                    decompress: flate2::Decompress::new(zlib),
+                    ended: false,
+                };
+                self.push(&buf, out)
+            }
+            DeflateDecoder::Decoding { decompress, ended } => {
+                while !input.is_empty() && !*ended {
+                    out.reserve(BUFFER_SIZE);
+                    let before = decompress.total_in();
+                    let status = decompress
+                        .decompress_vec(input, out, flate2::FlushDecompress::None)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    let consumed = (decompress.total_in() - before) as usize;
+                    input = &input[consumed..];
+                    *ended = status == flate2::Status::StreamEnd;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            DeflateDecoder::Decoding { ended: true, .. } => Ok(()),
+            _ => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// A brotli encoder. The writer is taken to finish the stream.
+#[cfg(feature = "brotli")]
+struct BrotliEncoder(Option<brotli::CompressorWriter<Vec<u8>>>);
+
+#[cfg(feature = "brotli")]
+impl Codec for BrotliEncoder {
+    fn push(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.0.as_mut().expect("encoder used after finish");
+        writer.write_all(input)?;
+        writer.flush()?;
+        out.append(writer.get_mut());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        let writer = self.0.take().expect("encoder used after finish");
+        out.append(&mut writer.into_inner());
+        Ok(())
+    }
+}
+
+/// A zstd decoder, which decodes a sequence of frames as their blocks
+/// arrive.
+#[cfg(feature = "zstd")]
+#[derive(Default)]
+struct ZstdDecoder {
+    decoder: Option<ruzstd::decoding::FrameDecoder>,
+    input: Vec<u8>,
+}
+
+#[cfg(feature = "zstd")]
+impl ZstdDecoder {
+    /// The largest size of a zstd frame header.
+    const MAX_HEADER: usize = 18;
+
+    fn decode(&mut self, out: &mut Vec<u8>, eof: bool) -> io::Result<()> {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let decoder = match &mut self.decoder {
+                Some(decoder) => decoder,
+                None if self.input.is_empty() => return Ok(()),
+                None if self.input.len() < Self::MAX_HEADER && !eof => return Ok(()),
+                None => self.decoder.insert(ruzstd::decoding::FrameDecoder::new()),
+            };
+            let (read, written) = decoder
+                .decode_from_to(&self.input, &mut buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if read > self.input.len() {
+                // The decoder is waiting for a frame's 4 byte checksum, and
+                // reports reading it before it has arrived.
+                return Ok(());
+            }
+            self.input.drain(..read);
+            out.extend_from_slice(&buf[..written]);
+            if decoder.is_finished() && decoder.can_collect() == 0 {
+                // Start the next frame, if there is one.
+                self.decoder = None;
+                continue;
+            }
+            if read == 0 && written == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for ZstdDecoder {
+    fn push(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.input.extend_from_slice(input);
+        self.decode(out, false)
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.decode(out, true)?;
+        if self.decoder.is_some() || !self.input.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+/// A zstd encoder, which compresses each chunk as a separate frame, as
+/// a streaming encoder isn't available in pure Rust.
+#[cfg(feature = "zstd")]
+struct ZstdEncoder {
+    wrote_frame: bool,
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for ZstdEncoder {
+    fn push(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        if !input.is_empty() {
+            ruzstd::encoding::compress(
+                input,
+                &mut *out,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            );
+            self.wrote_frame = true;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if !self.wrote_frame {
+            ruzstd::encoding::compress(
+                &[][..],
+                &mut *out,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A body compressed or decompressed with a [`Codec`] as it streams.
+struct Coded {
+    inner: UnsyncBoxBody<Bytes, Error>,
+    codec: Box<dyn Codec>,
+    encoding: Encoding,
+    decoding: bool,
+    seen_input: bool,
+    trailers: Option<HeaderMap>,
+    done: bool,
+}
+
+impl Coded {
+    fn new(
+        inner: UnsyncBoxBody<Bytes, Error>,
+        codec: Box<dyn Codec>,
+        encoding: Encoding,
+        decoding: bool,
+    ) -> Self {
+        Self {
+            inner,
+            codec,
+            encoding,
+            decoding,
+            seen_input: false,
+            trailers: None,
+            done: false,
+        }
+    }
+
+    fn error(&self, e: io::Error) -> Error {
+        let action = if self.decoding {
+            "decompressing"
+        } else {
+            "compressing"
+        };
+        Error::from(e).context(format!("{action} {} body", self.encoding))
+    }
+}
+
+impl HttpBody for Coded {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            }
+            let mut out = Vec::new();
+            match std::task::ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        this.seen_input |= !data.is_empty();
+                        if let Err(e) = this.codec.push(&data, &mut out) {
+                            return Poll::Ready(Some(Err(this.error(e))));
+                        }
+                    }
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            this.trailers = Some(trailers);
+                        }
+                    }
+                },
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    this.done = true;
+                    // An empty body, such as the response to a HEAD request,
+                    // has nothing to decompress.
+                    if !(this.decoding && !this.seen_input)
+                        && let Err(e) = this.codec.finish(&mut out)
+                    {
+                        return Poll::Ready(Some(Err(this.error(e))));
+                    }
+                }
+            }
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(Bytes::from(out)))));
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::body::chunked;
+
+    fn contents(mut body: Body) -> Result<Vec<u8>, Error> {
+        crate::runtime::block_on(async move { Ok(body.contents().await?.to_vec()) })
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|i| format!("line {i}: the quick brown fox\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = sample();
+        for &encoding in Encoding::ALL {
+            let compressed = contents(chunked(&data, 4096).compress(encoding)).unwrap();
+            assert!(compressed.len() < data.len() / 2, "{encoding}");
+            for size in [1, 7, 1000, compressed.len()] {
+                let decompressed =
+                    contents(chunked(&compressed, size).decompress(encoding)).unwrap();
+                assert!(decompressed == data, "{encoding} in chunks of {size}");
+            }
+            let empty = contents(Body::empty().compress(encoding)).unwrap();
+            let decompressed = contents(chunked(&empty, 1).decompress(encoding)).unwrap();
+            assert!(decompressed.is_empty(), "{encoding}");
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let data = sample();
+        for &encoding in Encoding::ALL {
+            let compressed = contents(chunked(&data, 4096).compress(encoding)).unwrap();
+            let truncated = &compressed[..compressed.len() / 2];
+            let Err(err) = contents(chunked(truncated, 100).decompress(encoding)) else {
+                panic!("{encoding}: truncated body decompressed without error");
+            };
+            assert!(
+                err.to_string().contains("decompressing"),
+                "{encoding}: {err}"
+            );
+        }
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn raw_deflate() {
+        let data = sample();
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let decompressed = contents(chunked(&compressed, 10).decompress(Encoding::Deflate));
+        assert!(decompressed.unwrap() == data);
+    }
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            Encoding::negotiate(&headers)
+        };
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(
+            negotiate("*").map(Encoding::as_str),
+            Some(Encoding::ALL[0].as_str())
+        );
+        #[cfg(feature = "gzip")]
+        {
+            assert_eq!(negotiate("GZIP"), Some(Encoding::Gzip));
+            assert_eq!(negotiate("br;q=0, gzip;q=0.5"), Some(Encoding::Gzip));
+            assert_ne!(negotiate("gzip;q=0, *"), Some(Encoding::Gzip));
+        }
+        #[cfg(all(feature = "gzip", feature = "brotli"))]
+        {
+            assert_eq!(negotiate("gzip, br"), Some(Encoding::Brotli));
+            assert_eq!(negotiate("gzip;q=1, br;q=0.9"), Some(Encoding::Gzip));
+        }
+        assert_eq!(parse_qvalue("0.123"), Some(123));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        assert_eq!(parse_qvalue("1.5"), None);
+    }
+
+    #[test]
+    fn response_compression() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, "*".parse().unwrap());
+        let response = Response::builder()
+            .header(CONTENT_LENGTH, "5")
+            .body(Body::from("hello"))
+            .unwrap();
+        let response = compress_response(&headers, response);
+        let encoding = Encoding::ALL[0];
+        assert_eq!(response.headers()[CONTENT_ENCODING], encoding.as_str());
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+
+        let response = decompress_response(response);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(contents(response.into_body()).unwrap(), b"hello");
+
+        let image = Response::builder()
+            .header(CONTENT_TYPE, "image/png")
+            .body(Body::from("png"))
+            .unwrap();
+        let image = compress_response(&headers, image);
+        assert!(!image.headers().contains_key(CONTENT_ENCODING));
+    }
+}
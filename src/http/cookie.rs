@@ -0,0 +1,698 @@
+//! Cookie storage for the HTTP [`Client`].
+//!
+//! A [`Client`] with a [`CookieJar`], set with [`Client::set_cookie_jar`],
+//! stores the cookies set by the `Set-Cookie` headers of its responses, and
+//! sends the matching cookies in the `Cookie` header of its requests,
+//! including each hop of a redirect. Cookies are handled per RFC 6265:
+//!
+//! * The `Domain` attribute must match the host of the request, and a cookie
+//!   without one is only sent to that exact host. There is no public suffix
+//!   list, so a `Domain` attribute without a dot is only accepted if it is
+//!   the request's host.
+//! * A cookie is only sent to paths within its `Path` attribute, or by
+//!   default, the directory of the request's path.
+//! * `Expires` and `Max-Age` are evaluated against
+//!   [`wstd::time::SystemTime`](crate::time::SystemTime). A `Set-Cookie`
+//!   which has already expired removes the cookie from the jar.
+//! * `Secure` cookies are only accepted from, and sent to, `https` uris, as
+//!   are cookies with the `__Secure-` and `__Host-` name prefixes.
+//! * `HttpOnly` is recorded, but doesn't affect a client, which only makes
+//!   HTTP requests.
+//! * Every request is treated as same-site, so `SameSite` is recorded, but
+//!   only restricts which cookies are accepted: `SameSite=None` requires
+//!   `Secure`.
+//!
+//! With the `json` feature, a jar can be saved to json with
+//! [`CookieJar::to_json`], and loaded with [`CookieJar::from_json`], to keep
+//! cookies between component invocations.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::http::cookie::CookieJar;
+//! use wstd::http::{Client, Error};
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let jar = CookieJar::new();
+//! let mut client = Client::new();
+//! client.set_cookie_jar(jar.clone());
+//!
+//! client.post("https://example.com/login").body("user=me").send().await?;
+//! // The session cookie set by the login response is sent with this request.
+//! client.get("https://example.com/account").send().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`Client::set_cookie_jar`]: super::Client::set_cookie_jar
+
+use super::{HeaderMap, HeaderValue, Uri};
+
+use http::header::SET_COOKIE;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A store of cookies, shared between its clones.
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    inner: Arc<Mutex<Store>>,
+}
+
+#[derive(Default)]
+struct Store {
+    cookies: Vec<Cookie>,
+    next_id: u64,
+}
+
+impl CookieJar {
+    /// Create an empty jar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store the cookies set by the `Set-Cookie` headers of a response to
+    /// a request to `uri`.
+    pub fn store(&self, uri: &Uri, headers: &HeaderMap) {
+        let now = now();
+        for value in headers.get_all(SET_COOKIE) {
+            if let Ok(value) = value.to_str() {
+                self.store_at(uri, value, now);
+            }
+        }
+    }
+
+    /// Store the cookie set by a single `Set-Cookie` header value, from a
+    /// response to a request to `uri`. Returns whether the cookie was
+    /// accepted.
+    pub fn set_cookie(&self, uri: &Uri, set_cookie: &str) -> bool {
+        self.store_at(uri, set_cookie, now())
+    }
+
+    fn store_at(&self, uri: &Uri, set_cookie: &str, now: SystemTime) -> bool {
+        let Some(cookie) = Cookie::parse(uri, set_cookie, now) else {
+            return false;
+        };
+        let mut store = self.inner.lock().unwrap();
+        let existing = store.cookies.iter().position(|c| {
+            c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path
+        });
+        let created = match existing {
+            Some(i) => store.cookies.remove(i).created,
+            None => {
+                store.next_id += 1;
+                store.next_id
+            }
+        };
+        if !cookie.is_expired(now) {
+            store.cookies.push(Cookie { created, ..cookie });
+        }
+        true
+    }
+
+    /// The value of the `Cookie` header for a request to `uri`, if any
+    /// cookies in the jar match it.
+    pub fn cookie_header(&self, uri: &Uri) -> Option<HeaderValue> {
+        self.cookie_header_at(uri, now())
+    }
+
+    fn cookie_header_at(&self, uri: &Uri, now: SystemTime) -> Option<HeaderValue> {
+        let mut store = self.inner.lock().unwrap();
+        store.cookies.retain(|c| !c.is_expired(now));
+        let mut matching: Vec<&Cookie> = store.cookies.iter().filter(|c| c.matches(uri)).collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths first, then the earliest created.
+        matching.sort_by_key(|c| (std::cmp::Reverse(c.path.len()), c.created));
+        let header = matching
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::try_from(header).ok()
+    }
+
+    /// Add the matching cookies to the `Cookie` header of a request to
+    /// `uri`, after any cookies which the request already has.
+    pub(crate) fn add_cookie_header(&self, uri: &Uri, headers: &mut HeaderMap) {
+        let Some(cookies) = self.cookie_header(uri) else {
+            return;
+        };
+        let value = match headers.get(http::header::COOKIE) {
+            Some(existing) => {
+                let mut value = existing.as_bytes().to_vec();
+                value.extend_from_slice(b"; ");
+                value.extend_from_slice(cookies.as_bytes());
+                match HeaderValue::from_bytes(&value) {
+                    Ok(value) => value,
+                    Err(_) => return,
+                }
+            }
+            None => cookies,
+        };
+        headers.insert(http::header::COOKIE, value);
+    }
+
+    /// The cookies in the jar which have not expired.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = now();
+        let store = self.inner.lock().unwrap();
+        store
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove all cookies from the jar.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().cookies.clear();
+    }
+
+    /// Serialize the cookies in the jar which have not expired as json.
+    ///
+    /// Session cookies, which have no expiry time, are included, so that a
+    /// session can continue in a later component invocation.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, super::Error> {
+        self.to_json_at(now())
+    }
+
+    #[cfg(feature = "json")]
+    fn to_json_at(&self, now: SystemTime) -> Result<String, super::Error> {
+        use serde_json::{Value, json};
+        let store = self.inner.lock().unwrap();
+        let cookies = store
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .map(|c| {
+                json!({
+                    "name": c.name,
+                    "value": c.value,
+                    "domain": c.domain,
+                    "host_only": c.host_only,
+                    "path": c.path,
+                    "expires": c.expires.map(|t| {
+                        t.duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs()
+                    }),
+                    "secure": c.secure,
+                    "http_only": c.http_only,
+                    "same_site": c.same_site.map(|s| s.as_str()),
+                })
+            })
+            .collect::<Vec<Value>>();
+        Ok(serde_json::to_string(&cookies)?)
+    }
+
+    /// Load a jar from json produced by [`CookieJar::to_json`].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, super::Error> {
+        use super::error::Context;
+        use serde_json::Value;
+
+        fn field<'a>(cookie: &'a Value, name: &str) -> Result<&'a Value, super::Error> {
+            cookie
+                .get(name)
+                .with_context(|| format!("cookie is missing field `{name}`"))
+        }
+        fn string(cookie: &Value, name: &str) -> Result<String, super::Error> {
+            Ok(field(cookie, name)?
+                .as_str()
+                .with_context(|| format!("cookie field `{name}` is not a string"))?
+                .to_owned())
+        }
+        fn boolean(cookie: &Value, name: &str) -> Result<bool, super::Error> {
+            field(cookie, name)?
+                .as_bool()
+                .with_context(|| format!("cookie field `{name}` is not a boolean"))
+        }
+
+        let cookies: Vec<Value> = serde_json::from_str(json).context("parsing cookie jar json")?;
+        let mut store = Store::default();
+        for cookie in &cookies {
+            let expires = match field(cookie, "expires")? {
+                Value::Null => None,
+                value => {
+                    let secs = value
+                        .as_u64()
+                        .context("cookie field `expires` is not a number")?;
+                    let expires = SystemTime::UNIX_EPOCH
+                        .checked_add(Duration::from_secs(secs))
+                        .context("cookie field `expires` is out of range")?;
+                    Some(expires)
+                }
+            };
+            let same_site = match field(cookie, "same_site")? {
+                Value::Null => None,
+                value => Some(
+                    value
+                        .as_str()
+                        .and_then(SameSite::parse)
+                        .context("cookie field `same_site` is invalid")?,
+                ),
+            };
+            store.next_id += 1;
+            store.cookies.push(Cookie {
+                name: string(cookie, "name")?,
+                value: string(cookie, "value")?,
+                domain: string(cookie, "domain")?,
+                host_only: boolean(cookie, "host_only")?,
+                path: string(cookie, "path")?,
+                expires,
+                secure: boolean(cookie, "secure")?,
+                http_only: boolean(cookie, "http_only")?,
+                same_site,
+                created: store.next_id,
+            });
+        }
+        Ok(Self {
+            inner: Arc::new(Mutex::new(store)),
+        })
+    }
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store = self.inner.lock().unwrap();
+        f.debug_list().entries(&store.cookies).finish()
+    }
+}
+
+/// A cookie stored in a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    created: u64,
+}
+
+impl Cookie {
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The domain which the cookie is sent to.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Whether the cookie is only sent to exactly [`Cookie::domain`], rather
+    /// than also to its subdomains.
+    pub fn host_only(&self) -> bool {
+        self.host_only
+    }
+
+    /// The path which the cookie is sent to, along with its subpaths.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// When the cookie expires. Session cookies have no expiry time.
+    pub fn expires(&self) -> Option<std::time::SystemTime> {
+        self.expires
+    }
+
+    /// Whether the cookie is only sent over `https`.
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Whether the cookie has the `HttpOnly` attribute.
+    pub fn http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// The `SameSite` attribute of the cookie, if it has one.
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    /// Parse a `Set-Cookie` header value from a response to `uri`,
+    /// following RFC 6265 section 5.2 and 5.3.
+    fn parse(uri: &Uri, set_cookie: &str, now: SystemTime) -> Option<Self> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let is_https = uri.scheme_str() == Some("https");
+
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut domain = None;
+        let mut path = None;
+        let mut expires = None;
+        let mut max_age = None;
+        let mut secure = false;
+        let mut http_only = false;
+        let mut same_site = None;
+        for attribute in attributes {
+            let (key, val) = match attribute.split_once('=') {
+                Some((key, val)) => (key.trim(), val.trim()),
+                None => (attribute.trim(), ""),
+            };
+            if key.eq_ignore_ascii_case("expires") {
+                if let Some(t) = super::date::parse_http_date(val) {
+                    expires = Some(t);
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                if let Ok(secs) = val.parse::<i64>() {
+                    max_age = Some(match u64::try_from(secs) {
+                        Ok(secs) if secs > 0 => now
+                            .checked_add(Duration::from_secs(secs))
+                            .unwrap_or(now + Duration::from_secs(u32::MAX.into())),
+                        _ => SystemTime::UNIX_EPOCH,
+                    });
+                }
+            } else if key.eq_ignore_ascii_case("domain") {
+                let val = val.strip_prefix('.').unwrap_or(val);
+                if !val.is_empty() {
+                    domain = Some(val.to_ascii_lowercase());
+                }
+            } else if key.eq_ignore_ascii_case("path") {
+                path = val.starts_with('/').then(|| val.to_owned());
+            } else if key.eq_ignore_ascii_case("secure") {
+                secure = true;
+            } else if key.eq_ignore_ascii_case("httponly") {
+                http_only = true;
+            } else if key.eq_ignore_ascii_case("samesite") {
+                same_site = SameSite::parse(val);
+            }
+        }
+
+        let (domain, host_only) = match domain {
+            Some(domain) if domain == host => (domain, false),
+            Some(domain) if domain.contains('.') && domain_matches(&host, &domain) => {
+                (domain, false)
+            }
+            Some(_) => return None,
+            None => (host, true),
+        };
+        let path = path.unwrap_or_else(|| default_path(uri.path()));
+
+        if secure && !is_https {
+            return None;
+        }
+        if same_site == Some(SameSite::None) && !secure {
+            return None;
+        }
+        if name.starts_with("__Secure-") && !secure {
+            return None;
+        }
+        if name.starts_with("__Host-") && !(secure && host_only && path == "/") {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain,
+            host_only,
+            path,
+            expires: max_age.or(expires),
+            secure,
+            http_only,
+            same_site,
+            created: 0,
+        })
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, uri: &Uri) -> bool {
+        let Some(host) = uri.host() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(uri.path(), &self.path)
+            && (!self.secure || uri.scheme_str() == Some("https"))
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// `SameSite=Strict`
+    Strict,
+    /// `SameSite=Lax`
+    Lax,
+    /// `SameSite=None`
+    None,
+}
+
+impl SameSite {
+    fn parse(s: &str) -> Option<Self> {
+        if s.eq_ignore_ascii_case("strict") {
+            Some(Self::Strict)
+        } else if s.eq_ignore_ascii_case("lax") {
+            Some(Self::Lax)
+        } else if s.eq_ignore_ascii_case("none") {
+            Some(Self::None)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+fn now() -> SystemTime {
+    crate::time::SystemTime::now().into()
+}
+
+/// Whether `host` domain-matches `domain`, per RFC 6265 section 5.1.3.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// Whether `path` path-matches `cookie_path`, per RFC 6265 section 5.1.4.
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    let path = if path.is_empty() { "/" } else { path };
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The default path of a cookie, per RFC 6265 section 5.1.4.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(i) => path[..i].to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn header(jar: &CookieJar, uri: &str) -> Option<String> {
+        jar.cookie_header_at(&Uri::try_from(uri).unwrap(), at(NOW))
+            .map(|v| v.to_str().unwrap().to_owned())
+    }
+
+    fn set(jar: &CookieJar, uri: &str, set_cookie: &str) -> bool {
+        jar.store_at(&Uri::try_from(uri).unwrap(), set_cookie, at(NOW))
+    }
+
+    #[test]
+    fn domain_and_path() {
+        let jar = CookieJar::new();
+        assert!(set(&jar, "https://www.example.com/a/b", "host=1"));
+        assert!(set(
+            &jar,
+            "https://www.example.com/",
+            "shared=2; Domain=.example.com"
+        ));
+        assert!(set(&jar, "https://www.example.com/", "deep=3; Path=/a/b"));
+        assert!(!set(
+            &jar,
+            "https://www.example.com/",
+            "other=4; Domain=other.com"
+        ));
+        assert!(!set(&jar, "https://www.example.com/", "tld=5; Domain=com"));
+
+        assert_eq!(
+            header(&jar, "https://www.example.com/a/b/c").as_deref(),
+            Some("deep=3; host=1; shared=2")
+        );
+        assert_eq!(
+            header(&jar, "https://www.example.com/a").as_deref(),
+            Some("host=1; shared=2")
+        );
+        assert_eq!(
+            header(&jar, "https://www.example.com/ab").as_deref(),
+            Some("shared=2")
+        );
+        assert_eq!(
+            header(&jar, "https://api.example.com/").as_deref(),
+            Some("shared=2")
+        );
+        assert_eq!(header(&jar, "https://example.org/"), None);
+    }
+
+    #[test]
+    fn expiry() {
+        let jar = CookieJar::new();
+        assert!(set(&jar, "https://example.com/", "session=1"));
+        assert!(set(
+            &jar,
+            "https://example.com/",
+            "future=2; Expires=Wed, 21 Oct 2099 07:28:00 GMT"
+        ));
+        assert!(set(
+            &jar,
+            "https://example.com/",
+            "past=3; Expires=Wed, 21 Oct 2015 07:28:00 GMT"
+        ));
+        assert!(set(
+            &jar,
+            "https://example.com/",
+            "max=4; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT"
+        ));
+        assert_eq!(
+            header(&jar, "https://example.com/").as_deref(),
+            Some("session=1; future=2; max=4")
+        );
+
+        // Setting a cookie which has expired removes it.
+        assert!(set(&jar, "https://example.com/", "session=; Max-Age=0"));
+        assert_eq!(
+            header(&jar, "https://example.com/").as_deref(),
+            Some("future=2; max=4")
+        );
+
+        // Max-Age is relative to the time the cookie was set.
+        let late = jar.cookie_header_at(&Uri::from_static("https://example.com/"), at(NOW + 61));
+        assert_eq!(late.unwrap(), "future=2");
+    }
+
+    #[test]
+    fn secure_and_same_site() {
+        let jar = CookieJar::new();
+        assert!(!set(&jar, "http://example.com/", "a=1; Secure"));
+        assert!(set(&jar, "https://example.com/", "a=1; Secure; HttpOnly"));
+        assert!(!set(&jar, "https://example.com/", "b=2; SameSite=None"));
+        assert!(set(
+            &jar,
+            "https://example.com/",
+            "b=2; SameSite=None; Secure"
+        ));
+        assert!(set(&jar, "https://example.com/", "c=3; SameSite=Strict"));
+        assert!(!set(
+            &jar,
+            "https://example.com/",
+            "__Host-d=4; Secure; Path=/x"
+        ));
+        assert!(set(
+            &jar,
+            "https://example.com/",
+            "__Host-d=4; Secure; Path=/"
+        ));
+
+        assert_eq!(header(&jar, "http://example.com/").as_deref(), Some("c=3"));
+        assert_eq!(
+            header(&jar, "https://example.com/").as_deref(),
+            Some("a=1; b=2; c=3; __Host-d=4")
+        );
+        let cookies = jar.inner.lock().unwrap().cookies.clone();
+        assert!(cookies[0].http_only());
+        assert_eq!(cookies[2].same_site(), Some(SameSite::Strict));
+    }
+
+    #[test]
+    fn replaces_existing() {
+        let jar = CookieJar::new();
+        assert!(set(&jar, "https://example.com/", "a=1"));
+        assert!(set(&jar, "https://example.com/", "b=2"));
+        assert!(set(&jar, "https://example.com/", "a=3"));
+        assert_eq!(
+            header(&jar, "https://example.com/").as_deref(),
+            Some("a=3; b=2")
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        let jar = CookieJar::new();
+        set(
+            &jar,
+            "https://example.com/app/",
+            "session=abc; Secure; HttpOnly; SameSite=Lax",
+        );
+        set(
+            &jar,
+            "https://example.com/",
+            "pref=dark; Domain=example.com; Max-Age=3600",
+        );
+        set(
+            &jar,
+            "https://example.com/",
+            "gone=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+        );
+
+        let json = jar.to_json_at(at(NOW)).unwrap();
+        let loaded = CookieJar::from_json(&json).unwrap();
+        assert_eq!(
+            header(&loaded, "https://example.com/app/x").as_deref(),
+            Some("session=abc; pref=dark")
+        );
+        let cookies = |jar: &CookieJar| {
+            let mut cookies = jar.inner.lock().unwrap().cookies.clone();
+            cookies.iter_mut().for_each(|c| c.created = 0);
+            cookies
+        };
+        assert_eq!(cookies(&loaded), cookies(&jar));
+
+        assert!(CookieJar::from_json(r#"[{"name":"x"}]"#).is_err());
+        assert!(CookieJar::from_json("{}").is_err());
+
+        let far = json.replacen("null", &u64::MAX.to_string(), 1);
+        let error = CookieJar::from_json(&far).unwrap_err();
+        assert!(error.to_string().contains("`expires` is out of range"));
+    }
+}
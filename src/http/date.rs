@@ -0,0 +1,127 @@
+//! Parsing of HTTP-date values, as used by headers such as `Retry-After`,
+//! `Date` and `Expires`.
+
+use std::time::{Duration, SystemTime};
+
+/// Parse an HTTP-date, as specified by RFC 9110 section 5.6.7. The preferred
+/// IMF-fixdate format is accepted, as are the obsolete RFC 850 and asctime
+/// formats.
+pub(crate) fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let mut time = None;
+    let tokens = s
+        .split([' ', ',', '-'])
+        .filter(|token| !token.is_empty())
+        .enumerate();
+    for (i, token) in tokens {
+        if token.contains(':') {
+            time = Some(parse_time(token)?);
+        } else if let Some(m) = parse_month(token) {
+            month = Some(m);
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            let n: u32 = token.parse().ok()?;
+            match token.len() {
+                1 | 2 if day.is_none() => day = Some(n),
+                2 => year = Some(if n < 70 { 2000 + n } else { 1900 + n }),
+                4 => year = Some(n),
+                _ => return None,
+            }
+        } else if i == 0 || token == "GMT" {
+            // The day of the week, which is redundant, or the time zone,
+            // which is always GMT.
+        } else {
+            return None;
+        }
+    }
+    let (day, month, year, (hour, minute, second)) = (day?, month?, year?, time?);
+    if !(1..=days_in_month(year, month)).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = days_from_civil(i64::from(year), month, day);
+    let secs = days * 86400 + i64::from(hour * 3600 + minute * 60 + second);
+    let secs = u64::try_from(secs).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+fn parse_month(token: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    MONTHS
+        .iter()
+        .position(|m| m.eq_ignore_ascii_case(token))
+        .map(|i| i as u32 + 1)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = token.split(':').map(|part| part.parse::<u32>().ok());
+    let time = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(time)
+}
+
+/// The number of days since 1970-01-01 of a date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        for s in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(s), Some(expected), "{s}");
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "",
+            "120",
+            "Sun, 06 Nov 1994",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Thu, 31 Feb 1994 08:49:37 GMT",
+            "Thu, 31 Apr 1994 08:49:37 GMT",
+            "Tue, 29 Feb 1994 08:49:37 GMT",
+            "Thu, 29 Feb 1900 08:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn leap_day() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT"),
+            Some(expected)
+        );
+    }
+}
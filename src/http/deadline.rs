@@ -0,0 +1,340 @@
+//! Deadlines for whole requests.
+//!
+//! The timeouts of a [`Client`], such as
+//! [`Client::set_first_byte_timeout`], are enforced by the wasi-http host,
+//! each limits only one phase of a request, and a host may not support them
+//! at all. A [`Deadline`] instead limits the whole of a request: sending it,
+//! following its redirects and retries, and receiving the response body. It
+//! is enforced by wstd with a [`Timer`](crate::time::Timer), so it works with
+//! any host.
+//!
+//! A deadline is set for every request with [`Client::set_timeout`], or for
+//! one request by inserting a [`Deadline`] into its extensions, as
+//! [`RequestBuilder::deadline`] does. When both are set, the earlier one
+//! applies. When the deadline passes, sending fails, or the response body
+//! gives an error, of kind [`ErrorKind::Timeout`](super::error::ErrorKind).
+//!
+//! # Propagation
+//!
+//! A server may be given a deadline by its caller in a header, such as
+//! gRPC's `grpc-timeout`. A [`Header`] reads it from an incoming request,
+//! and, set with [`Client::set_deadline_header`], writes the time remaining
+//! to outgoing requests, so that the services a server calls in turn know
+//! how long they have:
+//!
+//! ```no_run
+//! use wstd::http::{Body, Client, Error, Request, Response, deadline};
+//!
+//! # async fn handle(request: Request<Body>) -> Result<Response<Body>, Error> {
+//! let mut client = Client::new();
+//! client.set_deadline_header(deadline::Header::GrpcTimeout);
+//!
+//! let mut backend = client.get("https://backend.example.com/lookup");
+//! if let Some(deadline) = deadline::Header::GrpcTimeout.deadline(request.headers()) {
+//!     backend = backend.deadline(deadline);
+//! }
+//! let response = backend.send().await?;
+//! # Ok(response)
+//! # }
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`Client::set_first_byte_timeout`]: super::Client::set_first_byte_timeout
+//! [`Client::set_timeout`]: super::Client::set_timeout
+//! [`Client::set_deadline_header`]: super::Client::set_deadline_header
+//! [`RequestBuilder::deadline`]: super::RequestBuilder::deadline
+
+use super::error::ErrorKind;
+use super::{Body, Error, HeaderMap, HeaderName, HeaderValue};
+use crate::time::{Duration, Instant, Wait};
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::UnsyncBoxBody;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The instant by which a request, including its response body, must be
+/// complete.
+///
+/// Insert a `Deadline` into the extensions of a request to limit it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// A deadline at `instant`.
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    /// A deadline `timeout` from now, or at the latest representable
+    /// instant if that is sooner.
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant(Instant::now().0.saturating_add(timeout.0)))
+    }
+
+    /// The instant of the deadline.
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// The time remaining until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.duration_since(Instant::now())
+    }
+
+    /// Whether the deadline has passed.
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= self.0
+    }
+
+    /// The earlier of two optional deadlines.
+    pub(crate) fn earliest(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// A header which carries a deadline, as the time remaining until it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Header {
+    /// The `grpc-timeout` header of gRPC: at most 8 digits followed by a
+    /// unit, one of `H`, `M`, `S`, `m`, `u` or `n` for hours, minutes,
+    /// seconds, milliseconds, microseconds or nanoseconds, such as `250m`.
+    GrpcTimeout,
+    /// A header with the given name, holding the time remaining in whole
+    /// milliseconds, such as `x-request-timeout-ms: 250`.
+    Milliseconds(HeaderName),
+}
+
+static GRPC_TIMEOUT: HeaderName = HeaderName::from_static("grpc-timeout");
+
+impl Header {
+    /// The name of the header.
+    pub fn name(&self) -> &HeaderName {
+        match self {
+            Header::GrpcTimeout => &GRPC_TIMEOUT,
+            Header::Milliseconds(name) => name,
+        }
+    }
+
+    /// The deadline given by this header in `headers`, counted from now, if
+    /// it is present and valid. A timeout longer than 100 years is clamped to
+    /// 100 years.
+    pub fn deadline(&self, headers: &HeaderMap) -> Option<Deadline> {
+        let value = headers.get(self.name())?.to_str().ok()?;
+        let timeout = match self {
+            Header::GrpcTimeout => parse_grpc_timeout(value)?,
+            Header::Milliseconds(_) => timeout(value.trim().parse().ok()?, 1_000_000),
+        };
+        Some(Deadline::after(timeout))
+    }
+
+    /// Set this header in `headers` to the time remaining until `deadline`.
+    pub fn insert(&self, headers: &mut HeaderMap, deadline: Deadline) {
+        let remaining = std::time::Duration::from(deadline.remaining());
+        let value = match self {
+            Header::GrpcTimeout => encode_grpc_timeout(remaining),
+            Header::Milliseconds(_) => remaining.as_millis().to_string(),
+        };
+        let value = HeaderValue::try_from(value).expect("timeout is a valid header value");
+        headers.insert(self.name().clone(), value);
+    }
+}
+
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+    let nanos_per = match unit {
+        "H" => 3_600_000_000_000,
+        "M" => 60_000_000_000,
+        "S" => 1_000_000_000,
+        "m" => 1_000_000,
+        "u" => 1_000,
+        "n" => 1,
+        _ => return None,
+    };
+    Some(timeout(n, nanos_per))
+}
+
+/// The longest timeout read from a header, 100 years. Longer ones, which
+/// may not fit in a [`Duration`] at all, are clamped to it.
+const MAX_TIMEOUT_NANOS: u64 = 100 * 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A timeout of `n` units of `nanos_per` nanoseconds each.
+fn timeout(n: u64, nanos_per: u64) -> Duration {
+    let nanos = n
+        .checked_mul(nanos_per)
+        .map_or(MAX_TIMEOUT_NANOS, |nanos| nanos.min(MAX_TIMEOUT_NANOS));
+    Duration::from_nanos(nanos)
+}
+
+/// Encode `timeout` in the most precise unit which fits in 8 digits,
+/// rounding up so that the receiver's deadline is never the earlier one.
+fn encode_grpc_timeout(timeout: std::time::Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let nanos = timeout.as_nanos();
+    for (unit, per) in [
+        ("n", 1),
+        ("u", 1_000),
+        ("m", 1_000_000),
+        ("S", 1_000_000_000),
+        ("M", 60_000_000_000),
+    ] {
+        let n = nanos.div_ceil(per);
+        if n <= MAX {
+            return format!("{n}{unit}");
+        }
+    }
+    format!("{}H", nanos.div_ceil(3_600_000_000_000).min(MAX))
+}
+
+/// Limit `body` to `deadline`: once it passes, reading the body gives a
+/// timeout error.
+pub(crate) fn limit_body(body: Body, deadline: Deadline) -> Body {
+    Body::from_http_body(DeadlineBody {
+        inner: body.into_boxed_body(),
+        wait: crate::task::sleep_until(deadline.instant()),
+        expired: false,
+    })
+}
+
+pin_project! {
+    struct DeadlineBody {
+        #[pin]
+        inner: UnsyncBoxBody<Bytes, Error>,
+        #[pin]
+        wait: Wait,
+        expired: bool,
+    }
+}
+
+impl HttpBody for DeadlineBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = self.project();
+        if *this.expired {
+            return Poll::Ready(None);
+        }
+        if this.inner.is_end_stream() {
+            return Poll::Ready(None);
+        }
+        if this.wait.poll(cx).is_ready() {
+            *this.expired = true;
+            let error = Error::from(ErrorKind::Timeout).context("reading response body");
+            return Poll::Ready(Some(Err(error)));
+        }
+        this.inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.expired || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(
+            parse_grpc_timeout("99999999n"),
+            Some(Duration::from_nanos(99_999_999))
+        );
+        assert_eq!(parse_grpc_timeout("100000000n"), None);
+        assert_eq!(parse_grpc_timeout("10"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+
+        let encode = |d| encode_grpc_timeout(std::time::Duration::from_nanos(d));
+        assert_eq!(encode(0), "0n");
+        assert_eq!(encode(99_999_999), "99999999n");
+        assert_eq!(encode(250_000_000), "250000u");
+        assert_eq!(encode(250_000_001), "250001u");
+        assert_eq!(encode(3_000_000_000_000), "3000000m");
+        assert_eq!(encode(u64::MAX), "5124096H");
+    }
+
+    #[test]
+    fn clamps_long_timeouts() {
+        let max = Some(Duration::from_nanos(MAX_TIMEOUT_NANOS));
+        assert_eq!(parse_grpc_timeout("99999999H"), max);
+        assert_eq!(parse_grpc_timeout("5124096H"), max);
+        crate::runtime::test_runtime::block_on(async {
+            let name = HeaderName::from_static("x-request-timeout-ms");
+            let mut headers = HeaderMap::new();
+            headers.insert(GRPC_TIMEOUT.clone(), HeaderValue::from_static("99999999H"));
+            headers.insert(name.clone(), HeaderValue::from(u64::MAX));
+            let expected = Some(Deadline::after(Duration::from_nanos(MAX_TIMEOUT_NANOS)));
+            assert_eq!(Header::GrpcTimeout.deadline(&headers), expected);
+            assert_eq!(Header::Milliseconds(name).deadline(&headers), expected);
+
+            let latest = Deadline::after(Duration::from_nanos(u64::MAX));
+            assert_eq!(latest.instant(), Instant(u64::MAX));
+        });
+    }
+
+    #[test]
+    fn header_round_trip() {
+        crate::runtime::test_runtime::block_on(async {
+            let deadline = Deadline::after(Duration::from_millis(1500));
+            let name = HeaderName::from_static("x-request-timeout-ms");
+            let mut headers = HeaderMap::new();
+            Header::GrpcTimeout.insert(&mut headers, deadline);
+            Header::Milliseconds(name.clone()).insert(&mut headers, deadline);
+            assert_eq!(headers["grpc-timeout"], "1500000u");
+            assert_eq!(headers["x-request-timeout-ms"], "1500");
+            assert_eq!(Header::GrpcTimeout.deadline(&headers), Some(deadline));
+            assert_eq!(
+                Header::Milliseconds(name).deadline(&headers),
+                Some(deadline)
+            );
+        });
+    }
+
+    #[test]
+    fn body_times_out() {
+        use futures_lite::StreamExt;
+        let result = crate::runtime::test_runtime::block_on(async {
+            let body = Body::from_stream(
+                futures_lite::stream::once(Bytes::from("partial"))
+                    .chain(futures_lite::stream::pending()),
+            );
+            let deadline = Deadline::after(Duration::from_secs(5));
+            let mut body = limit_body(body, deadline);
+            let result = body.contents().await.map(|_| ());
+            (result, deadline.has_passed())
+        });
+        let (result, passed) = result;
+        assert!(passed);
+        assert!(result.unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn body_within_deadline() {
+        let contents = crate::runtime::test_runtime::block_on(async {
+            let body = Body::from("complete");
+            let mut body = limit_body(body, Deadline::after(Duration::from_secs(5)));
+            body.str_contents().await.unwrap().to_owned()
+        });
+        assert_eq!(contents, "complete");
+    }
+}
@@ -0,0 +1,595 @@
+//! Resumable and ranged downloads with the HTTP [`Client`].
+//!
+//! [`Client::download`] streams a response body into any [`AsyncWrite`],
+//! such as a file, reporting [`Progress`] as it goes. If the body fails
+//! part way, for instance because the host's between-bytes timeout expired,
+//! the download is resumed where it stopped with a `Range: bytes=N-`
+//! request, rather than started again. The resumed request carries an
+//! `If-Range` header with the `ETag` of the first response, so that the
+//! server sends the rest of the same representation, or else the download
+//! fails rather than mixing two versions of it. A download without a strong
+//! `ETag` can't be resumed.
+//!
+//! With [`Download::parallel`], a large resource is fetched as ranges of a
+//! fixed size, several at once. The ranges are buffered in memory until
+//! they can be written in order, so at most the number of connections times
+//! the range size is buffered.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::http::Client;
+//! use wstd::io::Cursor;
+//!
+//! # async fn run() -> Result<(), wstd::http::Error> {
+//! let mut artifact = Cursor::new(Vec::new());
+//! let progress = Client::new()
+//!     .download("https://example.com/artifact.tar")
+//!     .max_resumes(10)
+//!     .progress(|progress| {
+//!         if let Some(total) = progress.total() {
+//!             println!("{} of {total} bytes", progress.downloaded());
+//!         }
+//!     })
+//!     .write_to(&mut artifact)
+//!     .await?;
+//! println!("downloaded {} bytes", progress.downloaded());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`AsyncWrite`]: crate::io::AsyncWrite
+
+use super::error::{Context as _, ErrorKind};
+use super::{
+    Body, Client, Error, HeaderMap, HeaderName, HeaderValue, Method, Request, RequestBuilder,
+    StatusCode, Uri,
+};
+use crate::io::{AsyncWrite, Cursor};
+use bytes::Buf;
+use http::header::{ACCEPT_ENCODING, CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use http::request::Parts;
+use http_body_util::BodyExt;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A download in preparation, which is started with
+/// [`Download::write_to`].
+///
+/// Created by [`Client::download`].
+#[must_use = "a Download does nothing until it is written somewhere"]
+pub struct Download<'a> {
+    client: Client,
+    request: RequestBuilder,
+    max_resumes: u32,
+    parallel: Option<(usize, u64)>,
+    progress: Option<Box<dyn FnMut(Progress) + 'a>>,
+}
+
+/// How much of a download has been written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    downloaded: u64,
+    total: Option<u64>,
+}
+
+impl Progress {
+    /// The number of bytes written so far.
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    /// The size of the whole resource, if the server gave it.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+impl<'a> Download<'a> {
+    /// Append a header to the requests of the download.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        <K as TryInto<HeaderName>>::Error: Into<http::Error>,
+        V: TryInto<HeaderValue>,
+        <V as TryInto<HeaderValue>>::Error: Into<http::Error>,
+    {
+        self.request = self.request.header(key, value);
+        self
+    }
+
+    /// Append all of the given headers to the requests of the download.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.request = self.request.headers(headers);
+        self
+    }
+
+    /// Set the number of times the download may be resumed after a failure.
+    /// The default is 3. With parallel ranges, each range may be resumed
+    /// this many times.
+    pub fn max_resumes(mut self, max_resumes: u32) -> Self {
+        self.max_resumes = max_resumes;
+        self
+    }
+
+    /// Fetch the resource as ranges of `range_size` bytes, with up to
+    /// `connections` requests at once. If the server doesn't support
+    /// ranges, the resource is downloaded with a single request.
+    pub fn parallel(mut self, connections: usize, range_size: u64) -> Self {
+        self.parallel = Some((connections.max(1), range_size.max(1)));
+        self
+    }
+
+    /// Call `f` with the progress of the download each time data is
+    /// written.
+    pub fn progress<'b>(self, f: impl FnMut(Progress) + 'b) -> Download<'b>
+    where
+        'a: 'b,
+    {
+        Download {
+            client: self.client,
+            request: self.request,
+            max_resumes: self.max_resumes,
+            parallel: self.parallel,
+            progress: Some(Box::new(f)),
+        }
+    }
+
+    /// Download the resource, writing it to `writer`, and flush `writer`
+    /// once the whole resource is written.
+    pub async fn write_to<W: AsyncWrite>(self, mut writer: W) -> Result<Progress, Error> {
+        let (mut parts, _) = self.request.build()?.into_parts();
+        parts.method = Method::GET;
+        // Ranges are of the representation as sent, so it must not be
+        // decompressed on the way.
+        parts
+            .headers
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        let fetcher = Fetcher {
+            client: self.client,
+            parts,
+            max_resumes: self.max_resumes,
+        };
+        let mut tracker = Tracker {
+            progress: Progress::default(),
+            callback: self.progress,
+        };
+        let mut etag = None;
+        match self.parallel {
+            None => {
+                fetcher
+                    .fetch(0, None, &mut etag, &mut writer, &mut tracker)
+                    .await?;
+            }
+            Some((connections, range_size)) => {
+                fetcher
+                    .fetch_parallel(connections, range_size, &mut writer, &mut tracker)
+                    .await?;
+            }
+        }
+        writer.flush().await.context("flushing download")?;
+        Ok(tracker.progress)
+    }
+}
+
+impl fmt::Debug for Download<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Download")
+            .field("request", &self.request)
+            .field("max_resumes", &self.max_resumes)
+            .field("parallel", &self.parallel)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Client {
+    /// Start building a download of the resource at `uri`. See
+    /// [`download`](super::download) for details.
+    pub fn download<U>(&self, uri: U) -> Download<'static>
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        Download {
+            client: self.clone(),
+            request: self.get(uri),
+            max_resumes: 3,
+            parallel: None,
+            progress: None,
+        }
+    }
+}
+
+/// Progress, and the callback to report it to.
+struct Tracker<'a> {
+    progress: Progress,
+    callback: Option<Box<dyn FnMut(Progress) + 'a>>,
+}
+
+impl Tracker<'_> {
+    fn advance(&mut self, n: usize) {
+        self.progress.downloaded += n as u64;
+        if let Some(callback) = &mut self.callback {
+            callback(self.progress);
+        }
+    }
+}
+
+/// Sends the requests of a download.
+#[derive(Clone)]
+struct Fetcher {
+    client: Client,
+    parts: Parts,
+    max_resumes: u32,
+}
+
+/// Where a [`Fetcher::fetch`] stopped.
+struct Fetched {
+    end: u64,
+    total: Option<u64>,
+}
+
+enum CopyError {
+    Read(Error),
+    Write(Error),
+}
+
+impl Fetcher {
+    /// Fetch the resource as ranges, writing them in order.
+    async fn fetch_parallel<W: AsyncWrite>(
+        &self,
+        connections: usize,
+        range_size: u64,
+        writer: &mut W,
+        tracker: &mut Tracker<'_>,
+    ) -> Result<(), Error> {
+        // The first range tells the size of the resource, and its ETag,
+        // which the other ranges are validated with.
+        let mut etag = None;
+        let first = self
+            .fetch(0, Some(range_size), &mut etag, writer, tracker)
+            .await?;
+        let Some(total) = first.total else {
+            return Ok(());
+        };
+        if first.end >= total {
+            return Ok(());
+        }
+        let Some(etag) = etag else {
+            // Ranges of different versions could be mixed, so continue
+            // with a single request instead.
+            self.fetch(first.end, None, &mut None, writer, tracker)
+                .await?;
+            return Ok(());
+        };
+
+        let mut next = first.end;
+        let mut tasks = VecDeque::new();
+        loop {
+            while tasks.len() < connections && next < total {
+                let (start, end) = (next, (next + range_size).min(total));
+                let fetcher = self.clone();
+                let mut etag = Some(etag.clone());
+                tasks.push_back(crate::runtime::spawn(async move {
+                    let mut buffer = Cursor::new(Vec::new());
+                    let mut tracker = Tracker {
+                        progress: Progress::default(),
+                        callback: None,
+                    };
+                    fetcher
+                        .fetch(start, Some(end), &mut etag, &mut buffer, &mut tracker)
+                        .await?;
+                    Ok::<_, Error>(buffer.into_inner())
+                }));
+                next = end;
+            }
+            let Some(task) = tasks.pop_front() else {
+                return Ok(());
+            };
+            let buffer = task.await?;
+            writer
+                .write_all(&buffer)
+                .await
+                .context("writing download")?;
+            tracker.advance(buffer.len());
+        }
+    }
+
+    /// Fetch the resource from `start` until `end`, or the end of the
+    /// resource, resuming after failures, and write it to `writer`.
+    async fn fetch<W: AsyncWrite>(
+        &self,
+        start: u64,
+        mut end: Option<u64>,
+        etag: &mut Option<HeaderValue>,
+        writer: &mut W,
+        tracker: &mut Tracker<'_>,
+    ) -> Result<Fetched, Error> {
+        let mut pos = start;
+        let mut total = None;
+        let mut resumes = 0;
+        loop {
+            let error = match self
+                .client
+                .send(self.request(pos, end, etag.as_ref()))
+                .await
+            {
+                Ok(response) => {
+                    let skip = check_response(&response, pos, etag, &mut total)?;
+                    if total.is_some() {
+                        tracker.progress.total = total;
+                    }
+                    if response.status() == StatusCode::OK && pos == 0 {
+                        // The server doesn't support ranges, so take all of
+                        // the resource from this response.
+                        end = None;
+                    }
+                    let body = response.into_body();
+                    match copy(body, skip, end, &mut pos, writer, tracker).await {
+                        Ok(()) => match end.or(total) {
+                            Some(expected) if pos < expected => {
+                                Error::other("response body ended early")
+                            }
+                            _ => return Ok(Fetched { end: pos, total }),
+                        },
+                        Err(CopyError::Write(e)) => return Err(e),
+                        Err(CopyError::Read(e)) if matches!(e.kind(), ErrorKind::Timeout) => {
+                            return Err(e);
+                        }
+                        Err(CopyError::Read(e)) => e,
+                    }
+                }
+                // Only failures to connect are retried: an error response
+                // is final.
+                Err(e) if e.error_code().is_some() => e,
+                Err(e) => return Err(e),
+            };
+            if resumes >= self.max_resumes {
+                return Err(error.context("download failed, and can't be resumed again"));
+            }
+            if pos > start && etag.is_none() {
+                return Err(error.context("download failed, and can't be resumed without an ETag"));
+            }
+            resumes += 1;
+        }
+    }
+
+    /// A request for the resource from `pos` until `end`.
+    fn request(&self, pos: u64, end: Option<u64>, etag: Option<&HeaderValue>) -> Request<Body> {
+        let mut request = Request::from_parts(self.parts.clone(), Body::empty());
+        let range = match end {
+            _ if pos == 0 && end.is_none() => return request,
+            Some(end) => format!("bytes={pos}-{}", end - 1),
+            None => format!("bytes={pos}-"),
+        };
+        let headers = request.headers_mut();
+        headers.insert(RANGE, range.try_into().unwrap());
+        if let Some(etag) = etag {
+            headers.insert(IF_RANGE, etag.clone());
+        }
+        request
+    }
+}
+
+/// Check that `response` continues the download at `pos`, and return the
+/// number of bytes of its body to skip to get there.
+fn check_response(
+    response: &http::Response<Body>,
+    pos: u64,
+    etag: &mut Option<HeaderValue>,
+    total: &mut Option<u64>,
+) -> Result<u64, Error> {
+    let response_etag = strong_etag(response.headers());
+    let skip = match response.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let range = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| parse_content_range(value.to_str().ok()?))
+                .context("invalid Content-Range in download response")?;
+            if range.0 != pos {
+                return Err(Error::other(format!(
+                    "download response range starts at {}, not {pos}",
+                    range.0
+                )));
+            }
+            *total = range.1;
+            0
+        }
+        StatusCode::OK => {
+            *total = response.body().content_length();
+            pos
+        }
+        status if status.is_client_error() || status.is_server_error() => {
+            return Err(Error::from(ErrorKind::Status(super::error::StatusError {
+                status,
+            })));
+        }
+        status => {
+            return Err(Error::other(format!(
+                "unexpected status {status} for download"
+            )));
+        }
+    };
+    if pos == 0 {
+        *etag = response_etag;
+    } else if etag.is_none() || *etag != response_etag {
+        // A resumed download must be of the same representation.
+        return Err(Error::other("resource changed during download"));
+    }
+    Ok(skip)
+}
+
+/// Copy `body` to `writer`, skipping its first `skip` bytes, and stopping
+/// at `end`, while advancing `pos`.
+async fn copy<W: AsyncWrite>(
+    body: Body,
+    mut skip: u64,
+    end: Option<u64>,
+    pos: &mut u64,
+    writer: &mut W,
+    tracker: &mut Tracker<'_>,
+) -> Result<(), CopyError> {
+    let mut body = body.into_boxed_body();
+    while let Some(frame) = body.frame().await {
+        let Ok(mut data) = frame.map_err(CopyError::Read)?.into_data() else {
+            continue;
+        };
+        let n = skip.min(data.len() as u64);
+        data.advance(n as usize);
+        skip -= n;
+        if let Some(end) = end {
+            // Compare as u64, because the remaining length may not fit in a
+            // usize on wasm32.
+            data.truncate((data.len() as u64).min(end - *pos) as usize);
+        }
+        if !data.is_empty() {
+            writer
+                .write_all(&data)
+                .await
+                .context("writing download")
+                .map_err(CopyError::Write)?;
+            *pos += data.len() as u64;
+            tracker.advance(data.len());
+        }
+        if end.is_some_and(|end| *pos >= end) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The `ETag` of a response, if it is a strong validator, as `If-Range`
+/// requires.
+fn strong_etag(headers: &HeaderMap) -> Option<HeaderValue> {
+    let etag = headers.get(ETAG)?;
+    etag.as_bytes().starts_with(b"\"").then(|| etag.clone())
+}
+
+/// Parse a `Content-Range` header of a `206 Partial Content` response, as
+/// the first byte position and the complete length, if known.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, complete) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last): (u64, u64) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    if last < first {
+        return None;
+    }
+    let complete = match complete.trim() {
+        "*" => None,
+        complete => Some(complete.parse().ok()?),
+    };
+    Some((first, complete))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(
+            parse_content_range("bytes 0-499/1234"),
+            Some((0, Some(1234)))
+        );
+        assert_eq!(parse_content_range("bytes 500-999/*"), Some((500, None)));
+        assert_eq!(parse_content_range("bytes 9-1/10"), None);
+        assert_eq!(parse_content_range("bytes */1234"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn etag_must_be_strong() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(strong_etag(&headers), None);
+        headers.insert(ETAG, HeaderValue::from_static("W/\"abc\""));
+        assert_eq!(strong_etag(&headers), None);
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        assert_eq!(strong_etag(&headers).unwrap(), "\"abc\"");
+    }
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &'static str) -> http::Response<Body> {
+        let mut response = http::Response::builder().status(status);
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        response.body(Body::from(body)).unwrap()
+    }
+
+    #[test]
+    fn resumed_response() {
+        let etag = HeaderValue::from_static("\"v1\"");
+        let mut total = None;
+
+        // A partial response continues where the download stopped.
+        let partial = response(
+            206,
+            &[("etag", "\"v1\""), ("content-range", "bytes 4-9/10")],
+            "456789",
+        );
+        let skip = check_response(&partial, 4, &mut Some(etag.clone()), &mut total).unwrap();
+        assert_eq!((skip, total), (0, Some(10)));
+        assert!(check_response(&partial, 3, &mut Some(etag.clone()), &mut total).is_err());
+
+        // A full response of the same representation is skipped ahead.
+        let full = response(200, &[("etag", "\"v1\"")], "0123456789");
+        let skip = check_response(&full, 4, &mut Some(etag.clone()), &mut total).unwrap();
+        assert_eq!((skip, total), (4, Some(10)));
+
+        // A full response of another representation can't be resumed.
+        let changed = response(200, &[("etag", "\"v2\"")], "abcdefghij");
+        let error = check_response(&changed, 4, &mut Some(etag), &mut total).unwrap_err();
+        assert_eq!(error.to_string(), "resource changed during download");
+
+        // The first response gives the ETag.
+        let mut etag = None;
+        check_response(&changed, 0, &mut etag, &mut total).unwrap();
+        assert_eq!(etag.unwrap(), "\"v2\"");
+
+        let error = check_response(&response(404, &[], ""), 0, &mut None, &mut total).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Status(_)));
+    }
+
+    #[test]
+    fn copy_skips_and_limits() {
+        let (result, written, progress) = crate::runtime::block_on(async {
+            let mut seen = Vec::new();
+            let mut tracker = Tracker {
+                progress: Progress::default(),
+                callback: Some(Box::new(|p: Progress| seen.push(p.downloaded()))),
+            };
+            let body = Body::from_stream(futures_lite::stream::iter(["0123", "4567", "89"]));
+            let mut writer = Cursor::new(Vec::new());
+            let mut pos = 3;
+            let result = copy(body, 3, Some(8), &mut pos, &mut writer, &mut tracker).await;
+            drop(tracker);
+            (result.is_ok() && pos == 8, writer.into_inner(), seen)
+        });
+        assert!(result);
+        assert_eq!(written, b"34567");
+        assert_eq!(progress, [1, 5]);
+    }
+
+    #[test]
+    fn range_requests() {
+        let fetcher = Fetcher {
+            client: Client::new(),
+            parts: Request::get("https://example.com/artifact")
+                .body(())
+                .unwrap()
+                .into_parts()
+                .0,
+            max_resumes: 3,
+        };
+        let etag = HeaderValue::from_static("\"v1\"");
+        let request = fetcher.request(0, None, None);
+        assert!(!request.headers().contains_key(RANGE));
+        let request = fetcher.request(100, None, Some(&etag));
+        assert_eq!(request.headers()[RANGE], "bytes=100-");
+        assert_eq!(request.headers()[IF_RANGE], "\"v1\"");
+        let request = fetcher.request(0, Some(50), None);
+        assert_eq!(request.headers()[RANGE], "bytes=0-49");
+        assert!(!request.headers().contains_key(IF_RANGE));
+    }
+}
@@ -1,13 +1,486 @@
-//! The http portion of wstd uses `anyhow::Error` as its `Error` type.
+//! The http portion of wstd uses [`Error`] as its error type.
 //!
-//! There are various concrete error types
+//! An [`Error`] has an [`ErrorKind`], which tells what went wrong, such as a
+//! DNS failure reported by wasi-http, an invalid header, or a response body
+//! which isn't valid JSON, and which can be matched on:
+//!
+//! ```no_run
+//! use wstd::http::error::{ErrorCode, ErrorKind};
+//! use wstd::http::{Client, Error};
+//!
+//! # async fn run() -> Result<(), Error> {
+//! match Client::new().get("https://example.com").send().await {
+//!     Ok(response) => println!("{}", response.status()),
+//!     Err(e) => match e.kind() {
+//!         ErrorKind::ErrorCode(ErrorCode::DnsError(_)) => println!("no such host"),
+//!         ErrorKind::Timeout => println!("timed out"),
+//!         _ => return Err(e),
+//!     },
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An `Error` may also carry context, added with [`Error::context`] or the
+//! [`Context`] trait, which describes what was being done when it occurred.
+//! The context is its `Display`, while the error it was added to is its
+//! [`source`](std::error::Error::source), so that the whole chain is shown
+//! by `{:#}` or `{:?}`, as with `anyhow`.
 
 pub use crate::http::body::InvalidContentLength;
-pub use anyhow::Context;
+pub use crate::http::redirect::TooManyRedirects;
 pub use http::header::{InvalidHeaderName, InvalidHeaderValue};
 pub use http::method::InvalidMethod;
 pub use wasip2::http::types::{ErrorCode, HeaderError};
 
-pub type Error = anyhow::Error;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// The error type of wstd's http operations.
+///
+/// See the [module documentation](self) for more.
+pub struct Error(Box<Repr>);
+
+enum Repr {
+    Kind(ErrorKind),
+    Context { context: String, source: Error },
+}
+
+/// What went wrong, for an [`Error`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An error reported by wasi-http, such as a DNS failure, a refused
+    /// connection, or a timeout set with the `Client`.
+    ErrorCode(ErrorCode),
+    /// A header name which isn't valid.
+    InvalidHeaderName(InvalidHeaderName),
+    /// A header value which isn't valid.
+    InvalidHeaderValue(InvalidHeaderValue),
+    /// A header which wasi-http rejected, such as a forbidden header.
+    Header(HeaderError),
+    /// A `Content-Length` header which isn't valid.
+    InvalidContentLength(InvalidContentLength),
+    /// A request or response which isn't valid, such as one with an invalid
+    /// uri or method.
+    Http(http::Error),
+    /// An I/O error while reading or writing a body.
+    Body(std::io::Error),
+    /// A body which couldn't be serialized or deserialized as JSON.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// A body or query which couldn't be serialized or deserialized as a
+    /// url-encoded form.
+    #[cfg(feature = "form")]
+    Form(serde_qs::Error),
+    /// A deadline set with wstd passed, such as
+    /// [`RequestBuilder::timeout`](super::RequestBuilder::timeout).
+    Timeout,
+    /// A response with an error status, given by
+    /// [`ResponseExt::error_for_status`](super::ResponseExt::error_for_status).
+    Status(StatusError),
+    /// More redirects than the client's redirect policy allows.
+    TooManyRedirects(TooManyRedirects),
+    /// Any other error.
+    Other(Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    /// Create an `Error` of kind [`ErrorKind::Other`], from an error or a
+    /// message. If `error` is already an `Error`, it is returned as is.
+    pub fn other(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        match error.into().downcast::<Error>() {
+            Ok(error) => *error,
+            Err(error) => ErrorKind::Other(error).into(),
+        }
+    }
+
+    /// Add context to the error, describing what was being done when it
+    /// occurred.
+    pub fn context(self, context: impl fmt::Display) -> Self {
+        Self(Box::new(Repr::Context {
+            context: context.to_string(),
+            source: self,
+        }))
+    }
+
+    /// What went wrong, regardless of any context added to the error.
+    pub fn kind(&self) -> &ErrorKind {
+        let mut error = self;
+        loop {
+            match &*error.0 {
+                Repr::Kind(kind) => return kind,
+                Repr::Context { source, .. } => error = source,
+            }
+        }
+    }
+
+    /// The wasi-http error code, if the error is of kind
+    /// [`ErrorKind::ErrorCode`].
+    pub fn error_code(&self) -> Option<&ErrorCode> {
+        match self.kind() {
+            ErrorKind::ErrorCode(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Whether the error is a timeout: either a deadline set with wstd, or
+    /// one of the timeouts reported by wasi-http.
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self.kind(),
+            ErrorKind::Timeout
+                | ErrorKind::ErrorCode(
+                    ErrorCode::DnsTimeout
+                        | ErrorCode::ConnectionTimeout
+                        | ErrorCode::ConnectionReadTimeout
+                        | ErrorCode::ConnectionWriteTimeout
+                        | ErrorCode::HttpResponseTimeout
+                )
+        )
+    }
+
+    /// Find an error of type `E` in the error's kind or its sources, such as
+    /// an error given to [`Error::other`].
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        let mut next = self.kind().as_error();
+        while let Some(error) = next {
+            if let Some(e) = error.downcast_ref::<E>() {
+                return Some(e);
+            }
+            next = error.source();
+        }
+        None
+    }
+
+    /// The errors of the chain, starting with this one, with each error's
+    /// source following it.
+    fn chain(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+        let first: &(dyn StdError + 'static) = self;
+        std::iter::successors(Some(first), |e| (*e).source())
+    }
+}
+
+impl ErrorKind {
+    /// The error which this kind wraps, if any.
+    fn as_error(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(match self {
+            ErrorKind::ErrorCode(e) => e,
+            ErrorKind::InvalidHeaderName(e) => e,
+            ErrorKind::InvalidHeaderValue(e) => e,
+            ErrorKind::Header(e) => e,
+            ErrorKind::InvalidContentLength(e) => e,
+            ErrorKind::Http(e) => e,
+            ErrorKind::Body(e) => e,
+            #[cfg(feature = "json")]
+            ErrorKind::Json(e) => e,
+            #[cfg(feature = "form")]
+            ErrorKind::Form(e) => e,
+            ErrorKind::Timeout => return None,
+            ErrorKind::Status(e) => e,
+            ErrorKind::TooManyRedirects(e) => e,
+            ErrorKind::Other(e) => &**e,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.0 {
+            Repr::Kind(ErrorKind::Timeout) => f.write_str("operation timed out")?,
+            Repr::Kind(kind) => write!(f, "{}", kind.as_error().unwrap())?,
+            Repr::Context { context, .. } => f.write_str(context)?,
+        }
+        if f.alternate() {
+            for source in self.chain().skip(1) {
+                write!(f, ": {source}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return match &*self.0 {
+                Repr::Kind(kind) => f.debug_tuple("Error").field(kind).finish(),
+                Repr::Context { context, source } => f
+                    .debug_struct("Error")
+                    .field("context", context)
+                    .field("source", source)
+                    .finish(),
+            };
+        }
+        write!(f, "{self}")?;
+        let mut sources = self.chain().skip(1).peekable();
+        if sources.peek().is_some() {
+            f.write_str("\n\nCaused by:")?;
+            for (i, source) in sources.enumerate() {
+                write!(f, "\n    {i}: {source}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &*self.0 {
+            Repr::Kind(kind) => kind.as_error().and_then(|e| e.source()),
+            Repr::Context { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self(Box::new(Repr::Kind(kind)))
+    }
+}
+
+macro_rules! from_kind {
+    ($($(#[$attr:meta])* $variant:ident($ty:ty),)*) => {
+        $(
+            $(#[$attr])*
+            impl From<$ty> for Error {
+                fn from(e: $ty) -> Self {
+                    ErrorKind::$variant(e).into()
+                }
+            }
+        )*
+    };
+}
+
+from_kind! {
+    ErrorCode(ErrorCode),
+    InvalidHeaderName(InvalidHeaderName),
+    InvalidHeaderValue(InvalidHeaderValue),
+    Header(HeaderError),
+    InvalidContentLength(InvalidContentLength),
+    Http(http::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    #[cfg(feature = "form")]
+    Form(serde_qs::Error),
+    Status(StatusError),
+    TooManyRedirects(TooManyRedirects),
+}
+
+impl From<http::uri::InvalidUri> for Error {
+    fn from(e: http::uri::InvalidUri) -> Self {
+        http::Error::from(e).into()
+    }
+}
+
+impl From<http::uri::InvalidUriParts> for Error {
+    fn from(e: http::uri::InvalidUriParts) -> Self {
+        http::Error::from(e).into()
+    }
+}
+
+impl From<InvalidMethod> for Error {
+    fn from(e: InvalidMethod) -> Self {
+        http::Error::from(e).into()
+    }
+}
+
+impl From<std::io::Error> for Error {
+    /// An I/O error of kind [`TimedOut`](std::io::ErrorKind::TimedOut), as
+    /// given by [`FutureExt::timeout`](crate::future::FutureExt::timeout),
+    /// is an [`ErrorKind::Timeout`], and any other is an
+    /// [`ErrorKind::Body`].
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::TimedOut {
+            ErrorKind::Timeout.into()
+        } else {
+            ErrorKind::Body(e).into()
+        }
+    }
+}
+
+impl From<http::header::ToStrError> for Error {
+    fn from(e: http::header::ToStrError) -> Self {
+        Error::other(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Error::other(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::other(e)
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// An `anyhow::Error` which is an `Error` is unwrapped, and any other is
+    /// an [`ErrorKind::Other`], keeping its chain of sources.
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<Error>() {
+            Ok(e) => e,
+            Err(e) => Error::other(e),
+        }
+    }
+}
+
+impl From<&Error> for ErrorCode {
+    /// The error code for a server to respond with when it fails with
+    /// `error`: the error's own code if it has one, and otherwise an
+    /// [`ErrorCode::InternalError`] with the error's description.
+    fn from(error: &Error) -> Self {
+        match error.kind() {
+            ErrorKind::ErrorCode(code) => code.clone(),
+            _ => ErrorCode::InternalError(Some(format!("{error:#}"))),
+        }
+    }
+}
+
+impl From<Error> for ErrorCode {
+    fn from(error: Error) -> Self {
+        ErrorCode::from(&error)
+    }
+}
+
+/// Add context to the error of a `Result`, or to a `None`, converting it
+/// into an [`Error`].
+pub trait Context<T> {
+    /// Add `context` to the error.
+    fn context<C: fmt::Display>(self, context: C) -> Result<T>;
+
+    /// Add the context returned by `f` to the error, calling `f` only if
+    /// there is an error.
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T> {
+        self.ok_or_else(|| Error::other(context.to_string()))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce() -> C>(self, f: F) -> Result<T> {
+        self.ok_or_else(|| Error::other(f().to_string()))
+    }
+}
+
+/// The error given by [`ResponseExt::error_for_status`] for a response with
+/// a client error (4xx) or server error (5xx) status.
+///
+/// [`ResponseExt::error_for_status`]: crate::http::ResponseExt::error_for_status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusError {
+    pub(crate) status: http::StatusCode,
+}
+
+impl StatusError {
+    /// The status of the response.
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.status.is_client_error() {
+            "client error"
+        } else {
+            "server error"
+        };
+        write!(f, "HTTP status {kind} ({})", self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
 /// The `http` result type.
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kind_through_context() {
+        let error = Error::from(ErrorCode::DnsTimeout)
+            .context("connecting")
+            .context("fetching");
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::ErrorCode(ErrorCode::DnsTimeout)
+        ));
+        assert!(error.is_timeout());
+        assert_eq!(error.to_string(), "fetching");
+        assert_eq!(error.source().unwrap().to_string(), "connecting");
+        assert!(format!("{error:#}").starts_with("fetching: connecting: "));
+        assert!(format!("{error:?}").contains("\n\nCaused by:\n    0: connecting\n"));
+    }
+
+    #[test]
+    fn timeout() {
+        let error = Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert!(matches!(error.kind(), ErrorKind::Timeout));
+        assert!(error.is_timeout());
+        let error = Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(matches!(error.kind(), ErrorKind::Body(_)));
+        assert!(!error.is_timeout());
+    }
+
+    #[test]
+    fn into_error_code() {
+        let error = Error::from(ErrorCode::ConnectionRefused).context("sending request");
+        assert!(matches!(
+            ErrorCode::from(&error),
+            ErrorCode::ConnectionRefused
+        ));
+
+        let error = Error::from(StatusError {
+            status: http::StatusCode::NOT_FOUND,
+        })
+        .context("checking status");
+        match ErrorCode::from(error) {
+            ErrorCode::InternalError(Some(message)) => assert_eq!(
+                message,
+                "checking status: HTTP status client error (404 Not Found)"
+            ),
+            code => panic!("unexpected error code {code:?}"),
+        }
+    }
+
+    #[test]
+    fn other() {
+        let error = Error::other("no such thing").context("looking");
+        assert_eq!(format!("{error:#}"), "looking: no such thing");
+        // An `Error` isn't wrapped again, whether directly or through anyhow.
+        let error = Error::other(error);
+        assert_eq!(error.to_string(), "looking");
+        let error = Error::from(anyhow::Error::from(error));
+        assert_eq!(error.to_string(), "looking");
+
+        let error = Error::other(std::io::Error::other("inner"));
+        assert_eq!(
+            error.downcast_ref::<std::io::Error>().unwrap().to_string(),
+            "inner"
+        );
+    }
+
+    #[test]
+    fn option_context() {
+        let error = None::<()>.context("missing value").unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Other(_)));
+        assert_eq!(error.to_string(), "missing value");
+    }
+}
@@ -0,0 +1,482 @@
+//! Streaming JSON bodies, with the `json` feature.
+//!
+//! [`Body::json`] collects the whole body before deserializing it. For large
+//! bodies, such as exports of an API's records, the records can instead be
+//! decoded one at a time as the body arrives: with [`Body::json_lines`] from
+//! newline-delimited JSON, or with [`Body::json_array`] from the elements of
+//! a top-level JSON array. [`Body::from_json_lines`] produces
+//! newline-delimited JSON from a stream of records.
+//!
+//! ```no_run
+//! use wstd::http::{Client, Error, ResponseExt};
+//! use wstd::iter::AsyncIterator;
+//!
+//! #[derive(serde::Deserialize)]
+//! struct Record {
+//!     id: u64,
+//! }
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let response = Client::new()
+//!     .get("https://example.com/export.ndjson")
+//!     .send()
+//!     .await?
+//!     .error_for_status()?;
+//! let mut records = response.into_body().json_lines::<Record>();
+//! while let Some(record) = records.next().await {
+//!     println!("record {}", record?.id);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use super::body::HttpBody;
+use super::{Body, Error, error::Context as _};
+use crate::iter::AsyncIterator;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http_body_util::combinators::UnsyncBoxBody;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::future::poll_fn;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+impl Body {
+    /// Deserialize each line of this body, as newline-delimited JSON, as it
+    /// arrives. Blank lines are skipped, and the last line doesn't need to
+    /// end with a newline.
+    pub fn json_lines<T: DeserializeOwned>(self) -> JsonLines<T> {
+        JsonLines {
+            reader: Reader::new(self),
+            scanned: 0,
+            line: 0,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Deserialize each element of this body, which is a JSON array, as it
+    /// arrives.
+    pub fn json_array<T: DeserializeOwned>(self) -> JsonArray<T> {
+        JsonArray {
+            reader: Reader::new(self),
+            state: ArrayState::Start,
+            scanner: Scanner::default(),
+            index: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Construct a `Body` which sends each record of `records`, serialized
+    /// as JSON, on its own line. The body fails if a record can't be
+    /// serialized.
+    pub fn from_json_lines<S, T>(records: S) -> Self
+    where
+        S: futures_lite::Stream<Item = T> + Send + 'static,
+        T: serde::Serialize,
+    {
+        use futures_lite::StreamExt;
+        Body::from_try_stream(records.map(|record| {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(line)
+        }))
+    }
+}
+
+/// The bytes of a body which have arrived, and not yet been decoded.
+struct Reader {
+    body: UnsyncBoxBody<Bytes, Error>,
+    buf: BytesMut,
+    eof: bool,
+}
+
+impl Reader {
+    fn new(body: Body) -> Self {
+        Self {
+            body: body.into_boxed_body(),
+            buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    /// Read more of the body into `buf`, or set `eof` at its end.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            match std::task::ready!(Pin::new(&mut self.body).poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data()
+                        && !data.is_empty()
+                    {
+                        self.buf.extend_from_slice(&data);
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.eof = true;
+                    return Poll::Ready(Err(e));
+                }
+                None => {
+                    self.eof = true;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+/// The records of a newline-delimited JSON body, created by
+/// [`Body::json_lines`].
+pub struct JsonLines<T> {
+    reader: Reader,
+    /// How much of the buffer is known not to contain a newline.
+    scanned: usize,
+    line: usize,
+    done: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> JsonLines<T> {
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, Error>>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let newline = self.reader.buf[self.scanned..]
+                .iter()
+                .position(|b| *b == b'\n');
+            let line = match newline {
+                Some(i) => {
+                    let line = self.reader.buf.split_to(self.scanned + i + 1);
+                    self.scanned = 0;
+                    line
+                }
+                None if self.reader.eof => {
+                    self.done = true;
+                    self.reader.buf.split()
+                }
+                None => {
+                    self.scanned = self.reader.buf.len();
+                    if let Err(e) = std::task::ready!(self.reader.poll_fill(cx)) {
+                        self.done = true;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    continue;
+                }
+            };
+            self.line += 1;
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            let record = serde_json::from_slice(line)
+                .with_context(|| format!("decoding json line {}", self.line));
+            return Poll::Ready(Some(record));
+        }
+    }
+}
+
+impl<T: DeserializeOwned> AsyncIterator for JsonLines<T> {
+    type Item = Result<T, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        poll_fn(|cx| self.poll_record(cx)).await
+    }
+}
+
+impl<T: DeserializeOwned> futures_lite::Stream for JsonLines<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_record(cx)
+    }
+}
+
+impl<T> fmt::Debug for JsonLines<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLines")
+            .field("line", &self.line)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The elements of a body which is a JSON array, created by
+/// [`Body::json_array`].
+///
+/// Only the elements of the top-level array are decoded incrementally: each
+/// element is buffered until it is complete, and then deserialized.
+pub struct JsonArray<T> {
+    reader: Reader,
+    state: ArrayState,
+    scanner: Scanner,
+    index: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArrayState {
+    /// Before the opening `[`.
+    Start,
+    /// After the opening `[`, where the array may be empty.
+    Open,
+    /// Before an element, after a `,`.
+    Element,
+    /// In an element, which the scanner is finding the end of.
+    InElement,
+    /// After an element, before a `,` or the closing `]`.
+    AfterElement,
+    /// After the closing `]`, where only whitespace may follow.
+    End,
+    /// Done, after the end of the body or an error.
+    Done,
+}
+
+impl<T: DeserializeOwned> JsonArray<T> {
+    fn poll_element(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, Error>>> {
+        loop {
+            let result = match self.state {
+                ArrayState::Done => return Poll::Ready(None),
+                ArrayState::InElement => match self.scanner.scan(&self.reader.buf) {
+                    Some(end) => {
+                        let element = self.reader.buf.split_to(end);
+                        self.scanner = Scanner::default();
+                        self.state = ArrayState::AfterElement;
+                        self.index += 1;
+                        let element = serde_json::from_slice(&element).with_context(|| {
+                            format!("decoding json array element {}", self.index - 1)
+                        });
+                        return Poll::Ready(Some(element));
+                    }
+                    None => Ok(false),
+                },
+                state => self.punctuation(state),
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) if self.reader.eof => {
+                    let state = std::mem::replace(&mut self.state, ArrayState::Done);
+                    if state == ArrayState::End {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Err(Error::other("unexpected end of json array"))));
+                }
+                Ok(false) => {
+                    if let Err(e) = std::task::ready!(self.reader.poll_fill(cx)) {
+                        self.state = ArrayState::Done;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                Err(e) => {
+                    self.state = ArrayState::Done;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+
+    /// Consume whitespace and the punctuation expected in `state`. Returns
+    /// whether progress was made, or false if more of the body is needed.
+    fn punctuation(&mut self, state: ArrayState) -> Result<bool, Error> {
+        let buf = &mut self.reader.buf;
+        let whitespace = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        buf.advance(whitespace);
+        let Some(&next) = buf.first() else {
+            return Ok(false);
+        };
+        self.state = match (state, next) {
+            (ArrayState::Start, b'[') => ArrayState::Open,
+            (ArrayState::Start, _) => return Err(Error::other("expected a json array")),
+            (ArrayState::Open, b']') | (ArrayState::AfterElement, b']') => ArrayState::End,
+            (ArrayState::AfterElement, b',') => ArrayState::Element,
+            (ArrayState::Open | ArrayState::Element, b']' | b',') => {
+                return Err(Error::other("expected a json array element"));
+            }
+            (ArrayState::Open | ArrayState::Element, _) => {
+                // The element begins here, so don't consume it.
+                self.state = ArrayState::InElement;
+                return Ok(true);
+            }
+            (ArrayState::AfterElement, _) => {
+                return Err(Error::other("expected `,` or `]` after json array element"));
+            }
+            (ArrayState::End, _) => {
+                return Err(Error::other("trailing characters after json array"));
+            }
+            (ArrayState::InElement | ArrayState::Done, _) => unreachable!(),
+        };
+        buf.advance(1);
+        Ok(true)
+    }
+}
+
+impl<T: DeserializeOwned> AsyncIterator for JsonArray<T> {
+    type Item = Result<T, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        poll_fn(|cx| self.poll_element(cx)).await
+    }
+}
+
+impl<T: DeserializeOwned> futures_lite::Stream for JsonArray<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_element(cx)
+    }
+}
+
+impl<T> fmt::Debug for JsonArray<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonArray")
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Finds the end of a JSON value, which may arrive in several pieces, by
+/// tracking strings and the nesting of objects and arrays. The value itself
+/// is validated when it is deserialized.
+#[derive(Debug, Default)]
+struct Scanner {
+    /// How much of the value has been scanned.
+    pos: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Scanner {
+    /// Continue scanning the value at the start of `buf`, returning its
+    /// length once its end is found.
+    fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        while self.pos < buf.len() {
+            let b = buf[self.pos];
+            self.pos += 1;
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if b == b'\\' {
+                    self.escaped = true;
+                } else if b == b'"' {
+                    self.in_string = false;
+                    if self.depth == 0 {
+                        return Some(self.pos);
+                    }
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        return Some(self.pos);
+                    }
+                }
+                // The end of a number or literal, which is not part of it.
+                b',' | b']' | b'}' if self.depth == 0 => {
+                    self.pos -= 1;
+                    return Some(self.pos);
+                }
+                b if b.is_ascii_whitespace() && self.depth == 0 => {
+                    self.pos -= 1;
+                    return Some(self.pos);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::body::chunked;
+    use crate::runtime::block_on;
+
+    fn collect<T, I: AsyncIterator<Item = Result<T, Error>>>(mut iter: I) -> Vec<Result<T, Error>> {
+        block_on(async move {
+            let mut items = Vec::new();
+            while let Some(item) = iter.next().await {
+                items.push(item);
+            }
+            items
+        })
+    }
+
+    #[test]
+    fn json_lines() {
+        let input = "{\"id\":1}\r\n\n  {\"id\":2, \"note\":\"a\\nb\"}\n{\"id\":3}";
+        for size in [1, 4, input.len()] {
+            let records: Vec<serde_json::Value> = collect(chunked(input, size).json_lines())
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(
+                records,
+                [
+                    serde_json::json!({"id": 1}),
+                    serde_json::json!({"id": 2, "note": "a\nb"}),
+                    serde_json::json!({"id": 3}),
+                ],
+                "chunks of {size}"
+            );
+        }
+        let records = collect(chunked("1\nnope\n3\n", 3).json_lines::<u32>());
+        assert_eq!(records.len(), 3);
+        let err = records[1].as_ref().unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn from_json_lines() {
+        let records = futures_lite::stream::iter([1, 2, 3]);
+        let mut body = Body::from_json_lines(records);
+        let contents = block_on(async move { body.str_contents().await.unwrap().to_owned() });
+        assert_eq!(contents, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn json_array() {
+        let input = " [ {\"a\":[1,{\"b\":\"]\\\"\"}]}, 12 ,\"x,y\",true,null,[],-1.5e3 ] \n";
+        for size in [1, 2, 7, input.len()] {
+            let elements: Vec<serde_json::Value> = collect(chunked(input, size).json_array())
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(
+                elements,
+                [
+                    serde_json::json!({"a": [1, {"b": "]\""}]}),
+                    serde_json::json!(12),
+                    serde_json::json!("x,y"),
+                    serde_json::json!(true),
+                    serde_json::json!(null),
+                    serde_json::json!([]),
+                    serde_json::json!(-1.5e3),
+                ],
+                "chunks of {size}"
+            );
+        }
+        assert!(collect(chunked("[]", 1).json_array::<u32>()).is_empty());
+    }
+
+    #[test]
+    fn json_array_errors() {
+        for (input, message) in [
+            ("{}", "expected a json array"),
+            ("[1,]", "expected a json array element"),
+            ("[1 2]", "expected `,` or `]`"),
+            ("[1,2", "unexpected end"),
+            ("[1] x", "trailing characters"),
+        ] {
+            let items = collect(chunked(input, 2).json_array::<u32>());
+            let err = items.last().unwrap().as_ref().unwrap_err();
+            assert!(err.to_string().contains(message), "{input}: {err}");
+        }
+    }
+}
@@ -0,0 +1,889 @@
+//! Client middleware, built on the `tower` `Service` and `Layer` traits.
+//!
+//! With the `tower` feature enabled, [`Client`] implements
+//! `tower_service::Service<Request<Body>>`, so it can be wrapped in layers
+//! from the tower ecosystem, as well as the layers in this module:
+//!
+//! * [`DefaultHeadersLayer`] adds headers which a request doesn't already
+//!   have.
+//! * [`BaseUriLayer`] resolves requests with a relative uri, such as
+//!   `/users`, against a base uri.
+//! * [`TimeoutLayer`] fails requests which don't complete within a
+//!   duration.
+//! * [`LogLayer`] reports the method, uri, outcome and duration of each
+//!   request.
+//! * [`ConcurrencyLimitLayer`] limits the number of requests in flight at
+//!   once.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use tower_layer::Layer;
+//! use tower_service::Service;
+//! use wstd::http::middleware::{BaseUriLayer, ConcurrencyLimitLayer, TimeoutLayer};
+//! use wstd::http::{Body, Client, Error, Request, Uri};
+//! use wstd::time::Duration;
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let client = Client::new();
+//! let client = TimeoutLayer::new(Duration::from_secs(5)).layer(client);
+//! let client = ConcurrencyLimitLayer::new(4).layer(client);
+//! let mut client =
+//!     BaseUriLayer::new(Uri::from_static("https://api.example.com/v1")).layer(client);
+//!
+//! let request = Request::get("/users").body(Body::empty())?;
+//! std::future::poll_fn(|cx| client.poll_ready(cx)).await?;
+//! let response = client.call(request).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{
+    Body, Client, Error, HeaderMap, Method, Request, Response, StatusCode, Uri, error::Context as _,
+};
+use crate::time::{Duration, Instant};
+
+use pin_project_lite::pin_project;
+use slab::Slab;
+use std::fmt;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tower_layer::Layer;
+use tower_service::Service;
+
+impl Service<Request<Body>> for Client {
+    type Response = Response<Body>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> ResponseFuture {
+        let client = self.clone();
+        ResponseFuture(Box::pin(async move { client.send(request).await }))
+    }
+}
+
+/// The future returned by [`Client`]'s `Service` implementation.
+#[must_use = "futures do nothing unless polled or .awaited"]
+pub struct ResponseFuture(Pin<Box<dyn Future<Output = Result<Response<Body>, Error>> + Send>>);
+
+impl Future for ResponseFuture {
+    type Output = Result<Response<Body>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish_non_exhaustive()
+    }
+}
+
+/// A layer which adds default headers to requests. See [`DefaultHeaders`].
+#[derive(Debug, Clone)]
+pub struct DefaultHeadersLayer {
+    headers: HeaderMap,
+}
+
+impl DefaultHeadersLayer {
+    /// Add each of `headers` to requests which don't have a header with
+    /// the same name.
+    pub fn new(headers: HeaderMap) -> Self {
+        Self { headers }
+    }
+}
+
+impl<S> Layer<S> for DefaultHeadersLayer {
+    type Service = DefaultHeaders<S>;
+
+    fn layer(&self, inner: S) -> DefaultHeaders<S> {
+        DefaultHeaders {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+/// A service which adds default headers to requests, unless the request
+/// already has a header with the same name.
+#[derive(Debug, Clone)]
+pub struct DefaultHeaders<S> {
+    inner: S,
+    headers: HeaderMap,
+}
+
+impl<S, B> Service<Request<B>> for DefaultHeaders<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> S::Future {
+        let headers = request.headers_mut();
+        for name in self.headers.keys() {
+            if !headers.contains_key(name) {
+                for value in self.headers.get_all(name) {
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+        self.inner.call(request)
+    }
+}
+
+/// A layer which resolves relative request uris against a base uri. See
+/// [`BaseUri`].
+#[derive(Debug, Clone)]
+pub struct BaseUriLayer {
+    base: Uri,
+}
+
+impl BaseUriLayer {
+    /// Resolve relative request uris against `base`, which should have a
+    /// scheme and authority.
+    pub fn new(base: Uri) -> Self {
+        Self { base }
+    }
+}
+
+impl<S> Layer<S> for BaseUriLayer {
+    type Service = BaseUri<S>;
+
+    fn layer(&self, inner: S) -> BaseUri<S> {
+        BaseUri {
+            inner,
+            base: self.base.clone(),
+        }
+    }
+}
+
+/// A service which resolves relative request uris against a base uri.
+///
+/// A request uri without an authority, such as `/users?page=2`, is given
+/// the scheme and authority of the base uri, and its path is appended to
+/// the base uri's path. With a base of `https://api.example.com/v1`, that
+/// request is sent to `https://api.example.com/v1/users?page=2`. Requests
+/// with an absolute uri are sent unchanged.
+#[derive(Debug, Clone)]
+pub struct BaseUri<S> {
+    inner: S,
+    base: Uri,
+}
+
+impl<S, B> Service<Request<B>> for BaseUri<S>
+where
+    S: Service<Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = BaseUriFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if request.uri().authority().is_some() {
+            return BaseUriFuture::ok(self.inner.call(request));
+        }
+        match join_uri(&self.base, request.uri()) {
+            Ok(uri) => {
+                *request.uri_mut() = uri;
+                BaseUriFuture::ok(self.inner.call(request))
+            }
+            Err(e) => BaseUriFuture::err(e),
+        }
+    }
+}
+
+/// Append the path and query of the relative uri `relative` to `base`.
+fn join_uri(base: &Uri, relative: &Uri) -> Result<Uri, Error> {
+    let base_path = base.path().trim_end_matches('/');
+    let path_and_query = match relative.path_and_query() {
+        Some(pq) if pq.as_str().starts_with('/') => format!("{base_path}{pq}"),
+        Some(pq) => format!("{base_path}/{pq}"),
+        None => format!("{base_path}/"),
+    };
+    let mut parts = base.clone().into_parts();
+    parts.path_and_query = Some(
+        path_and_query
+            .try_into()
+            .context("joining request uri to base uri")?,
+    );
+    Uri::from_parts(parts).context("joining request uri to base uri")
+}
+
+pin_project! {
+    /// The future returned by [`BaseUri`].
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct BaseUriFuture<F> {
+        #[pin]
+        inner: Option<F>,
+        error: Option<Error>,
+    }
+}
+
+impl<F> BaseUriFuture<F> {
+    fn ok(inner: F) -> Self {
+        Self {
+            inner: Some(inner),
+            error: None,
+        }
+    }
+
+    fn err(error: Error) -> Self {
+        Self {
+            inner: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl<F, T, E> Future for BaseUriFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<Error>,
+{
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        match this.inner.as_pin_mut() {
+            Some(inner) => inner.poll(cx).map_err(Into::into),
+            None => Poll::Ready(Err(this
+                .error
+                .take()
+                .expect("future polled after completing"))),
+        }
+    }
+}
+
+/// A layer which applies a timeout to requests. See [`Timeout`].
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    /// Fail requests which don't complete within `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Timeout<S> {
+        Timeout {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// A service which fails requests with a timeout error if the inner
+/// service doesn't respond within a duration.
+///
+/// For a [`Client`], this limits the time until the response head is
+/// received. The timeout error can be recognized with
+/// [`Error::is_timeout`].
+#[derive(Debug, Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S, R> Service<R> for Timeout<S>
+where
+    S: Service<R>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = TimeoutFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        TimeoutFuture {
+            inner: crate::future::FutureExt::timeout(self.inner.call(request), self.timeout),
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`Timeout`].
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct TimeoutFuture<F> {
+        #[pin]
+        inner: crate::future::Timeout<F, <Duration as IntoFuture>::IntoFuture>,
+    }
+}
+
+impl<F, T, E> Future for TimeoutFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<Error>,
+{
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().inner.poll(cx).map(|result| match result {
+            Ok(result) => result.map_err(Into::into),
+            Err(e) => Err(Error::from(e)),
+        })
+    }
+}
+
+type Logger = Arc<dyn Fn(&Record<'_>) + Send + Sync>;
+
+/// A layer which logs requests. See [`Log`].
+#[derive(Clone)]
+pub struct LogLayer {
+    logger: Logger,
+}
+
+impl LogLayer {
+    /// Log each request to stderr, as a line such as
+    /// `GET https://example.com/ -> 200 OK (42ms)`.
+    pub fn new() -> Self {
+        Self::with(|record| eprintln!("{record}"))
+    }
+
+    /// Log each request by calling `f` with a [`Record`] of it.
+    pub fn with(f: impl Fn(&Record<'_>) + Send + Sync + 'static) -> Self {
+        Self {
+            logger: Arc::new(f),
+        }
+    }
+}
+
+impl Default for LogLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for LogLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for LogLayer {
+    type Service = Log<S>;
+
+    fn layer(&self, inner: S) -> Log<S> {
+        Log {
+            inner,
+            logger: self.logger.clone(),
+        }
+    }
+}
+
+/// A service which logs the method, uri, outcome and duration of each
+/// request, once the inner service has responded.
+#[derive(Clone)]
+pub struct Log<S> {
+    inner: S,
+    logger: Logger,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Log<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Log")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, B, ResBody> Service<Request<B>> for Log<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Error: fmt::Display,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = LogFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        LogFuture {
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            start: Instant::now(),
+            logger: self.logger.clone(),
+            inner: self.inner.call(request),
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`Log`].
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct LogFuture<F> {
+        #[pin]
+        inner: F,
+        method: Method,
+        uri: Uri,
+        start: Instant,
+        logger: Logger,
+    }
+}
+
+impl<F, ResBody, E> Future for LogFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    E: fmt::Display,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = std::task::ready!(this.inner.poll(cx));
+        let outcome = match &result {
+            Ok(response) => Ok(response.status()),
+            Err(e) => Err(e as &dyn fmt::Display),
+        };
+        (this.logger)(&Record {
+            method: this.method,
+            uri: this.uri,
+            outcome,
+            elapsed: this.start.elapsed(),
+        });
+        Poll::Ready(result)
+    }
+}
+
+/// A record of a request, given to the function of a [`LogLayer`].
+///
+/// The `Display` implementation formats the record as a single line.
+pub struct Record<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    outcome: Result<StatusCode, &'a dyn fmt::Display>,
+    elapsed: Duration,
+}
+
+impl<'a> Record<'a> {
+    /// The method of the request.
+    pub fn method(&self) -> &'a Method {
+        self.method
+    }
+
+    /// The uri of the request.
+    pub fn uri(&self) -> &'a Uri {
+        self.uri
+    }
+
+    /// The status of the response, if the request succeeded.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.outcome.ok()
+    }
+
+    /// The error, if the request failed.
+    pub fn error(&self) -> Option<&'a dyn fmt::Display> {
+        self.outcome.err()
+    }
+
+    /// The time from sending the request to receiving the response head,
+    /// or the error.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elapsed = std::time::Duration::from(self.elapsed);
+        match self.outcome {
+            Ok(status) => write!(f, "{} {} -> {status}", self.method, self.uri)?,
+            Err(e) => write!(f, "{} {} -> error: {e}", self.method, self.uri)?,
+        }
+        write!(f, " ({}ms)", elapsed.as_millis())
+    }
+}
+
+impl fmt::Debug for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Record")
+            .field("method", self.method)
+            .field("uri", self.uri)
+            .field("status", &self.status())
+            .field("error", &self.error().map(|e| e.to_string()))
+            .field("elapsed", &self.elapsed)
+            .finish()
+    }
+}
+
+/// A layer which limits the number of requests in flight. See
+/// [`ConcurrencyLimit`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConcurrencyLimitLayer {
+    max: usize,
+}
+
+impl ConcurrencyLimitLayer {
+    /// Allow at most `max` requests in flight at once.
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> ConcurrencyLimit<S> {
+        ConcurrencyLimit::new(inner, self.max)
+    }
+}
+
+/// A service which limits the number of requests in flight at once.
+///
+/// Clones of the service share the limit. `poll_ready` waits until fewer
+/// than the maximum number of requests are in flight, and reserves a slot
+/// for the next call. The slot is released when the response future
+/// completes or is dropped.
+#[derive(Debug)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    semaphore: Arc<Semaphore>,
+    permit: Option<Permit>,
+    /// Set while `poll_ready` is waiting for a slot.
+    waiter: Option<usize>,
+}
+
+impl<S> ConcurrencyLimit<S> {
+    /// Allow at most `max` requests to `inner` in flight at once.
+    pub fn new(inner: S, max: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Arc::new(Semaphore {
+                state: Mutex::new(SemaphoreState {
+                    available: max,
+                    waiters: Slab::new(),
+                }),
+            }),
+            permit: None,
+            waiter: None,
+        }
+    }
+}
+
+impl<S> Drop for ConcurrencyLimit<S> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            self.semaphore.cancel(waiter);
+        }
+    }
+}
+
+impl<S: Clone> Clone for ConcurrencyLimit<S> {
+    fn clone(&self) -> Self {
+        // The clone doesn't share this service's reserved slot.
+        Self {
+            inner: self.inner.clone(),
+            semaphore: self.semaphore.clone(),
+            permit: None,
+            waiter: None,
+        }
+    }
+}
+
+impl<S, R> Service<R> for ConcurrencyLimit<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ConcurrencyLimitFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        if self.permit.is_none() {
+            self.permit = Some(std::task::ready!(Semaphore::poll_acquire(
+                &self.semaphore,
+                &mut self.waiter,
+                cx
+            )));
+        }
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let permit = self
+            .permit
+            .take()
+            .expect("ConcurrencyLimit called before poll_ready");
+        ConcurrencyLimitFuture {
+            inner: self.inner.call(request),
+            _permit: permit,
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`ConcurrencyLimit`].
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct ConcurrencyLimitFuture<F> {
+        #[pin]
+        inner: F,
+        _permit: Permit,
+    }
+}
+
+impl<F: Future> Future for ConcurrencyLimitFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().inner.poll(cx)
+    }
+}
+
+#[derive(Debug)]
+struct Semaphore {
+    state: Mutex<SemaphoreState>,
+}
+
+#[derive(Debug)]
+struct SemaphoreState {
+    available: usize,
+    /// One entry for each `ConcurrencyLimit` waiting for a slot: its waker,
+    /// or `None` once it has been woken to take a released slot.
+    waiters: Slab<Option<Waker>>,
+}
+
+impl SemaphoreState {
+    /// Take the waker of one waiter which has not already been woken.
+    fn notify_one(&mut self) -> Option<Waker> {
+        self.waiters.iter_mut().find_map(|(_, waker)| waker.take())
+    }
+}
+
+impl Semaphore {
+    /// Acquire a slot, or register `cx`'s waker in the slot named by
+    /// `waiter`, which is created on the first call and reused by later
+    /// calls.
+    fn poll_acquire(
+        this: &Arc<Self>,
+        waiter: &mut Option<usize>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Permit> {
+        let mut state = this.state.lock().unwrap();
+        if state.available > 0 {
+            state.available -= 1;
+            if let Some(key) = waiter.take() {
+                state.waiters.remove(key);
+            }
+            return Poll::Ready(Permit(this.clone()));
+        }
+        match *waiter {
+            Some(key) => match &mut state.waiters[key] {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                slot => *slot = Some(cx.waker().clone()),
+            },
+            None => *waiter = Some(state.waiters.insert(Some(cx.waker().clone()))),
+        }
+        Poll::Pending
+    }
+
+    /// Stop waiting for a slot. If the waiter had already been woken to take
+    /// a released slot, another waiter is woken in its place.
+    fn cancel(&self, waiter: usize) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            let woken = state.waiters.remove(waiter).is_none();
+            if woken && state.available > 0 {
+                state.notify_one()
+            } else {
+                None
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A slot in a [`Semaphore`], released on drop.
+#[derive(Debug)]
+struct Permit(Arc<Semaphore>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.0.state.lock().unwrap();
+            state.available += 1;
+            state.notify_one()
+        };
+        // Each released slot wakes a single waiter.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::runtime::CountingWaker;
+    use std::future::{Ready, ready};
+
+    /// A service which responds to each request with an empty response,
+    /// after recording the request.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Request<()>>>>);
+
+    impl Service<Request<()>> for Recorder {
+        type Response = Response<()>;
+        type Error = Error;
+        type Future = Ready<Result<Response<()>, Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            self.0.lock().unwrap().push(request);
+            ready(Ok(Response::new(())))
+        }
+    }
+
+    impl Recorder {
+        fn last(&self) -> Request<()> {
+            self.0.lock().unwrap().pop().unwrap()
+        }
+    }
+
+    #[test]
+    fn default_headers() {
+        let recorder = Recorder::default();
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", "wstd".parse().unwrap());
+        headers.append("accept", "text/html".parse().unwrap());
+        headers.append("accept", "text/plain".parse().unwrap());
+        let mut service = DefaultHeadersLayer::new(headers).layer(recorder.clone());
+
+        let request = Request::builder()
+            .header("user-agent", "custom")
+            .body(())
+            .unwrap();
+        futures_lite::future::block_on(service.call(request)).unwrap();
+        let request = recorder.last();
+        assert_eq!(request.headers()["user-agent"], "custom");
+        assert_eq!(request.headers().get_all("accept").iter().count(), 2);
+    }
+
+    #[test]
+    fn base_uri() {
+        let base = Uri::from_static("https://api.example.com/v1/");
+        let join = |uri| join_uri(&base, &Uri::from_static(uri)).unwrap().to_string();
+        assert_eq!(
+            join("/users?page=2"),
+            "https://api.example.com/v1/users?page=2"
+        );
+        assert_eq!(join("/"), "https://api.example.com/v1/");
+
+        let root = Uri::from_static("https://api.example.com");
+        assert_eq!(
+            join_uri(&root, &Uri::from_static("/users")).unwrap(),
+            "https://api.example.com/users"
+        );
+
+        let recorder = Recorder::default();
+        let mut service = BaseUriLayer::new(base).layer(recorder.clone());
+        let request = Request::get("https://other.example.com/x")
+            .body(())
+            .unwrap();
+        futures_lite::future::block_on(service.call(request)).unwrap();
+        assert_eq!(recorder.last().uri(), "https://other.example.com/x");
+    }
+
+    #[test]
+    fn log_record() {
+        let method = Method::GET;
+        let uri = Uri::from_static("https://example.com/");
+        let record = Record {
+            method: &method,
+            uri: &uri,
+            outcome: Ok(StatusCode::OK),
+            elapsed: Duration::from_millis(42),
+        };
+        assert_eq!(
+            record.to_string(),
+            "GET https://example.com/ -> 200 OK (42ms)"
+        );
+    }
+
+    #[test]
+    fn concurrency_limit() {
+        let mut a = ConcurrencyLimitLayer::new(1).layer(Recorder::default());
+        let mut b = a.clone();
+        let waker = Waker::noop();
+        let mut cx = Context::from_waker(waker);
+
+        assert!(a.poll_ready(&mut cx).is_ready());
+        let response = a.call(Request::new(()));
+        assert!(b.poll_ready(&mut cx).is_pending());
+        drop(response);
+        assert!(b.poll_ready(&mut cx).is_ready());
+        assert!(a.poll_ready(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn concurrency_limit_wakes_one_waiter() {
+        let mut a = ConcurrencyLimitLayer::new(1).layer(Recorder::default());
+        let mut b = a.clone();
+        let mut c = a.clone();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(a.poll_ready(&mut cx).is_ready());
+        let response = a.call(Request::new(()));
+
+        let (b_count, c_count) = (CountingWaker::new(), CountingWaker::new());
+        let (b_waker, c_waker) = (Waker::from(b_count.clone()), Waker::from(c_count.clone()));
+        // Polling repeatedly reuses the waiter's slot.
+        for _ in 0..3 {
+            assert!(
+                b.poll_ready(&mut Context::from_waker(&b_waker))
+                    .is_pending()
+            );
+            assert!(
+                c.poll_ready(&mut Context::from_waker(&c_waker))
+                    .is_pending()
+            );
+        }
+        assert_eq!(a.semaphore.state.lock().unwrap().waiters.len(), 2);
+
+        drop(response);
+        assert_eq!(b_count.count() + c_count.count(), 1);
+
+        // A woken waiter which goes away passes the slot on.
+        let (woken_service, waiting_count) = if b_count.count() == 1 {
+            (b, &c_count)
+        } else {
+            (c, &b_count)
+        };
+        drop(woken_service);
+        assert_eq!(waiting_count.count(), 1);
+        assert_eq!(a.semaphore.state.lock().unwrap().waiters.len(), 1);
+    }
+}
@@ -10,16 +10,44 @@ pub use error::{Error, ErrorCode, Result};
 pub use fields::{HeaderMap, HeaderName, HeaderValue};
 pub use method::Method;
 pub use request::Request;
-pub use response::Response;
+#[cfg(feature = "form")]
+pub use request::RequestExt;
+pub use request_builder::RequestBuilder;
+pub use response::{Response, ResponseExt};
 pub use scheme::{InvalidUri, Scheme};
+#[cfg(feature = "form")]
+pub use uri::UriExt;
 
 pub mod body;
+pub mod cache;
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "brotli",
+    feature = "zstd"
+))]
+pub mod compression;
+pub mod cookie;
+pub mod deadline;
+pub mod download;
+#[cfg(feature = "tower")]
+pub mod middleware;
+pub mod multipart;
+pub mod redirect;
+pub mod retry;
+pub mod sse;
 
 mod client;
+mod date;
 pub mod error;
 mod fields;
+#[cfg(feature = "json")]
+pub mod json;
 mod method;
 pub mod request;
+mod request_builder;
 pub mod response;
 mod scheme;
 pub mod server;
+#[cfg(feature = "form")]
+mod uri;
@@ -0,0 +1,886 @@
+//! `multipart/form-data` bodies, per RFC 7578.
+//!
+//! A [`Form`] builds a multipart body from [`Part`]s, which stream as the
+//! body is sent, so a large file doesn't need to be held in memory:
+//!
+//! ```no_run
+//! use wstd::http::multipart::{Form, Part};
+//! use wstd::http::{Client, Error};
+//! use wstd::io::AsyncInputStream;
+//!
+//! # async fn run(file: AsyncInputStream) -> Result<(), Error> {
+//! let form = Form::new()
+//!     .text("key", "uploads/report.csv")
+//!     .part(
+//!         "file",
+//!         Part::stream(file)
+//!             .file_name("report.csv")
+//!             .content_type("text/csv")?,
+//!     );
+//! Client::new()
+//!     .post("https://storage.example.com/bucket")
+//!     .multipart(form)
+//!     .send()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A [`Multipart`] parses a multipart body into [`Field`]s as it streams,
+//! such as a browser upload received by a server. Each field's data is read
+//! in chunks, and any of a field's data which isn't read is skipped when
+//! the next field is requested:
+//!
+//! ```no_run
+//! use wstd::http::multipart::Multipart;
+//! use wstd::http::{Body, Error, Request, Response};
+//! use wstd::iter::AsyncIterator;
+//!
+//! #[wstd::http_server]
+//! async fn main(request: Request<Body>) -> Result<Response<Body>, Error> {
+//!     let mut multipart = Multipart::from_request(request)?;
+//!     let mut total = 0;
+//!     while let Some(field) = multipart.next().await {
+//!         let mut field = field?;
+//!         println!("field {:?}, file {:?}", field.name(), field.file_name());
+//!         while let Some(chunk) = field.next().await {
+//!             total += chunk?.len();
+//!         }
+//!     }
+//!     Ok(Response::new(format!("received {total} bytes\n").into()))
+//! }
+//! ```
+
+use super::body::{Frame, HttpBody, SizeHint};
+use super::{Body, Error, HeaderMap, HeaderName, HeaderValue, Request, error::Context as _};
+use crate::iter::AsyncIterator;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use http_body_util::combinators::UnsyncBoxBody;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// A `multipart/form-data` body, built from named [`Part`]s.
+pub struct Form {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl Form {
+    /// Create an empty form, with a random boundary.
+    pub fn new() -> Self {
+        let mut random = [0; 16];
+        crate::rand::get_insecure_random_bytes(&mut random);
+        let hex: String = random.iter().map(|b| format!("{b:02x}")).collect();
+        Self::with_boundary(format!("wstd-boundary-{hex}"))
+    }
+
+    /// Create an empty form with the given boundary, which must not occur
+    /// in the data of any part.
+    pub fn with_boundary(boundary: impl Into<String>) -> Self {
+        Self {
+            boundary: boundary.into(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// The boundary between the parts of the form.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Add a text field.
+    pub fn text(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    /// Add a part.
+    pub fn part(mut self, name: impl Into<String>, part: Part) -> Self {
+        self.parts.push((name.into(), part));
+        self
+    }
+
+    /// The value of the `Content-Type` header for the form.
+    pub fn content_type(&self) -> HeaderValue {
+        HeaderValue::try_from(format!("multipart/form-data; boundary={}", self.boundary))
+            .expect("boundary is a valid header value")
+    }
+
+    /// The length of the form's body, if the length of every part is known.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut length = self.closing_delimiter().len() as u64;
+        for (name, part) in &self.parts {
+            length += self.part_head(name, part).len() as u64;
+            length += part.body.content_length()?;
+            length += 2;
+        }
+        Some(length)
+    }
+
+    /// Convert the form into a body, which streams the data of each part.
+    pub fn into_body(self) -> Body {
+        let content_length = self.content_length();
+        let closing = self.closing_delimiter();
+        let heads: Vec<_> = self
+            .parts
+            .iter()
+            .map(|(name, part)| self.part_head(name, part))
+            .collect();
+        let mut segments = VecDeque::new();
+        for (head, (_, part)) in heads.into_iter().zip(self.parts) {
+            segments.push_back(Segment::Bytes(head.into()));
+            segments.push_back(Segment::Body(part.body.into_boxed_body()));
+            segments.push_back(Segment::Bytes(Bytes::from_static(b"\r\n")));
+        }
+        segments.push_back(Segment::Bytes(closing.into()));
+        Body::from_http_body(FormBody {
+            segments,
+            content_length,
+        })
+    }
+
+    /// The delimiter and headers which precede the data of a part.
+    fn part_head(&self, name: &str, part: &Part) -> Vec<u8> {
+        let mut head = format!("--{}\r\n", self.boundary).into_bytes();
+        head.extend_from_slice(b"Content-Disposition: form-data; name=\"");
+        head.extend_from_slice(escape_quoted(name).as_bytes());
+        head.push(b'"');
+        if let Some(file_name) = &part.file_name {
+            head.extend_from_slice(b"; filename=\"");
+            head.extend_from_slice(escape_quoted(file_name).as_bytes());
+            head.push(b'"');
+        }
+        head.extend_from_slice(b"\r\n");
+        for (name, value) in &part.headers {
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+        head.extend_from_slice(b"\r\n");
+        head
+    }
+
+    fn closing_delimiter(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Form")
+            .field("boundary", &self.boundary)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+/// Escape a name for a quoted Content-Disposition parameter, as browsers
+/// do, per the HTML standard.
+fn escape_quoted(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// A part of a [`Form`].
+pub struct Part {
+    body: Body,
+    file_name: Option<String>,
+    headers: HeaderMap,
+}
+
+impl Part {
+    /// A part with the given bytes as its data.
+    pub fn bytes(data: impl Into<Bytes>) -> Self {
+        Self::body(Body::from(data.into()))
+    }
+
+    /// A part with the given text as its data.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::body(Body::from(text.into()))
+    }
+
+    /// A part with `data` serialized as json as its data, and a
+    /// `Content-Type` of `application/json`.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(data: &T) -> Result<Self, Error> {
+        let data = serde_json::to_vec(data).context("serializing part as json")?;
+        let mut part = Self::bytes(data);
+        part.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        Ok(part)
+    }
+
+    /// A part which streams its data from `stream`, such as a file.
+    pub fn stream(stream: crate::io::AsyncInputStream) -> Self {
+        Self::body(Body::from(stream))
+    }
+
+    /// A part with the given body as its data, which is streamed.
+    pub fn body(body: impl Into<Body>) -> Self {
+        Self {
+            body: body.into(),
+            file_name: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Set the file name of the part.
+    pub fn file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Set the `Content-Type` header of the part.
+    pub fn content_type(self, content_type: &str) -> Result<Self, Error> {
+        let value = HeaderValue::try_from(content_type).context("part content type")?;
+        Ok(self.header(CONTENT_TYPE, value))
+    }
+
+    /// Set a header of the part. The `Content-Disposition` header is set by
+    /// the [`Form`].
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+impl fmt::Debug for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("file_name", &self.file_name)
+            .field("headers", &self.headers)
+            .field("content_length", &self.body.content_length())
+            .finish()
+    }
+}
+
+enum Segment {
+    Bytes(Bytes),
+    Body(UnsyncBoxBody<Bytes, Error>),
+}
+
+/// The body of a [`Form`]: its delimiters and headers, and the data of each
+/// part in turn.
+struct FormBody {
+    segments: VecDeque<Segment>,
+    content_length: Option<u64>,
+}
+
+impl HttpBody for FormBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        loop {
+            match self.segments.front_mut() {
+                None => return Poll::Ready(None),
+                Some(Segment::Bytes(bytes)) => {
+                    let bytes = std::mem::take(bytes);
+                    self.segments.pop_front();
+                    return Poll::Ready(Some(Ok(Frame::data(bytes))));
+                }
+                Some(Segment::Body(body)) => {
+                    match std::task::ready!(Pin::new(body).poll_frame(cx)) {
+                        Some(Ok(frame)) => {
+                            // The trailers of a part's body are dropped, as
+                            // parts can't have trailers.
+                            if let Ok(data) = frame.into_data()
+                                && !data.is_empty()
+                            {
+                                return Poll::Ready(Some(Ok(Frame::data(data))));
+                            }
+                        }
+                        Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                        None => {
+                            self.segments.pop_front();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.content_length {
+            Some(length) => SizeHint::with_exact(length),
+            None => SizeHint::default(),
+        }
+    }
+}
+
+/// A parser of a `multipart/form-data` body, which yields its [`Field`]s.
+pub struct Multipart {
+    inner: Rc<RefCell<Parser>>,
+}
+
+impl Multipart {
+    /// Parse `body`, with the given boundary.
+    pub fn new(body: Body, boundary: impl AsRef<str>) -> Self {
+        // Every delimiter is preceded by CRLF, except the first, so add one
+        // to the start of the body.
+        let mut buf = BytesMut::from(&b"\r\n"[..]);
+        buf.reserve(8 * 1024);
+        Self {
+            inner: Rc::new(RefCell::new(Parser {
+                body: body.into_boxed_body(),
+                delimiter: format!("\r\n--{}", boundary.as_ref()).into_bytes(),
+                buf,
+                eof: false,
+                state: State::Preamble,
+                field: 0,
+            })),
+        }
+    }
+
+    /// Parse the body of `request`, with the boundary from its
+    /// `Content-Type` header, which must be a `multipart` type.
+    pub fn from_request(request: Request<Body>) -> Result<Self, Error> {
+        let boundary = boundary(request.headers())?;
+        Ok(Self::new(request.into_body(), boundary))
+    }
+
+    /// The next field of the body, if there is one.
+    pub async fn next_field(&mut self) -> Result<Option<Field>, Error> {
+        let head = poll_fn(|cx| self.inner.borrow_mut().poll_next_field(cx)).await?;
+        Ok(head.map(|head| Field {
+            head,
+            inner: self.inner.clone(),
+        }))
+    }
+}
+
+impl AsyncIterator for Multipart {
+    type Item = Result<Field, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        self.next_field().await.transpose()
+    }
+}
+
+impl fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart").finish_non_exhaustive()
+    }
+}
+
+/// The boundary of a multipart body, from the `Content-Type` header.
+fn boundary(headers: &HeaderMap) -> Result<String, Error> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .context("missing content-type header")?
+        .to_str()
+        .context("content-type header")?;
+    let (mime, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    let mime = mime.trim();
+    if !mime
+        .get(..10)
+        .is_some_and(|m| m.eq_ignore_ascii_case("multipart/"))
+    {
+        return Err(Error::other(format!(
+            "content-type {mime} is not multipart"
+        )));
+    }
+    let boundary = parse_params(params)
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .context("missing multipart boundary")?;
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(Error::other("invalid multipart boundary"));
+    }
+    Ok(boundary)
+}
+
+/// Parse `; name=value` parameters, where values may be quoted strings.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+        let name: String =
+            std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ';')).collect();
+        if name.is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                value = std::iter::from_fn(|| chars.next_if(|c| *c != ';')).collect();
+            }
+        }
+        params.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+    params
+}
+
+/// Decode an RFC 8187 extended parameter value, such as
+/// `UTF-8''na%C3%AFve.txt`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let (charset, rest) = value.split_once('\'')?;
+    let (_language, encoded) = rest.split_once('\'')?;
+    if !charset.eq_ignore_ascii_case("utf-8") {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut iter = encoded.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// A field of a [`Multipart`] body.
+///
+/// The field's data is read in chunks, with [`Field::chunk`] or as an
+/// [`AsyncIterator`], or all at once with [`Field::bytes`] or
+/// [`Field::text`]. Once the next field of the body has been requested,
+/// a field has no more data.
+pub struct Field {
+    head: FieldHead,
+    inner: Rc<RefCell<Parser>>,
+}
+
+struct FieldHead {
+    index: usize,
+    name: Option<String>,
+    file_name: Option<String>,
+    headers: HeaderMap,
+}
+
+impl Field {
+    /// The name of the field, from its `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.head.name.as_deref()
+    }
+
+    /// The file name of the field, from its `Content-Disposition` header.
+    pub fn file_name(&self) -> Option<&str> {
+        self.head.file_name.as_deref()
+    }
+
+    /// The `Content-Type` of the field, if it has one.
+    pub fn content_type(&self) -> Option<&str> {
+        self.head
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// The headers of the field.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.head.headers
+    }
+
+    /// The next chunk of the field's data, if there is any more.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        let index = self.head.index;
+        poll_fn(|cx| self.inner.borrow_mut().poll_chunk(cx, index)).await
+    }
+
+    /// The rest of the field's data.
+    pub async fn bytes(mut self) -> Result<Bytes, Error> {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+
+    /// The rest of the field's data, as text.
+    pub async fn text(self) -> Result<String, Error> {
+        let data = self.bytes().await?;
+        String::from_utf8(data.into()).context("field is not valid utf-8")
+    }
+
+    /// The rest of the field's data, deserialized from json.
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, Error> {
+        let data = self.bytes().await?;
+        serde_json::from_slice(&data).context("parsing field as json")
+    }
+}
+
+impl AsyncIterator for Field {
+    type Item = Result<Bytes, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        self.chunk().await.transpose()
+    }
+}
+
+impl fmt::Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Field")
+            .field("name", &self.head.name)
+            .field("file_name", &self.head.file_name)
+            .field("headers", &self.head.headers)
+            .finish()
+    }
+}
+
+/// The most bytes of headers which a part may have.
+const MAX_HEADERS: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the first delimiter.
+    Preamble,
+    /// After a delimiter, which is followed by CRLF, or `--` if it is the
+    /// last.
+    Delimiter,
+    /// In the headers of a part.
+    Headers,
+    /// In the data of a part.
+    Data,
+    /// After the last delimiter.
+    Done,
+}
+
+struct Parser {
+    body: UnsyncBoxBody<Bytes, Error>,
+    delimiter: Vec<u8>,
+    buf: BytesMut,
+    eof: bool,
+    state: State,
+    /// The index of the current field.
+    field: usize,
+}
+
+impl Parser {
+    /// Read more of the body into `buf`. Fails at the end of the body,
+    /// which should end with the last delimiter.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            if self.eof {
+                return Poll::Ready(Err(Error::other("unexpected end of multipart body")));
+            }
+            match std::task::ready!(Pin::new(&mut self.body).poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data()
+                        && !data.is_empty()
+                    {
+                        self.buf.extend_from_slice(&data);
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => self.eof = true,
+            }
+        }
+    }
+
+    fn poll_next_field(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<FieldHead>, Error>> {
+        loop {
+            match self.state {
+                State::Preamble => match find(&self.buf, &self.delimiter) {
+                    Some(i) => {
+                        self.buf.advance(i + self.delimiter.len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        let keep = self.delimiter.len() - 1;
+                        if self.buf.len() > keep {
+                            self.buf.advance(self.buf.len() - keep);
+                        }
+                        std::task::ready!(self.poll_fill(cx))?;
+                    }
+                },
+                State::Delimiter => {
+                    // Skip transport padding.
+                    let padding = self
+                        .buf
+                        .iter()
+                        .take_while(|b| **b == b' ' || **b == b'\t')
+                        .count();
+                    self.buf.advance(padding);
+                    if self.buf.len() < 2 {
+                        std::task::ready!(self.poll_fill(cx))?;
+                        continue;
+                    }
+                    if self.buf.starts_with(b"--") {
+                        self.state = State::Done;
+                    } else if self.buf.starts_with(b"\r\n") {
+                        self.buf.advance(2);
+                        self.state = State::Headers;
+                    } else {
+                        return Poll::Ready(Err(Error::other("invalid multipart delimiter")));
+                    }
+                }
+                State::Headers => {
+                    let end = if self.buf.starts_with(b"\r\n") {
+                        Some(0)
+                    } else {
+                        find(&self.buf, b"\r\n\r\n").map(|i| i + 2)
+                    };
+                    let Some(end) = end else {
+                        if self.buf.len() > MAX_HEADERS {
+                            return Poll::Ready(Err(Error::other(
+                                "multipart headers are too large",
+                            )));
+                        }
+                        std::task::ready!(self.poll_fill(cx))?;
+                        continue;
+                    };
+                    let headers = parse_headers(&self.buf[..end])?;
+                    self.buf.advance(end + 2);
+                    self.state = State::Data;
+                    self.field += 1;
+                    return Poll::Ready(Ok(Some(field_head(self.field, headers))));
+                }
+                State::Data => {
+                    // Skip the rest of the previous field's data.
+                    let field = self.field;
+                    while std::task::ready!(self.poll_chunk(cx, field))?.is_some() {}
+                }
+                State::Done => return Poll::Ready(Ok(None)),
+            }
+        }
+    }
+
+    fn poll_chunk(
+        &mut self,
+        cx: &mut Context<'_>,
+        field: usize,
+    ) -> Poll<Result<Option<Bytes>, Error>> {
+        if field != self.field || self.state != State::Data {
+            return Poll::Ready(Ok(None));
+        }
+        loop {
+            match find(&self.buf, &self.delimiter) {
+                Some(i) => {
+                    if i > 0 {
+                        return Poll::Ready(Ok(Some(self.buf.split_to(i).freeze())));
+                    }
+                    self.buf.advance(self.delimiter.len());
+                    self.state = State::Delimiter;
+                    return Poll::Ready(Ok(None));
+                }
+                None => {
+                    // Keep enough bytes to find a delimiter which has only
+                    // partly arrived.
+                    let keep = self.delimiter.len() - 1;
+                    if self.buf.len() > keep {
+                        let len = self.buf.len() - keep;
+                        return Poll::Ready(Ok(Some(self.buf.split_to(len).freeze())));
+                    }
+                    std::task::ready!(self.poll_fill(cx))?;
+                }
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parse the CRLF-terminated header lines of a part.
+fn parse_headers(bytes: &[u8]) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    for line in bytes.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        let colon = line
+            .iter()
+            .position(|b| *b == b':')
+            .context("invalid multipart header")?;
+        let name = HeaderName::from_bytes(&line[..colon]).context("multipart header name")?;
+        let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii())
+            .context("multipart header value")?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn field_head(index: usize, headers: HeaderMap) -> FieldHead {
+    let mut name = None;
+    let mut file_name = None;
+    let mut file_name_ext = None;
+    if let Some(disposition) = headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+    {
+        let params = disposition.split_once(';').map_or("", |(_, p)| p);
+        for (key, value) in parse_params(params) {
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename") {
+                file_name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename*") {
+                file_name_ext = decode_ext_value(&value);
+            }
+        }
+    }
+    FieldHead {
+        index,
+        name,
+        file_name: file_name_ext.or(file_name),
+        headers,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::body::chunked;
+    use crate::runtime::block_on;
+
+    fn form() -> Form {
+        Form::with_boundary("XyZ")
+            .text("title", "Quarterly \"numbers\"")
+            .part(
+                "file",
+                Part::bytes(&b"a,b\r\n1,2\r\n--Xy\r\n-- XyZ\r\n"[..])
+                    .file_name("data.csv")
+                    .content_type("text/csv")
+                    .unwrap(),
+            )
+            .part("empty", Part::text(""))
+    }
+
+    #[test]
+    fn builds_form() {
+        let form = form();
+        let expected = "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Quarterly \"numbers\"\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"data.csv\"\r\n\
+            content-type: text/csv\r\n\r\n\
+            a,b\r\n1,2\r\n--Xy\r\n-- XyZ\r\n\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"empty\"\r\n\r\n\
+            \r\n\
+            --XyZ--\r\n";
+        assert_eq!(form.content_type(), "multipart/form-data; boundary=XyZ");
+        assert_eq!(form.content_length(), Some(expected.len() as u64));
+        let mut body = form.into_body();
+        let contents = block_on(async move { body.str_contents().await.unwrap().to_owned() });
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn parses_form() {
+        let mut body = form().into_body();
+        let data = block_on(async move { body.bytes_contents().await.unwrap() });
+        for size in [1, 3, 7, 64, data.len()] {
+            let mut multipart = Multipart::new(chunked(&data, size), "XyZ");
+            let fields = block_on(async {
+                let mut fields = Vec::new();
+                while let Some(field) = multipart.next().await {
+                    let field = field.unwrap();
+                    let name = field.name().unwrap().to_owned();
+                    let file_name = field.file_name().map(str::to_owned);
+                    let content_type = field.content_type().map(str::to_owned);
+                    let text = field.text().await.unwrap();
+                    fields.push((name, file_name, content_type, text));
+                }
+                fields
+            });
+            let field = |name: &str, file: Option<&str>, ct: Option<&str>, text: &str| {
+                (
+                    name.to_owned(),
+                    file.map(str::to_owned),
+                    ct.map(str::to_owned),
+                    text.to_owned(),
+                )
+            };
+            assert_eq!(
+                fields,
+                vec![
+                    field("title", None, None, "Quarterly \"numbers\""),
+                    field(
+                        "file",
+                        Some("data.csv"),
+                        Some("text/csv"),
+                        "a,b\r\n1,2\r\n--Xy\r\n-- XyZ\r\n"
+                    ),
+                    field("empty", None, None, ""),
+                ],
+                "chunks of {size}"
+            );
+        }
+    }
+
+    #[test]
+    fn skips_unread_fields() {
+        let data = "preamble\r\n--b\r\n\
+            Content-Disposition: form-data; name=a\r\n\r\n\
+            skipped\r\n\
+            --b  \r\n\
+            Content-Disposition: form-data; name=\"b\"; filename*=UTF-8''na%C3%AFve.txt\r\n\r\n\
+            kept\r\n\
+            --b--\r\nepilogue";
+        let mut multipart = Multipart::new(chunked(data.as_bytes(), 5), "b");
+        block_on(async {
+            let mut a = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(a.name(), Some("a"));
+            let b = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(a.chunk().await.unwrap(), None);
+            assert_eq!(b.file_name(), Some("naïve.txt"));
+            assert_eq!(b.text().await.unwrap(), "kept");
+            assert!(multipart.next_field().await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn truncated() {
+        let data = "--b\r\nContent-Disposition: form-data; name=a\r\n\r\ndata";
+        let mut multipart = Multipart::new(chunked(data.as_bytes(), 5), "b");
+        block_on(async {
+            let field = multipart.next_field().await.unwrap().unwrap();
+            let err = field.bytes().await.unwrap_err();
+            assert!(err.to_string().contains("unexpected end"), "{err}");
+        });
+    }
+
+    #[test]
+    fn boundary_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            "Multipart/Form-Data; charset=utf-8; boundary=\"a b\""
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(boundary(&headers).unwrap(), "a b");
+        headers.insert(CONTENT_TYPE, "text/plain".parse().unwrap());
+        assert!(boundary(&headers).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+//! Redirect policies for the HTTP [`Client`].
+//!
+//! By default, a [`Client`] does not follow redirects: a 3xx response is
+//! given to the caller as-is. Use [`Client::set_redirect_policy`] to follow
+//! them, with a [`Policy`].
+//!
+//! When a redirect is followed, the request is rewritten as RFC 9110
+//! specifies:
+//!
+//! * A `303 See Other` response changes the method to `GET`, unless it was
+//!   `HEAD`, and drops the request body.
+//! * A `301 Moved Permanently` or `302 Found` response to a `POST` changes
+//!   the method to `GET`, and drops the request body.
+//! * A `307 Temporary Redirect` or `308 Permanent Redirect` response
+//!   preserves the method and the request body. The body must be sent
+//!   again, so it must be in memory: see [`Body::try_clone`] and
+//!   [`Body::buffered`]. If it is not, the redirect response is given to the
+//!   caller rather than followed.
+//!
+//! When a redirect leads to another origin, the `Authorization`, `Cookie`
+//! and `Proxy-Authorization` headers are removed from the request.
+//!
+//! Every response given by a [`Client`] which followed at least one redirect
+//! carries a [`History`] in its extensions.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::http::{Client, redirect};
+//!
+//! let mut client = Client::new();
+//! client.set_redirect_policy(redirect::Policy::limited(5));
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`Client::set_redirect_policy`]: super::Client::set_redirect_policy
+//! [`Body::try_clone`]: super::Body::try_clone
+//! [`Body::buffered`]: super::Body::buffered
+
+use super::{Body, Client, Error, HeaderMap, Method, Request, Response, StatusCode, Uri};
+use http::header::{
+    AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION,
+    PROXY_AUTHORIZATION, TRANSFER_ENCODING,
+};
+use std::fmt;
+use std::sync::Arc;
+
+/// A policy deciding whether a [`Client`] follows each redirect.
+#[derive(Clone)]
+pub struct Policy(PolicyKind);
+
+#[derive(Clone)]
+enum PolicyKind {
+    None,
+    Limited(usize),
+    Custom(Arc<dyn Fn(Attempt<'_>) -> Action + Send + Sync>),
+}
+
+impl Policy {
+    /// Never follow redirects. This is the default.
+    pub fn none() -> Self {
+        Self(PolicyKind::None)
+    }
+
+    /// Follow up to `max` redirects for each request. Following more fails
+    /// with a [`TooManyRedirects`] error.
+    pub fn limited(max: usize) -> Self {
+        Self(PolicyKind::Limited(max))
+    }
+
+    /// Decide whether to follow each redirect with a closure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wstd::http::redirect::Policy;
+    ///
+    /// // Follow up to 3 redirects, but only within the same host.
+    /// let policy = Policy::custom(|attempt| {
+    ///     if attempt.previous().len() > 3 {
+    ///         attempt.error("too many redirects")
+    ///     } else if attempt.next().host() != attempt.previous()[0].host() {
+    ///         attempt.stop()
+    ///     } else {
+    ///         attempt.follow()
+    ///     }
+    /// });
+    /// ```
+    pub fn custom(f: impl Fn(Attempt<'_>) -> Action + Send + Sync + 'static) -> Self {
+        Self(PolicyKind::Custom(Arc::new(f)))
+    }
+
+    pub(crate) fn is_none(&self) -> bool {
+        matches!(self.0, PolicyKind::None)
+    }
+
+    fn decide(&self, attempt: Attempt<'_>) -> Action {
+        match &self.0 {
+            PolicyKind::None => attempt.stop(),
+            PolicyKind::Limited(max) if attempt.previous.len() > *max => {
+                Action(ActionKind::Error(TooManyRedirects { max: *max }.into()))
+            }
+            PolicyKind::Limited(_) => attempt.follow(),
+            PolicyKind::Custom(f) => f(attempt),
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            PolicyKind::None => f.write_str("Policy::none()"),
+            PolicyKind::Limited(max) => write!(f, "Policy::limited({max})"),
+            PolicyKind::Custom(_) => f.write_str("Policy::custom(..)"),
+        }
+    }
+}
+
+/// A redirect which a [`Policy`] is deciding whether to follow.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    status: StatusCode,
+    next: &'a Uri,
+    previous: &'a [Uri],
+}
+
+impl<'a> Attempt<'a> {
+    /// The status of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The uri the response redirects to.
+    pub fn next(&self) -> &'a Uri {
+        self.next
+    }
+
+    /// The uris which have been requested so far, starting with the
+    /// original request. The last was redirected by this response.
+    pub fn previous(&self) -> &'a [Uri] {
+        self.previous
+    }
+
+    /// Follow the redirect.
+    pub fn follow(self) -> Action {
+        Action(ActionKind::Follow)
+    }
+
+    /// Do not follow the redirect, and give the redirect response to the
+    /// caller.
+    pub fn stop(self) -> Action {
+        Action(ActionKind::Stop)
+    }
+
+    /// Fail the request with the given error.
+    pub fn error(self, error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Action {
+        Action(ActionKind::Error(Error::other(error)))
+    }
+}
+
+/// The decision of a [`Policy`], created by the methods of [`Attempt`].
+#[derive(Debug)]
+pub struct Action(ActionKind);
+
+#[derive(Debug)]
+enum ActionKind {
+    Follow,
+    Stop,
+    Error(Error),
+}
+
+/// The redirects which were followed to give a response. Found in the
+/// response's extensions when at least one redirect was followed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use wstd::http::{Client, Error, redirect};
+///
+/// # async fn run() -> Result<(), Error> {
+/// let mut client = Client::new();
+/// client.set_redirect_policy(redirect::Policy::limited(5));
+/// let response = client.get("http://example.com").send().await?;
+/// if let Some(history) = response.extensions().get::<redirect::History>() {
+///     println!("redirected to {}", history.final_uri());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct History {
+    hops: Vec<Hop>,
+    final_uri: Uri,
+}
+
+impl History {
+    /// Every request which was redirected, in order, starting with the
+    /// original request.
+    pub fn hops(&self) -> &[Hop] {
+        &self.hops
+    }
+
+    /// The uri of the request which gave the final response.
+    pub fn final_uri(&self) -> &Uri {
+        &self.final_uri
+    }
+}
+
+/// A request which was redirected, recorded in a [`History`].
+#[derive(Debug, Clone)]
+pub struct Hop {
+    uri: Uri,
+    status: StatusCode,
+}
+
+impl Hop {
+    /// The uri of the request.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// The status of the redirect response to the request.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// The error given when more redirects are followed than a
+/// [`Policy::limited`] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyRedirects {
+    max: usize,
+}
+
+impl fmt::Display for TooManyRedirects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "too many redirects: followed the maximum of {}",
+            self.max
+        )
+    }
+}
+
+impl std::error::Error for TooManyRedirects {}
+
+/// Send `request` with `client`, following redirects as its policy allows.
+pub(crate) async fn send(client: &Client, request: Request<Body>) -> Result<Response<Body>, Error> {
+    let policy = client.redirect_policy();
+    let (mut parts, mut body) = request.into_parts();
+    let mut previous: Vec<Uri> = Vec::new();
+    let mut hops: Vec<Hop> = Vec::new();
+    loop {
+        // Keep a copy of the body, in case it needs to be sent again.
+        let replay = body.try_clone();
+        let request = Request::from_parts(parts.clone(), body);
+        let response = client.send_once(request).await?;
+
+        let status = response.status();
+        let location = match status {
+            StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT => response.headers().get(LOCATION),
+            _ => None,
+        };
+        let Some(next) = location
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| resolve(&parts.uri, location))
+        else {
+            return Ok(finish(response, hops, parts.uri));
+        };
+
+        let (method, keep_body) = rewrite_method(status, &parts.method);
+        let next_body = if keep_body {
+            match replay {
+                Some(body) => body,
+                // The body can't be sent again, so the redirect can't be
+                // followed.
+                None => return Ok(finish(response, hops, parts.uri)),
+            }
+        } else {
+            Body::empty()
+        };
+
+        previous.push(parts.uri.clone());
+        let attempt = Attempt {
+            status,
+            next: &next,
+            previous: &previous,
+        };
+        match policy.decide(attempt).0 {
+            ActionKind::Follow => {}
+            ActionKind::Stop => return Ok(finish(response, hops, parts.uri)),
+            ActionKind::Error(e) => return Err(e.context(format!("redirect to {next}"))),
+        }
+        drop(response);
+
+        if !keep_body {
+            remove_body_headers(&mut parts.headers);
+        }
+        if !same_origin(&parts.uri, &next) {
+            remove_credentials(&mut parts.headers);
+        }
+        hops.push(Hop {
+            uri: std::mem::replace(&mut parts.uri, next),
+            status,
+        });
+        parts.method = method;
+        body = next_body;
+    }
+}
+
+fn finish(mut response: Response<Body>, hops: Vec<Hop>, final_uri: Uri) -> Response<Body> {
+    if !hops.is_empty() {
+        response
+            .extensions_mut()
+            .insert(History { hops, final_uri });
+    }
+    response
+}
+
+/// The method of the redirected request, and whether it keeps the body, as
+/// specified by RFC 9110 section 15.4.
+fn rewrite_method(status: StatusCode, method: &Method) -> (Method, bool) {
+    match status {
+        StatusCode::SEE_OTHER if *method == Method::HEAD => (Method::HEAD, false),
+        StatusCode::SEE_OTHER => (Method::GET, false),
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND if *method == Method::POST => {
+            (Method::GET, false)
+        }
+        _ => (method.clone(), true),
+    }
+}
+
+fn remove_body_headers(headers: &mut HeaderMap) {
+    for name in [
+        CONTENT_TYPE,
+        CONTENT_LENGTH,
+        CONTENT_ENCODING,
+        TRANSFER_ENCODING,
+    ] {
+        headers.remove(name);
+    }
+}
+
+fn remove_credentials(headers: &mut HeaderMap) {
+    for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+        headers.remove(name);
+    }
+}
+
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme() == b.scheme() && a.host() == b.host() && a.port_u16() == b.port_u16()
+}
+
+/// Resolve a `Location` header value against the uri of the request, as a
+/// uri reference per RFC 3986 section 5.
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    // Fragments are not sent in requests.
+    let location = location.split('#').next().unwrap_or_default();
+    if let Ok(uri) = location.parse::<Uri>()
+        && uri.scheme().is_some()
+    {
+        return Some(uri);
+    }
+    let scheme = base.scheme_str()?;
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{scheme}://{rest}").parse().ok();
+    }
+    let authority = base.authority()?;
+    let path_and_query = if location.starts_with('/') {
+        remove_dot_segments(location)
+    } else if location.starts_with('?') {
+        format!("{}{location}", base.path())
+    } else if location.is_empty() {
+        base.path_and_query()?.to_string()
+    } else {
+        let dir = match base.path().rfind('/') {
+            Some(index) => &base.path()[..=index],
+            None => "/",
+        };
+        remove_dot_segments(&format!("{dir}{location}"))
+    };
+    format!("{scheme}://{authority}{path_and_query}")
+        .parse()
+        .ok()
+}
+
+/// Remove `.` and `..` segments from an absolute path, which may be followed
+/// by a query, as per RFC 3986 section 5.2.4.
+fn remove_dot_segments(path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let mut output: Vec<&str> = Vec::new();
+    let segments = path.split('/').skip(1).collect::<Vec<_>>();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." | ".." => {
+                if *segment == ".." {
+                    output.pop();
+                }
+                // A trailing dot segment still leaves a directory.
+                if last {
+                    output.push("");
+                }
+            }
+            segment => output.push(segment),
+        }
+    }
+    let mut out = String::with_capacity(path_and_query.len());
+    for segment in output {
+        out.push('/');
+        out.push_str(segment);
+    }
+    if out.is_empty() {
+        out.push('/');
+    }
+    if let Some(query) = query {
+        out.push('?');
+        out.push_str(query);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+
+    fn uri(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn resolve_references() {
+        let base = uri("https://example.com/a/b/c?q=1");
+        let cases = [
+            ("https://other.com/x", "https://other.com/x"),
+            ("//other.com/x", "https://other.com/x"),
+            ("/x/y", "https://example.com/x/y"),
+            ("d", "https://example.com/a/b/d"),
+            ("../d?z", "https://example.com/a/d?z"),
+            ("./", "https://example.com/a/b/"),
+            ("?z=2", "https://example.com/a/b/c?z=2"),
+            ("/x#frag", "https://example.com/x"),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(resolve(&base, location).unwrap(), expected, "{location}");
+        }
+    }
+
+    #[test]
+    fn method_rewriting() {
+        use StatusCode as S;
+        assert_eq!(
+            rewrite_method(S::SEE_OTHER, &Method::PUT),
+            (Method::GET, false)
+        );
+        assert_eq!(
+            rewrite_method(S::SEE_OTHER, &Method::HEAD),
+            (Method::HEAD, false)
+        );
+        assert_eq!(
+            rewrite_method(S::FOUND, &Method::POST),
+            (Method::GET, false)
+        );
+        assert_eq!(rewrite_method(S::FOUND, &Method::PUT), (Method::PUT, true));
+        assert_eq!(
+            rewrite_method(S::MOVED_PERMANENTLY, &Method::POST),
+            (Method::GET, false)
+        );
+        assert_eq!(
+            rewrite_method(S::TEMPORARY_REDIRECT, &Method::POST),
+            (Method::POST, true)
+        );
+        assert_eq!(
+            rewrite_method(S::PERMANENT_REDIRECT, &Method::POST),
+            (Method::POST, true)
+        );
+    }
+
+    #[test]
+    fn origins() {
+        let a = uri("https://example.com/a");
+        assert!(same_origin(&a, &uri("https://example.com/b")));
+        assert!(!same_origin(&a, &uri("http://example.com/a")));
+        assert!(!same_origin(&a, &uri("https://example.com:8443/a")));
+        assert!(!same_origin(&a, &uri("https://api.example.com/a")));
+    }
+
+    #[test]
+    fn limited_policy() {
+        let next = uri("https://example.com/");
+        let previous = vec![next.clone(); 3];
+        let attempt = |n| Attempt {
+            status: StatusCode::FOUND,
+            next: &next,
+            previous: &previous[..n],
+        };
+        let policy = Policy::limited(2);
+        assert!(matches!(policy.decide(attempt(2)).0, ActionKind::Follow));
+        match policy.decide(attempt(3)).0 {
+            ActionKind::Error(e) => assert!(e.downcast_ref::<TooManyRedirects>().is_some()),
+            action => panic!("expected error, got {action:?}"),
+        }
+        assert!(matches!(
+            Policy::none().decide(attempt(1)).0,
+            ActionKind::Stop
+        ));
+    }
+
+    #[test]
+    fn body_replay() {
+        let body = Body::from("data");
+        assert!(body.try_clone().is_some());
+        let streaming = Body::from_stream(futures_lite::stream::iter([Bytes::from("data")]));
+        assert!(streaming.try_clone().is_none());
+    }
+}
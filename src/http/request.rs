@@ -11,7 +11,36 @@ use wasip2::http::types::IncomingRequest;
 
 pub use http::request::{Builder, Request};
 
-// TODO: go back and add json stuff???
+/// Extension methods for [`Request`].
+#[cfg(feature = "form")]
+pub trait RequestExt {
+    /// Deserialize the query string of the request's uri. A request without
+    /// a query deserializes from an empty query string.
+    ///
+    /// ```
+    /// use wstd::http::{Request, RequestExt};
+    ///
+    /// #[derive(serde::Deserialize)]
+    /// struct Search {
+    ///     q: String,
+    ///     page: Option<u32>,
+    /// }
+    ///
+    /// let request = Request::get("/search?q=wasi+http").body(()).unwrap();
+    /// let search: Search = request.query().unwrap();
+    /// assert_eq!(search.q, "wasi http");
+    /// assert_eq!(search.page, None);
+    /// ```
+    fn query<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error>;
+}
+
+#[cfg(feature = "form")]
+impl<B> RequestExt for Request<B> {
+    fn query<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        let query = self.uri().query().unwrap_or("");
+        super::body::deserialize_form(query.as_bytes()).context("decoding request query")
+    }
+}
 
 pub(crate) fn try_into_outgoing<T>(request: Request<T>) -> Result<(OutgoingRequest, T), Error> {
     let wasi_req = OutgoingRequest::new(header_map_to_wasi(request.headers())?);
@@ -22,7 +51,7 @@ pub(crate) fn try_into_outgoing<T>(request: Request<T>) -> Result<(OutgoingReque
     let method = to_wasi_method(parts.method);
     wasi_req
         .set_method(&method)
-        .map_err(|()| anyhow::anyhow!("method rejected by wasi-http: {method:?}"))?;
+        .map_err(|()| Error::other(format!("method rejected by wasi-http: {method:?}")))?;
 
     // Set the url scheme
     let scheme = parts
@@ -32,19 +61,21 @@ pub(crate) fn try_into_outgoing<T>(request: Request<T>) -> Result<(OutgoingReque
         .unwrap_or(wasip2::http::types::Scheme::Https);
     wasi_req
         .set_scheme(Some(&scheme))
-        .map_err(|()| anyhow::anyhow!("scheme rejected by wasi-http: {scheme:?}"))?;
+        .map_err(|()| Error::other(format!("scheme rejected by wasi-http: {scheme:?}")))?;
 
     // Set authority
     let authority = parts.uri.authority().map(Authority::as_str);
     wasi_req
         .set_authority(authority)
-        .map_err(|()| anyhow::anyhow!("authority rejected by wasi-http {authority:?}"))?;
+        .map_err(|()| Error::other(format!("authority rejected by wasi-http {authority:?}")))?;
 
     // Set the url path + query string
     if let Some(p_and_q) = parts.uri.path_and_query() {
         wasi_req
             .set_path_with_query(Some(p_and_q.as_str()))
-            .map_err(|()| anyhow::anyhow!("path and query rejected by wasi-http {p_and_q:?}"))?;
+            .map_err(|()| {
+                Error::other(format!("path and query rejected by wasi-http {p_and_q:?}"))
+            })?;
     }
 
     // All done; request is ready for send-off
@@ -107,3 +138,27 @@ pub fn try_from_incoming(incoming: IncomingRequest) -> Result<Request<Body>, Err
     }
     request.body(body).context("building request from wasi")
 }
+
+#[cfg(all(test, feature = "form"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query_with_encoded_brackets() {
+        #[derive(serde::Deserialize)]
+        struct User {
+            name: String,
+            tags: Vec<String>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Query {
+            user: User,
+        }
+        let request = Request::get("/users?user%5Bname%5D=alice&user[tags][]=admin")
+            .body(())
+            .unwrap();
+        let query: Query = request.query().unwrap();
+        assert_eq!(query.user.name, "alice");
+        assert_eq!(query.user.tags, ["admin"]);
+    }
+}
@@ -0,0 +1,392 @@
+use super::{
+    Body, Client, Error, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, Uri,
+    deadline::Deadline, error::Context,
+};
+use crate::time::Duration;
+
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+use std::fmt;
+
+/// A builder for an HTTP request, which is sent with the [`Client`] that
+/// created it.
+///
+/// Created by [`Client::request`], or one of [`Client::get`],
+/// [`Client::post`], etc. Errors while building the request, such as an
+/// invalid header, are deferred until [`RequestBuilder::send`] or
+/// [`RequestBuilder::build`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use wstd::http::{Client, Error, ResponseExt};
+///
+/// # async fn run() -> Result<(), Error> {
+/// let mut response = Client::new()
+///     .get("https://example.com/search")
+///     .header("Accept", "text/html")
+///     .bearer_auth("secret-token")
+///     .send()
+///     .await?
+///     .error_for_status()?;
+/// let html = response.body_mut().str_contents().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[must_use = "a RequestBuilder does nothing until it is sent"]
+pub struct RequestBuilder {
+    client: Client,
+    request: Result<Request<Body>, Error>,
+}
+
+impl RequestBuilder {
+    pub(crate) fn new<U>(client: Client, method: Method, uri: U) -> Self
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .context("building request");
+        Self { client, request }
+    }
+
+    /// Apply `f` to the request, unless building it has already failed.
+    fn and_then(mut self, f: impl FnOnce(&mut Request<Body>) -> Result<(), Error>) -> Self {
+        if let Ok(request) = &mut self.request
+            && let Err(e) = f(request)
+        {
+            self.request = Err(e);
+        }
+        self
+    }
+
+    /// Append a header to the request.
+    pub fn header<K, V>(self, key: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        <K as TryInto<HeaderName>>::Error: Into<http::Error>,
+        V: TryInto<HeaderValue>,
+        <V as TryInto<HeaderValue>>::Error: Into<http::Error>,
+    {
+        self.and_then(|request| {
+            let key = key.try_into().map_err(Into::into).context("header name")?;
+            let value = value
+                .try_into()
+                .map_err(Into::into)
+                .with_context(|| format!("header value for {key}"))?;
+            request.headers_mut().append(key, value);
+            Ok(())
+        })
+    }
+
+    /// Append all of the given headers to the request.
+    pub fn headers(self, headers: HeaderMap) -> Self {
+        self.and_then(|request| {
+            request.headers_mut().extend(headers);
+            Ok(())
+        })
+    }
+
+    /// Set the `Authorization` header to the given bearer token.
+    pub fn bearer_auth(self, token: impl fmt::Display) -> Self {
+        self.authorization(format!("Bearer {token}"))
+    }
+
+    /// Set the `Authorization` header for HTTP basic authentication, with
+    /// the given username and optional password.
+    pub fn basic_auth(
+        self,
+        username: impl fmt::Display,
+        password: Option<impl fmt::Display>,
+    ) -> Self {
+        let credentials = match password {
+            Some(password) => format!("{username}:{password}"),
+            None => format!("{username}:"),
+        };
+        self.authorization(format!("Basic {}", base64_encode(credentials.as_bytes())))
+    }
+
+    fn authorization(self, value: String) -> Self {
+        self.and_then(|request| {
+            let mut value = HeaderValue::try_from(value).context("authorization header")?;
+            value.set_sensitive(true);
+            request.headers_mut().insert(AUTHORIZATION, value);
+            Ok(())
+        })
+    }
+
+    /// Set the body of the request.
+    pub fn body(self, body: impl Into<Body>) -> Self {
+        let body = body.into();
+        self.and_then(|request| {
+            *request.body_mut() = body;
+            Ok(())
+        })
+    }
+
+    /// Set the body of the request to `data` serialized as json, and the
+    /// `Content-Type` header to `application/json`, unless it is already set.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(self, data: &T) -> Self {
+        let body = serde_json::to_vec(data).context("serializing request body as json");
+        self.and_then(|request| {
+            *request.body_mut() = body?.into();
+            request
+                .headers_mut()
+                .entry(CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static("application/json"));
+            Ok(())
+        })
+    }
+
+    /// Set the body of the request to a `multipart/form-data` form, and the
+    /// `Content-Type` header to match the form's boundary.
+    pub fn multipart(self, form: super::multipart::Form) -> Self {
+        let content_type = form.content_type();
+        let body = form.into_body();
+        self.and_then(|request| {
+            *request.body_mut() = body;
+            request.headers_mut().insert(CONTENT_TYPE, content_type);
+            Ok(())
+        })
+    }
+
+    /// Set the body of the request to `data` serialized as a url-encoded
+    /// form, and the `Content-Type` header to
+    /// `application/x-www-form-urlencoded`, unless it is already set.
+    #[cfg(feature = "form")]
+    pub fn form<T: serde::Serialize>(self, data: &T) -> Self {
+        let body = Body::from_form(data).context("serializing request body as form");
+        self.and_then(|request| {
+            *request.body_mut() = body?;
+            request
+                .headers_mut()
+                .entry(CONTENT_TYPE)
+                .or_insert(HeaderValue::from_static(
+                    "application/x-www-form-urlencoded",
+                ));
+            Ok(())
+        })
+    }
+
+    /// Append `data`, serialized as a url-encoded query string, to the query
+    /// of the request's uri.
+    #[cfg(feature = "form")]
+    pub fn query<T: serde::Serialize>(self, data: &T) -> Self {
+        self.and_then(|request| {
+            *request.uri_mut() = super::UriExt::with_query(request.uri(), data)?;
+            Ok(())
+        })
+    }
+
+    /// Limit the whole request, including the response body, to `timeout`
+    /// from now. This is [`RequestBuilder::deadline`] with
+    /// [`Deadline::after`].
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Deadline::after(timeout))
+    }
+
+    /// Limit the whole request, including the response body, to `deadline`,
+    /// by inserting it into the request's extensions. See
+    /// [`deadline`](super::deadline) for details.
+    pub fn deadline(self, deadline: Deadline) -> Self {
+        self.and_then(|request| {
+            request.extensions_mut().insert(deadline);
+            Ok(())
+        })
+    }
+
+    /// Build the request, without sending it.
+    pub fn build(self) -> Result<Request<Body>, Error> {
+        self.request
+    }
+
+    /// Send the request with the [`Client`] which created this builder.
+    pub async fn send(self) -> Result<Response<Body>, Error> {
+        self.client.send(self.request?).await
+    }
+}
+
+impl fmt::Debug for RequestBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RequestBuilder");
+        match &self.request {
+            Ok(request) => d
+                .field("method", request.method())
+                .field("uri", request.uri())
+                .field("headers", request.headers()),
+            Err(e) => d.field("error", e),
+        };
+        d.finish()
+    }
+}
+
+impl Client {
+    /// Start building a request with the given method and uri.
+    pub fn request<U>(&self, method: Method, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        RequestBuilder::new(self.clone(), method, uri)
+    }
+
+    /// Start building a `GET` request to the given uri.
+    pub fn get<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::GET, uri)
+    }
+
+    /// Start building a `POST` request to the given uri.
+    pub fn post<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::POST, uri)
+    }
+
+    /// Start building a `PUT` request to the given uri.
+    pub fn put<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::PUT, uri)
+    }
+
+    /// Start building a `PATCH` request to the given uri.
+    pub fn patch<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::PATCH, uri)
+    }
+
+    /// Start building a `DELETE` request to the given uri.
+    pub fn delete<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::DELETE, uri)
+    }
+
+    /// Start building a `HEAD` request to the given uri.
+    pub fn head<U>(&self, uri: U) -> RequestBuilder
+    where
+        U: TryInto<Uri>,
+        <U as TryInto<Uri>>::Error: Into<http::Error>,
+    {
+        self.request(Method::HEAD, uri)
+    }
+}
+
+/// Standard base64 encoding with padding, as used by basic authentication.
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(
+            base64_encode(b"Aladdin:open sesame"),
+            "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn builds_request() {
+        let request = Client::new()
+            .post("https://example.com/upload")
+            .header("X-Custom", "yes")
+            .basic_auth("Aladdin", Some("open sesame"))
+            .body("hello")
+            .build()
+            .unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.headers()["x-custom"], "yes");
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert!(request.headers()[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn timeout_sets_deadline() {
+        crate::runtime::test_runtime::block_on(async {
+            let request = Client::new()
+                .get("https://example.com")
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap();
+            let deadline = request.extensions().get::<Deadline>().unwrap();
+            assert_eq!(deadline.remaining(), Duration::from_secs(5));
+        })
+    }
+
+    #[test]
+    fn deferred_error() {
+        let err = Client::new()
+            .get("https://example.com")
+            .header("bad header", "value")
+            .bearer_auth("token")
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("header name"), "{err}");
+    }
+
+    #[cfg(feature = "form")]
+    #[test]
+    fn query_appends() {
+        #[derive(serde::Serialize)]
+        struct Search<'a> {
+            q: &'a str,
+            page: u32,
+        }
+        let request = Client::new()
+            .get("https://example.com/search?lang=en")
+            .query(&Search {
+                q: "wasi http",
+                page: 2,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.uri(),
+            "https://example.com/search?lang=en&q=wasi+http&page=2"
+        );
+    }
+}
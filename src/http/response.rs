@@ -2,16 +2,42 @@ use http::StatusCode;
 use wasip2::http::types::IncomingResponse;
 
 use crate::http::body::{Body, BodyHint};
-use crate::http::error::Error;
+use crate::http::error::{Error, StatusError};
 use crate::http::fields::{HeaderMap, header_map_from_wasi};
 
 pub use http::response::{Builder, Response};
 
+/// Extension methods for [`Response`].
+pub trait ResponseExt: Sized {
+    /// Fail with a [`StatusError`] if the response's status is a client
+    /// error (4xx) or a server error (5xx).
+    fn error_for_status(self) -> Result<Self, Error>;
+
+    /// Like [`ResponseExt::error_for_status`], but borrowing the response.
+    fn error_for_status_ref(&self) -> Result<&Self, Error>;
+}
+
+impl<B> ResponseExt for Response<B> {
+    fn error_for_status(self) -> Result<Self, Error> {
+        self.error_for_status_ref()?;
+        Ok(self)
+    }
+
+    fn error_for_status_ref(&self) -> Result<&Self, Error> {
+        let status = self.status();
+        if status.is_client_error() || status.is_server_error() {
+            Err(StatusError { status }.into())
+        } else {
+            Ok(self)
+        }
+    }
+}
+
 pub(crate) fn try_from_incoming(incoming: IncomingResponse) -> Result<Response<Body>, Error> {
     let headers: HeaderMap = header_map_from_wasi(incoming.headers())?;
     // TODO: Does WASI guarantee that the incoming status is valid?
     let status = StatusCode::from_u16(incoming.status())
-        .map_err(|err| anyhow::anyhow!("wasi provided invalid status code ({err})"))?;
+        .map_err(|err| Error::other(format!("wasi provided invalid status code ({err})")))?;
 
     let hint = BodyHint::from_headers(&headers)?;
     // `body_stream` is a child of `incoming_body` which means we cannot
@@ -0,0 +1,441 @@
+//! Retry policies for the HTTP [`Client`].
+//!
+//! By default, a [`Client`] sends each request once. Use
+//! [`Client::set_retry_policy`] to retry requests which fail transiently,
+//! with a [`Policy`].
+//!
+//! Between attempts, the client waits with exponential backoff and full
+//! jitter: the delay before the `n`th retry is chosen at random, up to
+//! `initial_backoff * 2^(n-1)`, capped at `max_backoff`. If a response has a
+//! `Retry-After` header, its delay is used instead, unless it is longer than
+//! `max_backoff`, in which case the response is returned without retrying.
+//!
+//! Only requests which can be sent again are retried:
+//!
+//! * The method must be idempotent (`GET`, `HEAD`, `OPTIONS`, `TRACE`, `PUT`
+//!   or `DELETE`), or the request must have an `Idempotency-Key` header,
+//!   unless [`Policy::retry_non_idempotent`] is set.
+//! * The body must be in memory: see [`Body::try_clone`] and
+//!   [`Body::buffered`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::http::{Client, retry};
+//! use wstd::time::Duration;
+//!
+//! let mut client = Client::new();
+//! client.set_retry_policy(
+//!     retry::Policy::new()
+//!         .max_attempts(5)
+//!         .deadline(Duration::from_secs(10)),
+//! );
+//! ```
+//!
+//! [`Client`]: super::Client
+//! [`Client::set_retry_policy`]: super::Client::set_retry_policy
+//! [`Body::try_clone`]: super::Body::try_clone
+//! [`Body::buffered`]: super::Body::buffered
+
+use super::{Body, Client, Error, ErrorCode, Method, Request, Response, StatusCode};
+use crate::future::FutureExt;
+use crate::time::{Duration, Instant};
+
+use http::header::RETRY_AFTER;
+use std::fmt;
+use std::sync::Arc;
+
+type Classifier = Arc<dyn Fn(&Outcome<'_>) -> Classification + Send + Sync>;
+
+/// A policy deciding whether, and when, a [`Client`] retries a
+/// request.
+#[derive(Clone)]
+pub struct Policy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+    retry_non_idempotent: bool,
+    classify: Option<Classifier>,
+}
+
+impl Policy {
+    /// A policy which makes up to 3 attempts, with backoff starting at 100
+    /// milliseconds and capped at 10 seconds, and no deadline.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            deadline: None,
+            retry_non_idempotent: false,
+            classify: None,
+        }
+    }
+
+    /// Never retry. This is the default.
+    pub fn none() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Set the maximum number of attempts, including the first.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the backoff before the first retry, and the most that the
+    /// backoff may grow to. A response which asks, with `Retry-After`, for a
+    /// longer delay than `max` is not retried.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Limit the total time spent on all attempts, and the delays between
+    /// them. When the deadline passes, an attempt in progress fails with a
+    /// timeout error, and no further attempts are made.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set whether requests with non-idempotent methods, such as `POST`,
+    /// are retried. By default, they are not, unless they have an
+    /// `Idempotency-Key` header.
+    pub fn retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    /// Decide whether each outcome is retried with a closure, rather than
+    /// with [`Outcome::classify_default`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wstd::http::StatusCode;
+    /// use wstd::http::retry::{Classification, Policy};
+    ///
+    /// // Also retry when rate limited by a server which uses 420.
+    /// let policy = Policy::new().classify(|outcome| match outcome.status() {
+    ///     Some(status) if status.as_u16() == 420 => Classification::Retry,
+    ///     _ => outcome.classify_default(),
+    /// });
+    /// ```
+    pub fn classify(
+        mut self,
+        f: impl Fn(&Outcome<'_>) -> Classification + Send + Sync + 'static,
+    ) -> Self {
+        self.classify = Some(Arc::new(f));
+        self
+    }
+
+    pub(crate) fn is_none(&self) -> bool {
+        self.max_attempts <= 1
+    }
+
+    fn classify_outcome(&self, outcome: &Outcome<'_>) -> Classification {
+        match &self.classify {
+            Some(f) => f(outcome),
+            None => outcome.classify_default(),
+        }
+    }
+
+    /// The backoff before retry number `retry`, counting from 1, given a
+    /// random number for jitter.
+    fn backoff_for(&self, retry: u32, random: u64) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let max: std::time::Duration = self.max_backoff.into();
+        let ceiling = std::time::Duration::from(self.initial_backoff)
+            .checked_mul(factor)
+            .map_or(max, |backoff| backoff.min(max));
+        let nanos = u64::try_from(ceiling.as_nanos()).unwrap_or(u64::MAX);
+        Duration::from_nanos(random % nanos.saturating_add(1))
+    }
+
+    /// The delay before retry number `retry`, counting from 1, given the
+    /// delay requested by a `Retry-After` header, if any, and a random
+    /// number for jitter. Gives `None` if the requested delay is longer than
+    /// the maximum backoff.
+    fn delay_for(
+        &self,
+        retry: u32,
+        retry_after: Option<Duration>,
+        random: u64,
+    ) -> Option<Duration> {
+        match retry_after {
+            Some(delay) if delay > self.max_backoff => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff_for(retry, random)),
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Policy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("deadline", &self.deadline)
+            .field("retry_non_idempotent", &self.retry_non_idempotent)
+            .field("classify", &self.classify.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Whether an [`Outcome`] should be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// The request failed transiently, and may be retried.
+    Retry,
+    /// The outcome is final, and is given to the caller.
+    Stop,
+}
+
+/// The outcome of an attempt to send a request: either a response, or an
+/// error.
+#[derive(Debug)]
+pub struct Outcome<'a> {
+    method: &'a Method,
+    attempt: u32,
+    result: Result<&'a Response<Body>, &'a Error>,
+}
+
+impl<'a> Outcome<'a> {
+    /// The method of the request.
+    pub fn method(&self) -> &'a Method {
+        self.method
+    }
+
+    /// The number of the attempt, counting from 1.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The response, if one was received.
+    pub fn response(&self) -> Option<&'a Response<Body>> {
+        self.result.ok()
+    }
+
+    /// The status of the response, if one was received.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.response().map(Response::status)
+    }
+
+    /// The error, if the attempt failed.
+    pub fn error(&self) -> Option<&'a Error> {
+        self.result.err()
+    }
+
+    /// The wasi-http `ErrorCode` of the error, if the attempt failed with
+    /// one.
+    pub fn error_code(&self) -> Option<&'a ErrorCode> {
+        self.error().and_then(Error::error_code)
+    }
+
+    /// The default classification: retry on DNS timeouts, connection
+    /// failures and timeouts, and on responses with status `408 Request
+    /// Timeout`, `429 Too Many Requests`, `502 Bad Gateway`, `503 Service
+    /// Unavailable` or `504 Gateway Timeout`.
+    pub fn classify_default(&self) -> Classification {
+        let retry = match self.result {
+            Ok(response) => matches!(
+                response.status(),
+                StatusCode::REQUEST_TIMEOUT
+                    | StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Err(_) => matches!(
+                self.error_code(),
+                Some(
+                    ErrorCode::DnsTimeout
+                        | ErrorCode::ConnectionRefused
+                        | ErrorCode::ConnectionTerminated
+                        | ErrorCode::ConnectionTimeout
+                        | ErrorCode::ConnectionReadTimeout
+                        | ErrorCode::ConnectionWriteTimeout
+                        | ErrorCode::ConnectionLimitReached
+                        | ErrorCode::HttpResponseTimeout
+                )
+            ),
+        };
+        if retry {
+            Classification::Retry
+        } else {
+            Classification::Stop
+        }
+    }
+}
+
+/// Send `request` with `client`, retrying as its policy allows.
+pub(crate) async fn send(client: &Client, request: Request<Body>) -> Result<Response<Body>, Error> {
+    let policy = client.retry_policy();
+    let deadline = policy.deadline.map(|d| Instant::now() + d);
+    let (parts, mut body) = request.into_parts();
+    let retryable = policy.retry_non_idempotent
+        || is_idempotent(&parts.method)
+        || parts.headers.contains_key("idempotency-key");
+
+    let mut attempt = 1;
+    loop {
+        let replay = if retryable { body.try_clone() } else { None };
+        let request = Request::from_parts(parts.clone(), body);
+        let send = client.send_redirects(request);
+        let result = match deadline {
+            Some(deadline) => send.timeout(deadline).await.map_err(Error::from).flatten(),
+            None => send.await,
+        };
+
+        let Some(next_body) = replay else {
+            return result;
+        };
+        if attempt >= policy.max_attempts {
+            return result;
+        }
+        let classification = policy.classify_outcome(&Outcome {
+            method: &parts.method,
+            attempt,
+            result: result.as_ref(),
+        });
+        if classification == Classification::Stop {
+            return result;
+        }
+
+        let retry_after = result
+            .as_ref()
+            .ok()
+            .and_then(|response| response.headers().get(RETRY_AFTER))
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let mut random = [0; 8];
+        crate::rand::get_insecure_random_bytes(&mut random);
+        let Some(delay) = policy.delay_for(attempt, retry_after, u64::from_le_bytes(random)) else {
+            // The server asked for a longer wait than the policy allows.
+            return result;
+        };
+        if let Some(deadline) = deadline
+            && Instant::now() + delay >= deadline
+        {
+            // The next attempt could not start before the deadline.
+            return result;
+        }
+        drop(result);
+        crate::task::sleep(delay).await;
+
+        attempt += 1;
+        body = next_body;
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Parse a `Retry-After` header value, which is either a number of seconds,
+/// or an HTTP-date, into a delay from now.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = super::date::parse_http_date(value)?;
+    let now = std::time::SystemTime::from(crate::time::SystemTime::now());
+    let delay = date.duration_since(now).unwrap_or_default();
+    Some(Duration::from_nanos(
+        u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = Policy::new().backoff(Duration::from_millis(100), Duration::from_secs(1));
+        let max = u64::MAX;
+        assert_eq!(
+            policy.backoff_for(1, max),
+            Duration::from_nanos(max % 100_000_001)
+        );
+        for retry in 1..=40 {
+            let backoff = policy.backoff_for(retry, max);
+            assert!(backoff <= Duration::from_secs(1), "{retry}: {backoff:?}");
+        }
+        assert_eq!(policy.backoff_for(3, 0), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn retry_after_within_max_backoff() {
+        let policy = Policy::new().backoff(Duration::from_millis(100), Duration::from_secs(10));
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(10)), 0),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(policy.delay_for(1, None, 0), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_beyond_max_backoff() {
+        let policy = Policy::new().backoff(Duration::from_millis(100), Duration::from_secs(10));
+        let day = parse_retry_after("86400");
+        assert_eq!(policy.delay_for(1, day, 0), None);
+    }
+
+    #[test]
+    fn default_classification() {
+        let method = Method::GET;
+        let classify = |result| {
+            Outcome {
+                method: &method,
+                attempt: 1,
+                result,
+            }
+            .classify_default()
+        };
+        let unavailable = Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::empty())
+            .unwrap();
+        let not_found = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(classify(Ok(&unavailable)), Classification::Retry);
+        assert_eq!(classify(Ok(&not_found)), Classification::Stop);
+
+        let refused = Error::from(ErrorCode::ConnectionRefused).context("sending request");
+        assert_eq!(classify(Err(&refused)), Classification::Retry);
+        let denied = Error::from(ErrorCode::HttpRequestDenied);
+        assert_eq!(classify(Err(&denied)), Classification::Stop);
+    }
+
+    #[test]
+    fn idempotency() {
+        assert!(is_idempotent(&Method::PUT));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -77,11 +77,8 @@ impl Responder {
 
     /// This is used by the `http_server` macro.
     #[doc(hidden)]
-    pub fn fail(self, err: Error) {
-        let e = match err.downcast_ref::<ErrorCode>() {
-            Some(e) => e.clone(),
-            None => ErrorCode::InternalError(Some(format!("{err:?}"))),
-        };
+    pub fn fail(self, err: impl Into<Error>) {
+        let e = ErrorCode::from(err.into());
         ResponseOutparam::set(self.outparam, Err(e));
     }
 }
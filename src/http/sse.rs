@@ -0,0 +1,767 @@
+//! Server-Sent Events, per the [HTML standard].
+//!
+//! An [`EventStream`] decodes the events of a `text/event-stream` body as it
+//! arrives:
+//!
+//! ```no_run
+//! use wstd::http::sse::EventStream;
+//! use wstd::http::{Client, Error, ResponseExt};
+//! use wstd::iter::AsyncIterator;
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let response = Client::new()
+//!     .get("https://example.com/completions")
+//!     .header("Accept", "text/event-stream")
+//!     .send()
+//!     .await?
+//!     .error_for_status()?;
+//! let mut events = EventStream::new(response.into_body());
+//! while let Some(event) = events.next().await {
+//!     print!("{}", event?.data);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An [`EventSource`] sends a request, and when its stream ends or fails,
+//! reconnects after the delay given by the server's `retry:` field,
+//! resending the last event id in the `Last-Event-ID` header so that the
+//! server can resume the stream:
+//!
+//! ```no_run
+//! use wstd::http::sse::EventSource;
+//! use wstd::http::{Body, Client, Error, Request};
+//! use wstd::iter::AsyncIterator;
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let request = Request::get("https://example.com/changes").body(Body::empty())?;
+//! let mut events = EventSource::new(Client::new(), request)?;
+//! while let Some(event) = events.next().await {
+//!     let event = event?;
+//!     println!("{:?}: {}", event.id, event.data);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! On the server, an [`SseBody`] encodes a stream of events as a
+//! `text/event-stream` response, sending a keep-alive comment whenever the
+//! stream has been idle for a while:
+//!
+//! ```no_run
+//! use wstd::http::sse::{Event, SseBody};
+//! use wstd::http::{Body, Error, Request, Response};
+//! use wstd::time::Duration;
+//!
+//! #[wstd::http_server]
+//! async fn main(_request: Request<Body>) -> Result<Response<Body>, Error> {
+//!     let events = futures_lite::stream::iter(["hello", "world"])
+//!         .map(|word| Event::new(word).event("word"));
+//!     Ok(SseBody::new(events)
+//!         .keep_alive(Duration::from_secs(15))
+//!         .into_response())
+//! }
+//! # use futures_lite::StreamExt;
+//! ```
+//!
+//! [HTML standard]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use super::body::HttpBody;
+use super::{Body, Client, Error, HeaderValue, Request, Response, ResponseExt, StatusCode};
+use crate::iter::AsyncIterator;
+use crate::time::{Duration, Timer};
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+use http_body_util::combinators::UnsyncBoxBody;
+use std::fmt;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The `Last-Event-ID` request header.
+pub const LAST_EVENT_ID: http::HeaderName = http::HeaderName::from_static("last-event-id");
+
+/// An event of a Server-Sent Events stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The last event id of the stream, as of this event. Like a browser's
+    /// `lastEventId`, this is kept from earlier events which set it.
+    pub id: Option<String>,
+    /// The type of the event. An event without a type has the type
+    /// `message`.
+    pub event: Option<String>,
+    /// The data of the event, with the lines of its `data:` fields joined
+    /// by newlines.
+    pub data: String,
+    /// The reconnection time given by a `retry:` field of this event.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    /// An event with the given data.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Set the id of the event.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the type of the event.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Set the reconnection time for the client.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Encode the event as it is sent in a `text/event-stream`. Line breaks
+    /// in the id or type, which can't be sent, are removed, and each line
+    /// of the data is sent as a `data:` field.
+    fn encode(&self) -> Bytes {
+        fn single_line(s: &str) -> String {
+            s.chars()
+                .filter(|c| !matches!(c, '\r' | '\n' | '\0'))
+                .collect()
+        }
+        let mut out = String::new();
+        if let Some(id) = &self.id {
+            out.push_str("id: ");
+            out.push_str(&single_line(id));
+            out.push('\n');
+        }
+        if let Some(event) = &self.event {
+            out.push_str("event: ");
+            out.push_str(&single_line(event));
+            out.push('\n');
+        }
+        if let Some(retry) = self.retry {
+            let millis = std::time::Duration::from(retry).as_millis();
+            out.push_str(&format!("retry: {millis}\n"));
+        }
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        out.into()
+    }
+}
+
+/// A decoder of the events of a `text/event-stream` body.
+///
+/// Events are decoded as the body arrives, and yielded by the
+/// [`AsyncIterator`] or [`futures_lite::Stream`] impl. An incomplete event at
+/// the end of the body is discarded.
+pub struct EventStream {
+    body: UnsyncBoxBody<Bytes, Error>,
+    buf: BytesMut,
+    eof: bool,
+    /// Whether the start of the body, which may have a byte order mark, has
+    /// been seen.
+    started: bool,
+    parser: Parser,
+}
+
+impl EventStream {
+    /// Decode the events of `body`.
+    pub fn new(body: Body) -> Self {
+        Self {
+            body: body.into_boxed_body(),
+            buf: BytesMut::new(),
+            eof: false,
+            started: false,
+            parser: Parser::default(),
+        }
+    }
+
+    /// The last event id of the stream, which is sent in the
+    /// `Last-Event-ID` header to resume the stream.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.parser.last_event_id.as_deref()
+    }
+
+    /// The reconnection time most recently given by the stream.
+    pub fn retry(&self) -> Option<Duration> {
+        self.parser.retry
+    }
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Event, Error>>> {
+        loop {
+            if !self.started {
+                if self.buf.len() < 3 && !self.eof && b"\xEF\xBB\xBF".starts_with(&self.buf) {
+                    if let Err(e) = std::task::ready!(self.poll_fill(cx)) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    continue;
+                }
+                if self.buf.starts_with(b"\xEF\xBB\xBF") {
+                    self.buf.advance(3);
+                }
+                self.started = true;
+            }
+            while let Some(line) = self.next_line() {
+                if let Some(event) = self.parser.line(&line) {
+                    return Poll::Ready(Some(Ok(event)));
+                }
+            }
+            if self.eof {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = std::task::ready!(self.poll_fill(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+
+    /// Read more of the body into `buf`, or set `eof` at its end.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            match std::task::ready!(Pin::new(&mut self.body).poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data()
+                        && !data.is_empty()
+                    {
+                        self.buf.extend_from_slice(&data);
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.eof = true;
+                    return Poll::Ready(Err(e));
+                }
+                None => {
+                    self.eof = true;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+
+    /// Take the next complete line from `buf`, without its line ending,
+    /// which may be CRLF, LF, or CR.
+    fn next_line(&mut self) -> Option<Bytes> {
+        let end = self.buf.iter().position(|b| *b == b'\r' || *b == b'\n')?;
+        let ending = if self.buf[end] == b'\n' {
+            1
+        } else if end + 1 < self.buf.len() {
+            if self.buf[end + 1] == b'\n' { 2 } else { 1 }
+        } else if self.eof {
+            1
+        } else {
+            // A CR at the end of the buffer may be followed by a LF.
+            return None;
+        };
+        let line = self.buf.split_to(end).freeze();
+        self.buf.advance(ending);
+        Some(line)
+    }
+}
+
+impl AsyncIterator for EventStream {
+    type Item = Result<Event, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        poll_fn(|cx| self.poll_event(cx)).await
+    }
+}
+
+impl futures_lite::Stream for EventStream {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_event(cx)
+    }
+}
+
+impl fmt::Debug for EventStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventStream")
+            .field("last_event_id", &self.parser.last_event_id)
+            .field("retry", &self.parser.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The fields of the event being decoded, and the state of the stream.
+#[derive(Default)]
+struct Parser {
+    data: String,
+    event: Option<String>,
+    event_retry: Option<Duration>,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Parser {
+    /// Process a line, returning an event if the line ends one.
+    fn line(&mut self, line: &[u8]) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (&*line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => {
+                self.last_event_id = (!value.is_empty()).then(|| value.to_owned());
+            }
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(millis) = value.parse() {
+                    let retry = Duration::from_millis(millis);
+                    self.retry = Some(retry);
+                    self.event_retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+        let retry = self.event_retry.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+        Some(Event {
+            id: self.last_event_id.clone(),
+            event,
+            data,
+            retry,
+        })
+    }
+}
+
+/// A Server-Sent Events client, which reconnects when its stream ends or
+/// fails.
+///
+/// The stream is requested when the `EventSource` is first polled. When it
+/// ends, or the connection fails, the request is sent again after the
+/// reconnection time, which is 3 seconds unless the server gives another
+/// with a `retry:` field, with the `Last-Event-ID` header set to the last
+/// event id received.
+///
+/// The `EventSource` stops, yielding an error, if a response has a status
+/// other than 200 OK, or is not a `text/event-stream`, or after
+/// [`EventSource::max_reconnects`] consecutive failed reconnections. A
+/// response with the status 204 No Content ends the `EventSource` without
+/// an error, as the server's way to stop it.
+pub struct EventSource {
+    client: Client,
+    parts: http::request::Parts,
+    body: Body,
+    stream: Option<EventStream>,
+    last_event_id: Option<String>,
+    retry: Duration,
+    max_reconnects: Option<u32>,
+    failures: u32,
+    done: bool,
+}
+
+impl EventSource {
+    /// Create an `EventSource` which sends `request` with `client`.
+    ///
+    /// The request's body is sent again on each reconnection, so it must be
+    /// in memory: see [`Body::try_clone`] and [`Body::buffered`].
+    pub fn new(client: Client, request: Request<Body>) -> Result<Self, Error> {
+        let (mut parts, body) = request.into_parts();
+        if body.try_clone().is_none() {
+            return Err(Error::other("event source request body must be in memory"));
+        }
+        parts
+            .headers
+            .insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+        parts
+            .headers
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        Ok(Self {
+            client,
+            parts,
+            body,
+            stream: None,
+            last_event_id: None,
+            retry: Duration::from_secs(3),
+            max_reconnects: None,
+            failures: 0,
+            done: false,
+        })
+    }
+
+    /// Stop after `max` consecutive reconnections which fail to connect. By
+    /// default, an `EventSource` reconnects until it is dropped.
+    pub fn max_reconnects(mut self, max: u32) -> Self {
+        self.max_reconnects = Some(max);
+        self
+    }
+
+    /// The last event id received, which is sent in the `Last-Event-ID`
+    /// header on reconnection.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// Set the last event id, such as one saved from an earlier stream, to
+    /// resume from.
+    pub fn set_last_event_id(&mut self, id: impl Into<String>) {
+        self.last_event_id = Some(id.into());
+    }
+
+    /// The time to wait before reconnecting.
+    pub fn retry(&self) -> Duration {
+        self.retry
+    }
+
+    /// Send the request, with the `Last-Event-ID` header.
+    async fn connect(&mut self) -> Result<Option<EventStream>, Error> {
+        let body = self.body.try_clone().expect("body is in memory");
+        let mut request = Request::from_parts(self.parts.clone(), body);
+        if let Some(id) = &self.last_event_id {
+            let id = HeaderValue::try_from(id.as_str())?;
+            request.headers_mut().insert(LAST_EVENT_ID, id);
+        }
+        let response = self.client.send(request).await?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        if response.status() != StatusCode::OK {
+            return Err(Error::other(format!(
+                "event source response status {}",
+                response.status()
+            )));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !mime.eq_ignore_ascii_case("text/event-stream") {
+            return Err(Error::other(format!(
+                "event source response content-type {content_type:?}"
+            )));
+        }
+        let mut stream = EventStream::new(response.into_body());
+        stream.parser.last_event_id = self.last_event_id.clone();
+        Ok(Some(stream))
+    }
+}
+
+impl AsyncIterator for EventSource {
+    type Item = Result<Event, Error>;
+
+    async fn next(&mut self) -> Option<Self::Item> {
+        let mut reconnect = false;
+        loop {
+            if self.done {
+                return None;
+            }
+            if let Some(stream) = &mut self.stream {
+                let next = stream.next().await;
+                self.last_event_id = stream.last_event_id().map(str::to_owned);
+                if let Some(retry) = stream.retry() {
+                    self.retry = retry;
+                }
+                match next {
+                    Some(Ok(event)) => {
+                        self.failures = 0;
+                        return Some(Ok(event));
+                    }
+                    // The stream ended or failed: reconnect.
+                    Some(Err(_)) | None => {
+                        self.stream = None;
+                        reconnect = true;
+                    }
+                }
+            }
+            if reconnect {
+                Timer::after(self.retry).wait().await;
+            }
+            match self.connect().await {
+                Ok(Some(stream)) => self.stream = Some(stream),
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    // Only failures to connect are retried: a response
+                    // which isn't an event stream is final.
+                    let retryable = e.error_code().is_some();
+                    self.failures += 1;
+                    let exhausted = self.max_reconnects.is_some_and(|max| self.failures > max);
+                    if !retryable || exhausted {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                    reconnect = true;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("method", &self.parts.method)
+            .field("uri", &self.parts.uri)
+            .field("last_event_id", &self.last_event_id)
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A `text/event-stream` body, which sends the events of a stream as they
+/// are produced.
+///
+/// The body is never buffered, and has no `Content-Length`. Each event is
+/// sent as soon as the stream yields it. If [`SseBody::keep_alive`] is set,
+/// a comment is sent whenever the stream hasn't yielded an event for the
+/// interval, so that idle connections aren't closed by proxies.
+pub struct SseBody<S> {
+    events: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> SseBody<S>
+where
+    S: futures_lite::Stream<Item = Event> + Send + 'static,
+{
+    /// A body which sends the events of `events`, ending when it does.
+    pub fn new(events: S) -> Self {
+        Self {
+            events,
+            keep_alive: None,
+        }
+    }
+
+    /// Send a keep-alive comment after each `interval` without an event.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// A `200 OK` response with this body, and the `Content-Type:
+    /// text/event-stream` and `Cache-Control: no-cache` headers.
+    pub fn into_response(self) -> Response<Body> {
+        let mut response = Response::new(Body::from(self));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+impl SseBody<()> {
+    /// A body which sends the events of an [`AsyncIterator`], ending when it
+    /// does.
+    pub fn from_async_iter<I>(events: I) -> SseBody<impl futures_lite::Stream<Item = Event>>
+    where
+        I: AsyncIterator<Item = Event> + 'static,
+    {
+        SseBody {
+            events: futures_lite::stream::unfold(events, |mut events| async move {
+                let event = events.next().await?;
+                Some((event, events))
+            }),
+            keep_alive: None,
+        }
+    }
+}
+
+impl<S> From<SseBody<S>> for Body
+where
+    S: futures_lite::Stream<Item = Event> + Send + 'static,
+{
+    fn from(sse: SseBody<S>) -> Body {
+        use futures_lite::StreamExt;
+        let state = (Box::pin(sse.events), sse.keep_alive);
+        Body::from_stream(futures_lite::stream::unfold(
+            state,
+            |(mut events, keep_alive)| async move {
+                let frame = match keep_alive {
+                    None => events.next().await?.encode(),
+                    Some(interval) => {
+                        // The timer starts afresh for each frame, so a
+                        // comment is only sent once `interval` has passed
+                        // without an event.
+                        let event = async { Some(events.next().await) };
+                        let tick = async {
+                            crate::task::sleep(interval).await;
+                            None
+                        };
+                        match futures_lite::future::or(event, tick).await {
+                            Some(Some(event)) => event.encode(),
+                            Some(None) => return None,
+                            None => Bytes::from_static(b":\n\n"),
+                        }
+                    }
+                };
+                Some((frame, (events, keep_alive)))
+            },
+        ))
+        .flush_when_pending()
+    }
+}
+
+impl<S> fmt::Debug for SseBody<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseBody")
+            .field("keep_alive", &self.keep_alive)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::body::chunked;
+    use crate::runtime::block_on;
+
+    /// Decode `input`, delivered in chunks of `size` bytes.
+    fn decode(input: &str, size: usize) -> Vec<Event> {
+        let mut stream = EventStream::new(chunked(input, size));
+        block_on(async move {
+            let mut events = Vec::new();
+            while let Some(event) = stream.next().await {
+                events.push(event.unwrap());
+            }
+            events
+        })
+    }
+
+    fn event(id: Option<&str>, event: Option<&str>, data: &str) -> Event {
+        Event {
+            id: id.map(str::to_owned),
+            event: event.map(str::to_owned),
+            data: data.to_owned(),
+            retry: None,
+        }
+    }
+
+    #[test]
+    fn decodes_events() {
+        let input = "\u{FEFF}: comment\r\n\
+            data: first\r\n\
+            data:  second line\r\n\r\n\
+            id: 7\revent: update\rdata\r\r\
+            retry: 2500\n\
+            data: {\"token\":\"hi\"}\n\n\
+            data: no id\nid\n\n\
+            event: ignored without data\n\n\
+            data: incomplete";
+        let mut with_retry = event(Some("7"), None, "{\"token\":\"hi\"}");
+        with_retry.retry = Some(Duration::from_millis(2500));
+        let expected = vec![
+            event(None, None, "first\n second line"),
+            event(Some("7"), Some("update"), ""),
+            with_retry,
+            event(None, None, "no id"),
+        ];
+        for size in [1, 2, 5, input.len()] {
+            assert_eq!(decode(input, size), expected, "chunks of {size}");
+        }
+    }
+
+    #[test]
+    fn encodes_events() {
+        let event = Event::new("line one\r\nline two\n")
+            .id("4\n2")
+            .event("update")
+            .retry(Duration::from_millis(1500));
+        assert_eq!(
+            event.encode(),
+            "id: 42\nevent: update\nretry: 1500\ndata: line one\ndata: line two\ndata: \n\n"
+        );
+        // Line breaks in the data are decoded as LF.
+        let decoded = decode(std::str::from_utf8(&event.encode()).unwrap(), 4);
+        let mut expected = event.id("42");
+        expected.data = "line one\nline two\n".to_owned();
+        assert_eq!(decoded, vec![expected]);
+    }
+
+    #[test]
+    fn sse_body_keep_alive() {
+        use futures_lite::StreamExt;
+        let events = futures_lite::stream::iter([0, 25])
+            .then(|secs| async move {
+                crate::task::sleep(Duration::from_secs(secs)).await;
+                Event::new(secs.to_string())
+            })
+            .boxed();
+        let mut body = Body::from(SseBody::new(events).keep_alive(Duration::from_secs(10)));
+        assert_eq!(body.content_length(), None);
+        let contents = crate::runtime::test_runtime::block_on(async move {
+            body.str_contents().await.unwrap().to_owned()
+        });
+        assert_eq!(contents, "data: 0\n\n:\n\n:\n\ndata: 25\n\n");
+    }
+
+    #[test]
+    fn sse_body_from_async_iter() {
+        struct Countdown(u32);
+        impl AsyncIterator for Countdown {
+            type Item = Event;
+            async fn next(&mut self) -> Option<Event> {
+                self.0 = self.0.checked_sub(1)?;
+                Some(Event::new(self.0.to_string()))
+            }
+        }
+        let mut body = Body::from(SseBody::from_async_iter(Countdown(2)));
+        let contents = block_on(async move { body.str_contents().await.unwrap().to_owned() });
+        assert_eq!(contents, "data: 1\n\ndata: 0\n\n");
+    }
+
+    #[test]
+    fn ignores_invalid_fields() {
+        let input = "retry: 1s\nid: a\0b\nunknown: x\ndata: ok\n\n";
+        assert_eq!(decode(input, 3), vec![event(None, None, "ok")]);
+    }
+
+    #[test]
+    fn keep_alive_after_idle_interval() {
+        // Events at 600ms and 1200ms, then the stream ends at 2500ms.
+        let events = futures_lite::stream::unfold(0, |n| async move {
+            let delay = if n < 2 { 600 } else { 1300 };
+            crate::task::sleep(Duration::from_millis(delay)).await;
+            (n < 2).then(|| (Event::new(format!("tick {n}")), n + 1))
+        });
+        let body = SseBody::new(events).keep_alive(Duration::from_secs(1));
+        let contents = crate::runtime::test_runtime::block_on(async move {
+            Body::from(body).str_contents().await.unwrap().to_owned()
+        });
+        // A comment is only sent once a full second passes without an event.
+        let expected = [
+            Event::new("tick 0").encode(),
+            Event::new("tick 1").encode(),
+            Bytes::from_static(b":\n\n"),
+        ]
+        .concat();
+        assert_eq!(contents.as_bytes(), expected);
+    }
+}
@@ -0,0 +1,63 @@
+use super::{Error, Uri, error::Context as _};
+
+/// Extension methods for [`Uri`].
+pub trait UriExt: Sized {
+    /// Append `data`, serialized as a url-encoded query string, to the
+    /// query of the uri.
+    ///
+    /// ```
+    /// use wstd::http::{Uri, UriExt};
+    ///
+    /// #[derive(serde::Serialize)]
+    /// struct Search<'a> {
+    ///     q: &'a str,
+    ///     page: u32,
+    /// }
+    ///
+    /// let uri: Uri = "https://example.com/search?lang=en".parse().unwrap();
+    /// let uri = uri.with_query(&Search { q: "wasi http", page: 2 }).unwrap();
+    /// assert_eq!(uri, "https://example.com/search?lang=en&q=wasi+http&page=2");
+    /// ```
+    fn with_query<T: serde::Serialize>(&self, data: &T) -> Result<Self, Error>;
+}
+
+impl UriExt for Uri {
+    fn with_query<T: serde::Serialize>(&self, data: &T) -> Result<Self, Error> {
+        let query = serde_qs::to_string(data).context("serializing uri query")?;
+        if query.is_empty() {
+            return Ok(self.clone());
+        }
+        let mut parts = self.clone().into_parts();
+        let path_and_query = match &parts.path_and_query {
+            Some(pq) => match pq.query() {
+                Some(existing) if !existing.is_empty() => {
+                    format!("{}?{existing}&{query}", pq.path())
+                }
+                _ => format!("{}?{query}", pq.path()),
+            },
+            None => format!("/?{query}"),
+        };
+        parts.path_and_query = Some(path_and_query.try_into().context("uri query")?);
+        Uri::from_parts(parts).context("uri with query")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn with_query() {
+        use std::collections::BTreeMap;
+        let query = BTreeMap::from([("a", "1"), ("b", "x&y")]);
+        let uri: Uri = "https://example.com".parse().unwrap();
+        assert_eq!(
+            uri.with_query(&query).unwrap(),
+            "https://example.com/?a=1&b=x%26y"
+        );
+        let uri: Uri = "/path?".parse().unwrap();
+        assert_eq!(uri.with_query(&query).unwrap(), "/path?a=1&b=x%26y");
+        let empty = BTreeMap::<&str, &str>::new();
+        assert_eq!(uri.with_query(&empty).unwrap(), "/path?");
+    }
+}
@@ -1,5 +1,5 @@
 use super::{AsyncPollable, AsyncRead, AsyncWrite};
-use crate::runtime::WaitFor;
+use crate::runtime::{WaitFor, budget};
 use std::future::{Future, poll_fn};
 use std::pin::Pin;
 use std::sync::{Mutex, OnceLock};
@@ -27,6 +27,9 @@ impl AsyncInputStream {
         }
     }
     fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Every read is preceded by a readiness check, so this is where the
+        // task's cooperative budget is spent.
+        std::task::ready!(budget::poll_proceed(cx));
         // Lazily initialize the AsyncPollable
         let subscription = self
             .subscription
@@ -250,6 +253,9 @@ impl AsyncOutputStream {
     /// using the debug string provided by the WASI error, or else that the,
     /// indicated by `std::io::ErrorKind::ConnectionReset`.
     pub async fn write(&self, buf: &[u8]) -> std::io::Result<usize> {
+        // A write to a stream with capacity does not wait, so yield if the
+        // task has exhausted its cooperative budget.
+        budget::proceed().await;
         // Loops at most twice.
         loop {
             match self.stream.check_write() {
@@ -305,6 +311,7 @@ impl AsyncOutputStream {
     /// or else that the stream is closed, indicated by
     /// `std::io::ErrorKind::ConnectionReset`.
     pub async fn flush(&self) -> std::io::Result<()> {
+        budget::proceed().await;
         match self.stream.flush() {
             Ok(()) => {
                 self.ready().await;
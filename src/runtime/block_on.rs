@@ -1,34 +1,88 @@
-use super::{REACTOR, Reactor};
+use super::{REACTOR, Reactor, Snapshot};
+use crate::task::{Instrumented, Locals, TaskId, TaskInfo};
 
+use std::fmt;
 use std::future::Future;
-use std::pin::pin;
+use std::pin::Pin;
 use std::task::{Context, Poll, Waker};
 
 /// Start the event loop. Blocks until the future
+///
+/// # Panics
+///
+/// Panics if the future can never complete because the runtime has
+/// deadlocked: see [`try_block_on`] for details. The panic message describes
+/// every task which was still pending.
+#[track_caller]
 pub fn block_on<F>(fut: F) -> F::Output
 where
     F: Future,
 {
-    // Construct the reactor
-    let reactor = Reactor::new();
+    match try_block_on(fut) {
+        Ok(res) => res,
+        Err(deadlock) => panic!("{deadlock}"),
+    }
+}
+
+/// Start the event loop. Blocks until the future is complete, or fails with
+/// a [`Deadlock`] if it never can be.
+///
+/// A deadlock occurs when the future has not completed, but no task is ready
+/// to run and no task is waiting on a pollable, so there is no event which
+/// could ever wake a task to make progress. When this happens, the future
+/// is dropped, and the `Deadlock` describes every task which was still
+/// pending.
+#[track_caller]
+pub fn try_block_on<F>(fut: F) -> Result<F::Output, Deadlock>
+where
+    F: Future,
+{
+    run(Reactor::new(), fut)
+}
+
+/// Run the event loop on the given `Reactor` until the future is complete,
+/// or until no further progress is possible.
+#[track_caller]
+pub(super) fn run<F>(reactor: Reactor, fut: F) -> Result<F::Output, Deadlock>
+where
+    F: Future,
+{
     // Store a copy as a singleton to be used elsewhere:
     let prev = REACTOR.replace(Some(reactor.clone()));
     if prev.is_some() {
         panic!("cannot wstd::runtime::block_on inside an existing block_on!")
     }
 
+    let info = TaskInfo::new(None);
+    let root = info.id();
+    let registration = reactor.register_task(info.clone());
     // Spawn the task onto the reactor.
     // Safety: The execution loop below, concluding with pulling the Ready out
-    // of the root_task, ensures that it does not outlive the Future or its
-    // output.
+    // of the root_task (or dropping it, if it can never become Ready),
+    // ensures that it does not outlive the Future or its output.
     #[allow(unsafe_code)]
-    let root_task = unsafe { reactor.spawn_unchecked(fut) };
+    let mut root_task = unsafe {
+        reactor.spawn_unchecked(Instrumented::new(
+            info,
+            Locals::default(),
+            registration,
+            fut,
+        ))
+    };
 
     loop {
         match reactor.pop_ready_list() {
             // No more work is possible - only a pending pollable could
             // possibly create a runnable, and there are none.
-            None if reactor.pending_pollables_is_empty() => break,
+            None if reactor.pending_pollables_is_empty() && !reactor.can_auto_advance() => break,
+            // Under a test runtime, virtual time only passes when no task
+            // is able to run, even once pollables have been checked.
+            None if reactor.can_auto_advance() => {
+                reactor.nonblock_check_pollables();
+                if reactor.ready_list_is_empty() {
+                    reactor.auto_advance();
+                }
+            }
             // Block until a pending pollable puts something on the ready
             // list.
             None => reactor.block_on_pollables(),
@@ -48,17 +102,105 @@ where
             }
         }
     }
-    // Clear the singleton
-    REACTOR.replace(None);
     // Get the result out of the root task
-    let mut root_task = pin!(root_task);
     let mut noop_context = Context::from_waker(Waker::noop());
-    match root_task.as_mut().poll(&mut noop_context) {
-        Poll::Ready(res) => res,
+    let result = match Pin::new(&mut root_task).poll(&mut noop_context) {
+        Poll::Ready(res) => Ok(res),
         Poll::Pending => {
-            unreachable!(
-                "ready list empty, therefore root task should be ready. malformed root task?"
-            )
+            let deadlock = Deadlock {
+                root,
+                snapshot: reactor.snapshot(),
+            };
+            // Cancel the root task while the reactor is still current,
+            // because dropping its future may deregister pollables.
+            drop(root_task);
+            Err(deadlock)
+        }
+    };
+    // Clear the singleton
+    REACTOR.replace(None);
+    result
+}
+
+/// The error returned by [`try_block_on`] when the future passed to it can
+/// never complete.
+///
+/// The `Display` impl describes the problem, and every task which was still
+/// pending, along with where it was spawned.
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+    root: TaskId,
+    snapshot: Snapshot,
+}
+
+impl Deadlock {
+    /// The state of the runtime when the deadlock was detected.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "wstd runtime deadlock: the future passed to `block_on` is not complete, \
+             but no task is ready to run and no task is waiting on a pollable, so no \
+             further progress is possible."
+        )?;
+        writeln!(
+            f,
+            "This usually means a future returned `Poll::Pending` without arranging \
+             for its `Waker` to be woken, such as a hand-written future which never \
+             calls `wake`, or that a task is waiting on something which can never \
+             happen, such as `Timer::never` or a task which is itself stuck."
+        )?;
+        writeln!(f, "Pending tasks:")?;
+        for task in self.snapshot.tasks() {
+            write!(f, "  {task}")?;
+            if task.id() == self.root {
+                write!(f, " (block_on)")?;
+            }
+            writeln!(f)?;
+        }
+        for pollable in self.snapshot.pollables() {
+            writeln!(f, "  {pollable}")?;
         }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Deadlock {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending_forever_is_deadlock() {
+        let err = try_block_on(std::future::pending::<()>()).unwrap_err();
+        assert_eq!(err.snapshot().tasks().len(), 1);
+        let message = err.to_string();
+        assert!(message.contains("(block_on)"), "{message}");
+    }
+
+    #[test]
+    fn forgotten_waker_is_deadlock() {
+        let err = try_block_on(async {
+            let task = crate::task::Builder::new()
+                .name("forgetful")
+                .spawn(futures_lite::future::poll_fn(|_cx| Poll::<()>::Pending));
+            task.await
+        })
+        .unwrap_err();
+        assert_eq!(err.snapshot().tasks().len(), 2);
+        let message = err.to_string();
+        assert!(message.contains("\"forgetful\" spawned at"), "{message}");
+    }
+
+    #[test]
+    #[should_panic(expected = "wstd runtime deadlock")]
+    fn block_on_panics() {
+        block_on(std::future::pending::<()>())
     }
 }
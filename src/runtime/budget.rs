@@ -0,0 +1,89 @@
+//! Cooperative scheduling budget.
+//!
+//! A task which performs I/O on streams which are always ready would never
+//! return `Poll::Pending`, and so would starve every other task in the
+//! `Reactor`, including timers. To prevent this, each time a task is polled
+//! it is given a budget of I/O operations. Once the budget is exhausted, the
+//! next I/O operation wakes the task and returns `Poll::Pending`, forcing it
+//! to yield to the rest of the ready list.
+
+use std::cell::Cell;
+use std::future::poll_fn;
+use std::task::{Context, Poll};
+
+/// The number of ready I/O operations a task may perform each time it is
+/// polled, before it is forced to yield.
+pub(crate) const BUDGET: u32 = 128;
+
+std::thread_local! {
+    /// The remaining budget of the current task, or `None` outside of a task,
+    /// where the budget is unconstrained.
+    static REMAINING: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Run `f` with a fresh budget, restoring the previous budget afterwards.
+pub(crate) fn with_budget<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<u32>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            REMAINING.set(self.0);
+        }
+    }
+    let _restore = Restore(REMAINING.replace(Some(BUDGET)));
+    f()
+}
+
+/// Consume one unit of the current task's budget. If the budget is
+/// exhausted, wakes the task and returns `Poll::Pending`.
+pub(crate) fn poll_proceed(cx: &mut Context<'_>) -> Poll<()> {
+    match REMAINING.get() {
+        None => Poll::Ready(()),
+        Some(0) => {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        Some(n) => {
+            REMAINING.set(Some(n - 1));
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Consume one unit of the current task's budget, yielding to other tasks
+/// if the budget is exhausted.
+pub(crate) async fn proceed() {
+    poll_fn(poll_proceed).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unconstrained_outside_task() {
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        for _ in 0..(BUDGET * 2) {
+            assert!(poll_proceed(&mut cx).is_ready());
+        }
+    }
+
+    #[test]
+    fn exhausted_budget_yields() {
+        let polls = crate::runtime::block_on(async {
+            let mut polls = 0;
+            futures_lite::future::poll_fn(|cx| {
+                polls += 1;
+                loop {
+                    match poll_proceed(cx) {
+                        Poll::Ready(()) => continue,
+                        Poll::Pending if polls < 3 => return Poll::Pending,
+                        Poll::Pending => return Poll::Ready(()),
+                    }
+                }
+            })
+            .await;
+            polls
+        });
+        assert_eq!(polls, 3, "each exhausted budget forced a yield");
+    }
+}
@@ -6,16 +6,33 @@
 //! [`wasip2::Pollable`](https://docs.rs/wasi/latest/wasi/io/poll/struct.Pollable.html).
 //! This will automatically wait for the futures to resolve, and call the
 //! necessary wakers to work.
+//!
+//! Bindings for other WASI interfaces, generated by `wit-bindgen`, can share
+//! the `Pollable` type used here by mapping `wasi:io/poll` to `wasip2` with
+//! the `with` option of `wit_bindgen::generate!`. Their pollables can then be
+//! awaited with [`AsyncPollable::new`], in batches with
+//! [`AsyncPollable::wait_any`] and [`AsyncPollable::wait_all`], or handed a
+//! callback with [`Reactor::spawn_on_ready`].
 
 #![deny(missing_debug_implementations, nonstandard_style)]
 #![warn(missing_docs, unreachable_pub)]
 
 mod block_on;
+pub(crate) mod budget;
 mod reactor;
+mod snapshot;
+pub mod test_runtime;
+mod wait;
 
 pub use ::async_task::Task;
-pub use block_on::block_on;
+pub use block_on::{Deadlock, block_on, try_block_on};
 pub use reactor::{AsyncPollable, Reactor, WaitFor};
+pub use snapshot::{PollableSnapshot, Snapshot, TaskSnapshot};
+pub use wait::{WaitAll, WaitAny};
+
+#[cfg(test)]
+pub(crate) use reactor::CountingWaker;
+pub(crate) use reactor::TaskRegistration;
 use std::cell::RefCell;
 
 // There are no threads in WASI 0.2, so this is just a safe way to thread a single reactor to all
@@ -27,6 +44,7 @@ pub(crate) static REACTOR: RefCell<Option<Reactor>> = const { RefCell::new(None)
 /// Spawn a `Future` as a `Task` on the current `Reactor`.
 ///
 /// Panics if called from outside `block_on`.
+#[track_caller]
 pub fn spawn<F, T>(fut: F) -> Task<T>
 where
     F: std::future::Future<Output = T> + 'static,
@@ -1,12 +1,19 @@
 use super::REACTOR;
+use super::snapshot::{PollableSnapshot, Snapshot, TaskSnapshot};
+use super::test_runtime::{TimerKey, VirtualState};
+use crate::task::{Instrumented, Locals, TaskId, TaskInfo};
+use crate::time::{Duration, Instant};
 
 use async_task::{Runnable, Task};
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll, Waker};
 use slab::Slab;
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::Location;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use wasip2::io::poll::Pollable;
 
 /// A key for a `Pollable`, which is an index into the `Slab<Pollable>` in `Reactor`.
@@ -14,6 +21,12 @@ use wasip2::io::poll::Pollable;
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub(crate) struct EventKey(pub(crate) usize);
 
+/// A key for a waiter, which is an index into the waker `Slab` of the
+/// pollable it is waiting on.
+#[repr(transparent)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+struct WaiterKey(usize);
+
 /// A Registration is a reference to the Reactor's owned Pollable. When the registration is
 /// dropped, the reactor will drop the Pollable resource.
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -29,78 +42,169 @@ impl Drop for Registration {
 
 /// An AsyncPollable is a reference counted Registration. It can be cloned, and used to create
 /// as many WaitFor futures on a Pollable that the user needs.
+///
+/// All of the `WaitFor` futures created from an `AsyncPollable` (and its
+/// clones) share a single entry in the set of pollables passed to
+/// `wasi:io/poll`, so any number of tasks may wait on the same `AsyncPollable`
+/// without adding to the cost of each poll.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AsyncPollable(Arc<Registration>);
 
 impl AsyncPollable {
     /// Create an `AsyncPollable` from a Wasi `Pollable`. Schedules the `Pollable` with the current
     /// `Reactor`.
+    #[track_caller]
     pub fn new(pollable: Pollable) -> Self {
         Reactor::current().schedule(pollable)
     }
     /// Create a Future that waits for the Pollable's readiness.
     pub fn wait_for(&self) -> WaitFor {
-        use std::sync::atomic::{AtomicU64, Ordering};
-        static COUNTER: AtomicU64 = AtomicU64::new(0);
-        let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
         WaitFor {
-            waitee: Waitee {
-                pollable: self.clone(),
-                unique,
-            },
-            needs_deregistration: false,
+            pollable: self.clone(),
+            waiter: None,
         }
     }
-}
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-struct Waitee {
-    /// This needs to be a reference counted registration, because it may outlive the AsyncPollable
-    /// &self that it was created from.
-    pollable: AsyncPollable,
-    unique: u64,
+    /// Check, without blocking, whether the Pollable is ready.
+    pub fn is_ready(&self) -> bool {
+        Reactor::current().ready(self.0.key)
+    }
 }
 
 /// A Future that waits for the Pollable's readiness.
 #[must_use = "futures do nothing unless polled or .awaited"]
 #[derive(Debug)]
 pub struct WaitFor {
-    waitee: Waitee,
-    needs_deregistration: bool,
+    /// This needs to be a reference counted registration, because it may outlive the AsyncPollable
+    /// &self that it was created from.
+    pollable: AsyncPollable,
+    /// Set once this future has been polled and found pending, and cleared when it completes.
+    waiter: Option<WaiterKey>,
+}
+impl WaitFor {
+    /// Check, without blocking, whether the Pollable this future is waiting
+    /// on is ready. If this returns `true`, the next poll of this future will
+    /// return `Poll::Ready`.
+    pub fn is_ready(&self) -> bool {
+        self.pollable.is_ready()
+    }
 }
 impl Future for WaitFor {
     type Output = ();
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let reactor = Reactor::current();
-        if reactor.ready(&self.as_ref().waitee, cx.waker()) {
+        let this = &mut *self;
+        let key = this.pollable.0.key;
+        if reactor.ready(key) {
+            if let Some(waiter) = this.waiter.take() {
+                reactor.deregister_waiter(key, waiter);
+            }
             Poll::Ready(())
         } else {
-            self.as_mut().needs_deregistration = true;
+            reactor.register_waiter(key, &mut this.waiter, cx.waker());
             Poll::Pending
         }
     }
 }
 impl Drop for WaitFor {
     fn drop(&mut self) {
-        if self.needs_deregistration {
-            Reactor::current().deregister_waitee(&self.waitee)
+        if let Some(waiter) = self.waiter.take() {
+            Reactor::current().deregister_waiter(self.pollable.0.key, waiter)
         }
     }
 }
 
 /// Manage async system resources for WASI 0.2
+///
+/// The `Reactor` is single-threaded, and is neither `Send` nor `Sync`. Futures
+/// which need to be `Send` should use [`Reactor::current`] where needed,
+/// rather than holding a `Reactor` across an `.await`.
 #[derive(Debug, Clone)]
 pub struct Reactor {
-    inner: Arc<InnerReactor>,
+    inner: Rc<InnerReactor>,
 }
 
-/// The private, internal `Reactor` implementation - factored out so we can take
-/// a lock of the whole.
+/// The private, internal `Reactor` implementation. WASI 0.2 is
+/// single-threaded, so all state lives in `RefCell`s, and no borrow is held
+/// across a call that may re-enter the reactor (such as a wake).
 #[derive(Debug)]
 struct InnerReactor {
-    pollables: Mutex<Slab<Pollable>>,
-    wakers: Mutex<HashMap<Waitee, Waker>>,
-    ready_list: Mutex<VecDeque<Runnable>>,
+    pollables: RefCell<Pollables>,
+    ready_list: RefCell<VecDeque<Runnable>>,
+    tasks: Rc<RefCell<Slab<TaskInfo>>>,
+    /// Present when running as a `test_runtime`, with a virtual clock.
+    virtual_state: Option<RefCell<VirtualState>>,
+}
+
+/// Registers a live task with the `Reactor` for introspection, and removes
+/// it when dropped along with the task's future. Holds a weak reference, as a
+/// task may outlive the `Reactor` it was spawned on.
+#[derive(Debug)]
+pub(crate) struct TaskRegistration {
+    tasks: Weak<RefCell<Slab<TaskInfo>>>,
+    key: usize,
+}
+
+impl Drop for TaskRegistration {
+    fn drop(&mut self) {
+        if let Some(tasks) = self.tasks.upgrade() {
+            let info = tasks.borrow_mut().remove(self.key);
+            drop(info);
+        }
+    }
+}
+
+/// A `Pollable` owned by the reactor, and the wakers of every `WaitFor` which
+/// has been polled and found it pending.
+#[derive(Debug)]
+struct Registered {
+    pollable: Pollable,
+    /// Where the pollable was scheduled with the reactor.
+    location: &'static Location<'static>,
+    waiters: Slab<Waiter>,
+    /// Position of this pollable in `Pollables::interest`, if it is currently
+    /// part of the set passed to `wasi:io/poll`.
+    interest_index: Option<usize>,
+}
+
+/// A `WaitFor` which has been polled and found pending.
+#[derive(Debug)]
+struct Waiter {
+    waker: Waker,
+    /// The task which polled the `WaitFor`, if any.
+    task: Option<TaskId>,
+}
+
+/// The set of registered pollables, along with the dense interest list of
+/// those pollables which have waiters that have not yet been woken.
+/// Registering, waking, and deregistering a waiter are all O(1), so the only
+/// per-iteration cost proportional to the number of pending pollables is
+/// building the argument to `wasi:io/poll`.
+#[derive(Debug, Default)]
+struct Pollables {
+    slab: Slab<Registered>,
+    interest: Vec<EventKey>,
+}
+
+impl Pollables {
+    fn add_interest(&mut self, key: EventKey) {
+        let registered = &mut self.slab[key.0];
+        if registered.interest_index.is_none() {
+            registered.interest_index = Some(self.interest.len());
+            self.interest.push(key);
+        }
+    }
+
+    fn remove_interest(&mut self, key: EventKey) {
+        let Some(index) = self.slab[key.0].interest_index.take() else {
+            return;
+        };
+        self.interest.swap_remove(index);
+        // The last pollable in the interest list has been moved into the
+        // vacated position, so its index needs to be updated.
+        if let Some(moved) = self.interest.get(index) {
+            self.slab[moved.0].interest_index = Some(index);
+        }
+    }
 }
 
 impl Reactor {
@@ -119,11 +223,22 @@ impl Reactor {
 
     /// Create a new instance of `Reactor`
     pub(crate) fn new() -> Self {
+        Self::with_state(None)
+    }
+
+    /// Create a new instance of `Reactor` for a `test_runtime`, with a
+    /// virtual clock.
+    pub(crate) fn with_virtual_state(state: VirtualState) -> Self {
+        Self::with_state(Some(RefCell::new(state)))
+    }
+
+    fn with_state(virtual_state: Option<RefCell<VirtualState>>) -> Self {
         Self {
-            inner: Arc::new(InnerReactor {
-                pollables: Mutex::new(Slab::new()),
-                wakers: Mutex::new(HashMap::new()),
-                ready_list: Mutex::new(VecDeque::new()),
+            inner: Rc::new(InnerReactor {
+                pollables: RefCell::new(Pollables::default()),
+                ready_list: RefCell::new(VecDeque::new()),
+                tasks: Rc::new(RefCell::new(Slab::new())),
+                virtual_state,
             }),
         }
     }
@@ -132,21 +247,13 @@ impl Reactor {
     /// Future pending on their readiness. This function returns indicating
     /// that set of pollables is not empty.
     pub(crate) fn pending_pollables_is_empty(&self) -> bool {
-        self.inner.wakers.lock().unwrap().is_empty()
+        self.inner.pollables.borrow().interest.is_empty()
     }
 
     /// Block until at least one pending pollable is ready, waking a pending future.
     /// Precondition: self.nonempty_pending_pollables() is true.
     pub(crate) fn block_on_pollables(&self) {
-        self.check_pollables(|targets| {
-            debug_assert_ne!(
-                targets.len(),
-                0,
-                "Attempting to block on an empty list of pollables - without any pending work, no progress can be made and wasip2::io::poll::poll will trap"
-            );
-            wasip2::io::poll::poll(targets)
-
-        })
+        self.check_pollables(None)
     }
 
     /// Without blocking, check for any ready pollables and wake the
@@ -162,100 +269,140 @@ impl Reactor {
         static READY_POLLABLE: LazyLock<Pollable> =
             LazyLock::new(|| wasip2::clocks::monotonic_clock::subscribe_duration(0));
 
-        self.check_pollables(|targets| {
-            // Create a new set of targets, with the addition of the ready
-            // pollable:
-            let ready_index = targets.len();
-            let mut new_targets = Vec::with_capacity(ready_index + 1);
-            new_targets.extend_from_slice(targets);
-            new_targets.push(&*READY_POLLABLE);
-
-            // Poll is now guaranteed to return immediately, because at least
-            // one member is ready:
-            let mut ready_list = wasip2::io::poll::poll(&new_targets);
-
-            // Erase our extra ready pollable from the ready list:
-            ready_list.retain(|e| *e != ready_index as u32);
-            ready_list
-        })
+        self.check_pollables(Some(&READY_POLLABLE))
     }
 
     /// Common core of blocking and nonblocking pollable checks. Wakes any
-    /// futures which are pending on the pollables, according to the result of
-    /// the check_ready function.
+    /// futures which are pending on the pollables which `wasi:io/poll`
+    /// reports as ready. If `always_ready` is provided, it is appended to the
+    /// targets so that the poll returns immediately.
     /// Precondition: self.nonempty_pending_pollables() is true.
-    fn check_pollables<F>(&self, check_ready: F)
-    where
-        F: FnOnce(&[&Pollable]) -> Vec<u32>,
-    {
-        let wakers = self.inner.wakers.lock().unwrap();
-        let pollables = self.inner.pollables.lock().unwrap();
-
-        // We're about to wait for a number of pollables. When they wake we get
-        // the *indexes* back for the pollables whose events were available - so
-        // we need to be able to associate the index with the right waker.
-
-        // We start by iterating over the pollables, and keeping note of which
-        // pollable belongs to which waker
-        let mut indexed_wakers = Vec::with_capacity(wakers.len());
-        let mut targets = Vec::with_capacity(wakers.len());
-        for (waitee, waker) in wakers.iter() {
-            let pollable_index = waitee.pollable.0.key;
-            indexed_wakers.push(waker);
-            targets.push(&pollables[pollable_index.0]);
-        }
-
-        // Now that we have that association, we're ready to check our targets for readiness.
-        // (This is either a wasi poll, or the nonblocking variant.)
-        let ready_indexes = check_ready(&targets);
+    fn check_pollables(&self, always_ready: Option<&Pollable>) {
+        let ready_wakers = {
+            let mut pollables = self.inner.pollables.borrow_mut();
+
+            // The interest list is positional: the indexes returned by poll
+            // are indexes into it, so no additional bookkeeping is needed
+            // to associate a ready pollable with its waiters.
+            let ready_keys = {
+                let mut targets = Vec::with_capacity(pollables.interest.len() + 1);
+                for key in pollables.interest.iter() {
+                    targets.push(&pollables.slab[key.0].pollable);
+                }
+                debug_assert_ne!(
+                    targets.len(),
+                    0,
+                    "Attempting to block on an empty list of pollables - without any pending work, no progress can be made and wasip2::io::poll::poll will trap"
+                );
+                let ready_index = targets.len();
+                if let Some(always_ready) = always_ready {
+                    targets.push(always_ready);
+                }
 
-        // Once we have the indexes for which pollables are available, we need
-        // to convert it back to the right keys for the wakers. Earlier we
-        // established a positional index -> waker key relationship, so we can
-        // go right ahead and perform a lookup there.
-        let ready_wakers = ready_indexes
-            .into_iter()
-            .map(|index| indexed_wakers[index as usize]);
+                // Removing a pollable from the interest list moves the
+                // pollable at the end of the list into its position, which
+                // would invalidate the remaining ready indexes. Resolve them
+                // all to keys first, erasing our extra ready pollable from
+                // the ready list, if present.
+                wasip2::io::poll::poll(&targets)
+                    .into_iter()
+                    .filter(|index| *index as usize != ready_index)
+                    .map(|index| pollables.interest[index as usize])
+                    .collect::<Vec<_>>()
+            };
 
+            // Every waiter on a ready pollable needs to be woken, but only
+            // once. The pollable stays out of the interest list until one of
+            // its `WaitFor`s is polled again and finds it is still not ready.
+            let mut ready_wakers = Vec::new();
+            for key in ready_keys {
+                pollables.remove_interest(key);
+                ready_wakers.extend(
+                    pollables.slab[key.0]
+                        .waiters
+                        .iter()
+                        .map(|(_, w)| w.waker.clone()),
+                );
+            }
+            ready_wakers
+        };
+
+        // Wake without any borrows held, because waking may re-enter the
+        // reactor.
         for waker in ready_wakers {
-            waker.wake_by_ref()
+            waker.wake()
         }
     }
 
     /// Turn a Wasi [`Pollable`] into an [`AsyncPollable`]
+    #[track_caller]
     pub fn schedule(&self, pollable: Pollable) -> AsyncPollable {
-        let mut pollables = self.inner.pollables.lock().unwrap();
-        let key = EventKey(pollables.insert(pollable));
+        let mut pollables = self.inner.pollables.borrow_mut();
+        let key = EventKey(pollables.slab.insert(Registered {
+            pollable,
+            location: Location::caller(),
+            waiters: Slab::new(),
+            interest_index: None,
+        }));
         AsyncPollable(Arc::new(Registration { key }))
     }
 
     fn deregister_event(&self, key: EventKey) {
-        let mut pollables = self.inner.pollables.lock().unwrap();
-        pollables.remove(key.0);
+        let mut pollables = self.inner.pollables.borrow_mut();
+        // Every `WaitFor` holds a reference to the registration, so there
+        // can be no waiters left once it is dropped.
+        debug_assert!(pollables.slab[key.0].waiters.is_empty());
+        pollables.remove_interest(key);
+        let registered = pollables.slab.remove(key.0);
+        drop(pollables);
+        // Drop the resource after the borrow is released.
+        drop(registered);
     }
 
-    fn deregister_waitee(&self, waitee: &Waitee) {
-        let mut wakers = self.inner.wakers.lock().unwrap();
-        wakers.remove(waitee);
+    /// Register interest in the readiness of the pollable `key`, on behalf of
+    /// the `WaitFor` which owns `waiter`. The waiter is created on the first
+    /// call, and reused by subsequent calls.
+    fn register_waiter(&self, key: EventKey, waiter: &mut Option<WaiterKey>, waker: &Waker) {
+        let mut pollables = self.inner.pollables.borrow_mut();
+        let waiters = &mut pollables.slab[key.0].waiters;
+        match *waiter {
+            Some(waiter_key) => {
+                let existing = &mut waiters[waiter_key.0].waker;
+                if !existing.will_wake(waker) {
+                    existing.clone_from(waker);
+                }
+            }
+            None => {
+                *waiter = Some(WaiterKey(waiters.insert(Waiter {
+                    waker: waker.clone(),
+                    task: crate::task::try_id(),
+                })))
+            }
+        }
+        pollables.add_interest(key);
     }
 
-    fn ready(&self, waitee: &Waitee, waker: &Waker) -> bool {
-        let ready = self
-            .inner
+    fn deregister_waiter(&self, key: EventKey, waiter: WaiterKey) {
+        let mut pollables = self.inner.pollables.borrow_mut();
+        let waiters = &mut pollables.slab[key.0].waiters;
+        let removed = waiters.remove(waiter.0);
+        if waiters.is_empty() {
+            pollables.remove_interest(key);
+        }
+        drop(pollables);
+        // Drop the waker after the borrow is released.
+        drop(removed);
+    }
+
+    fn ready(&self, key: EventKey) -> bool {
+        self.inner
             .pollables
-            .lock()
-            .unwrap()
-            .get(waitee.pollable.0.key.0)
+            .borrow()
+            .slab
+            .get(key.0)
             .expect("only live EventKey can be checked for readiness")
-            .ready();
-        if !ready {
-            self.inner
-                .wakers
-                .lock()
-                .unwrap()
-                .insert(waitee.clone(), waker.clone());
-        }
-        ready
+            .pollable
+            .ready()
     }
 
     /// We need an unchecked spawn for implementing `block_on`, where the
@@ -271,7 +418,7 @@ impl Reactor {
         F: Future<Output = T>,
     {
         let this = self.clone();
-        let schedule = move |runnable| this.inner.ready_list.lock().unwrap().push_back(runnable);
+        let schedule = move |runnable| this.inner.ready_list.borrow_mut().push_back(runnable);
 
         // SAFETY:
         // we're using this exactly like async_task::spawn_local, except that
@@ -280,29 +427,208 @@ impl Reactor {
         // single-threaded.
         #[allow(unsafe_code)]
         let (runnable, task) = unsafe { async_task::spawn_unchecked(fut, schedule) };
-        self.inner.ready_list.lock().unwrap().push_back(runnable);
+        self.inner.ready_list.borrow_mut().push_back(runnable);
         task
     }
 
     /// Spawn a `Task` on the `Reactor`.
+    ///
+    /// The task is unnamed, and does not inherit task-locals. Use
+    /// [`wstd::task::Builder`](crate::task::Builder) to configure these.
+    #[track_caller]
     pub fn spawn<F, T>(&self, fut: F) -> Task<T>
     where
         F: Future<Output = T> + 'static,
         T: 'static,
     {
+        self.spawn_with(TaskInfo::new(None), Locals::default(), fut)
+    }
+
+    /// Schedule a Wasi [`Pollable`] with the `Reactor`, and spawn a `Task`
+    /// which calls `f` once it is ready. The `Task` gives the result of `f`.
+    ///
+    /// Dropping the `Task` cancels the callback; use [`Task::detach`] to let
+    /// it run in the background.
+    #[track_caller]
+    pub fn spawn_on_ready<F, T>(&self, pollable: Pollable, f: F) -> Task<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        let pollable = self.schedule(pollable);
+        self.spawn(async move {
+            pollable.wait_for().await;
+            f()
+        })
+    }
+
+    /// Spawn a `Task` on the `Reactor`, with the given identity and
+    /// task-locals.
+    pub(crate) fn spawn_with<F, T>(&self, info: TaskInfo, locals: Locals, fut: F) -> Task<T>
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let registration = self.register_task(info.clone());
         // Safety: 'static constraints satisfy the lifetime requirements
         #[allow(unsafe_code)]
         unsafe {
-            self.spawn_unchecked(fut)
+            self.spawn_unchecked(Instrumented::new(info, locals, registration, fut))
         }
     }
 
+    /// Register a task as live, until the returned registration is dropped.
+    pub(crate) fn register_task(&self, info: TaskInfo) -> TaskRegistration {
+        let key = self.inner.tasks.borrow_mut().insert(info);
+        TaskRegistration {
+            tasks: Rc::downgrade(&self.inner.tasks),
+            key,
+        }
+    }
+
+    /// Capture the current state of the `Reactor`: its live tasks, its
+    /// registered pollables and the tasks waiting on them, and the number of
+    /// tasks ready to run.
+    ///
+    /// This is intended for debugging a program which is not making
+    /// progress, e.g. by printing the snapshot to stderr with
+    /// [`Reactor::dump`].
+    pub fn snapshot(&self) -> Snapshot {
+        let tasks = self
+            .inner
+            .tasks
+            .borrow()
+            .iter()
+            .map(|(_, info)| TaskSnapshot::new(info.clone()))
+            .collect();
+        let pollables = self
+            .inner
+            .pollables
+            .borrow()
+            .slab
+            .iter()
+            .map(|(_, registered)| {
+                PollableSnapshot::new(
+                    registered.location,
+                    registered
+                        .waiters
+                        .iter()
+                        .map(|(_, waiter)| waiter.task)
+                        .collect(),
+                    registered.interest_index.is_some(),
+                )
+            })
+            .collect();
+        let ready = self.inner.ready_list.borrow().len();
+        Snapshot::new(tasks, pollables, ready)
+    }
+
+    /// Print a [`Reactor::snapshot`] to stderr.
+    pub fn dump(&self) {
+        eprintln!("{}", self.snapshot());
+    }
+
     pub(super) fn pop_ready_list(&self) -> Option<Runnable> {
-        self.inner.ready_list.lock().unwrap().pop_front()
+        let mut ready_list = self.inner.ready_list.borrow_mut();
+        match &self.inner.virtual_state {
+            Some(state) => {
+                let index = state.borrow_mut().choose(ready_list.len());
+                ready_list.remove(index)
+            }
+            None => ready_list.pop_front(),
+        }
     }
 
     pub(super) fn ready_list_is_empty(&self) -> bool {
-        self.inner.ready_list.lock().unwrap().is_empty()
+        self.inner.ready_list.borrow().is_empty()
+    }
+
+    pub(super) fn is_virtual(&self) -> bool {
+        self.inner.virtual_state.is_some()
+    }
+
+    /// The reading of the virtual clock, if this is a `test_runtime`.
+    pub(crate) fn virtual_now(&self) -> Option<Instant> {
+        let state = self.inner.virtual_state.as_ref()?;
+        Some(state.borrow().now())
+    }
+
+    /// Register interest in the virtual clock reaching `deadline`, on behalf
+    /// of the timer which owns `key`.
+    pub(crate) fn register_timer(
+        &self,
+        deadline: Instant,
+        key: &mut Option<TimerKey>,
+        waker: &Waker,
+    ) {
+        self.inner
+            .virtual_state
+            .as_ref()
+            .expect("virtual timers require a test runtime")
+            .borrow_mut()
+            .register_timer(deadline, key, waker)
+    }
+
+    pub(crate) fn deregister_timer(&self, key: TimerKey) {
+        if let Some(state) = &self.inner.virtual_state {
+            let removed = state.borrow_mut().deregister_timer(key);
+            // Drop the waker after the borrow is released.
+            drop(removed);
+        }
+    }
+
+    /// Advance the virtual clock, if this is a `test_runtime`, waking every
+    /// expired timer.
+    pub(super) fn advance_virtual_clock(&self, duration: Duration) -> Option<()> {
+        let state = self.inner.virtual_state.as_ref()?;
+        let wakers = state.borrow_mut().advance(duration);
+        for waker in wakers {
+            waker.wake()
+        }
+        Some(())
+    }
+
+    /// Whether the virtual clock should be advanced when no task can run.
+    pub(super) fn can_auto_advance(&self) -> bool {
+        self.inner
+            .virtual_state
+            .as_ref()
+            .is_some_and(|state| state.borrow().can_auto_advance())
+    }
+
+    /// Advance the virtual clock to the next timer's deadline, waking it.
+    pub(super) fn auto_advance(&self) {
+        if let Some(state) = &self.inner.virtual_state {
+            let wakers = state.borrow_mut().auto_advance();
+            for waker in wakers {
+                waker.wake()
+            }
+        }
+    }
+}
+
+/// A waker which counts how many times it has been woken, for testing
+/// which waiters are woken.
+#[cfg(test)]
+pub(crate) struct CountingWaker(std::sync::atomic::AtomicUsize);
+
+#[cfg(test)]
+impl std::task::Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl CountingWaker {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(CountingWaker(std::sync::atomic::AtomicUsize::new(0)))
+    }
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
     }
 }
 
@@ -379,6 +705,186 @@ mod test {
         })
     }
 
+    // Many waiters on a single pollable should share a single entry in the
+    // set of pollables passed to poll(), and all be woken when it is ready.
+    #[test]
+    fn multiple_waiters_share_pollable() {
+        crate::runtime::block_on(async {
+            let reactor = Reactor::current();
+            let soon = wasip2::clocks::monotonic_clock::subscribe_duration(10_000_000);
+            let soon = reactor.schedule(soon);
+            assert!(!soon.is_ready());
+
+            let waiters = (0..3)
+                .map(|_| {
+                    let soon = soon.clone();
+                    crate::runtime::spawn(async move { soon.wait_for().await })
+                })
+                .collect::<Vec<_>>();
+            futures_lite::future::yield_now().await;
+            assert_eq!(reactor.inner.pollables.borrow().interest.len(), 1);
+
+            for waiter in waiters {
+                waiter.await;
+            }
+            assert!(soon.wait_for().is_ready());
+            assert!(reactor.pending_pollables_is_empty());
+        })
+    }
+
+    /// Every pollable in the interest list knows its own position in it.
+    fn assert_interest_consistent(reactor: &Reactor) {
+        let pollables = reactor.inner.pollables.borrow();
+        for (index, key) in pollables.interest.iter().enumerate() {
+            assert_eq!(pollables.slab[key.0].interest_index, Some(index));
+        }
+        let interested = pollables
+            .slab
+            .iter()
+            .filter(|(_, r)| r.interest_index.is_some())
+            .count();
+        assert_eq!(interested, pollables.interest.len());
+    }
+
+    #[test]
+    fn register_and_deregister_waiters() {
+        crate::runtime::block_on(async {
+            let reactor = Reactor::current();
+            let later = wasip2::clocks::monotonic_clock::subscribe_duration(1_000_000_000);
+            let later = reactor.schedule(later);
+            let key = later.0.key;
+
+            let mut waits = (0..3).map(|_| later.wait_for()).collect::<Vec<_>>();
+            for wait in waits.iter_mut() {
+                assert!(futures_lite::future::poll_once(wait).await.is_none());
+            }
+            // Polling again reuses the existing waiter.
+            assert!(
+                futures_lite::future::poll_once(&mut waits[0])
+                    .await
+                    .is_none()
+            );
+            {
+                let pollables = reactor.inner.pollables.borrow();
+                assert_eq!(pollables.slab[key.0].waiters.len(), 3);
+                assert_eq!(pollables.interest, vec![key]);
+            }
+
+            drop(waits.remove(1));
+            assert_eq!(
+                reactor.inner.pollables.borrow().slab[key.0].waiters.len(),
+                2
+            );
+            assert!(!reactor.pending_pollables_is_empty());
+
+            drop(waits);
+            assert!(
+                reactor.inner.pollables.borrow().slab[key.0]
+                    .waiters
+                    .is_empty()
+            );
+            assert!(reactor.pending_pollables_is_empty());
+
+            drop(later);
+            assert!(reactor.inner.pollables.borrow().slab.is_empty());
+        })
+    }
+
+    #[test]
+    fn interest_list_removals() {
+        crate::runtime::block_on(async {
+            let reactor = Reactor::current();
+            let pollables = (0..4)
+                .map(|_| {
+                    reactor.schedule(wasip2::clocks::monotonic_clock::subscribe_duration(
+                        1_000_000_000,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            let mut waits = pollables.iter().map(|p| p.wait_for()).collect::<Vec<_>>();
+            for wait in waits.iter_mut() {
+                assert!(futures_lite::future::poll_once(wait).await.is_none());
+            }
+            assert_eq!(reactor.inner.pollables.borrow().interest.len(), 4);
+            assert_interest_consistent(&reactor);
+
+            // Remove from the front, the middle, and the back of the list.
+            for index in [0, 1, 1] {
+                drop(waits.remove(index));
+                assert_interest_consistent(&reactor);
+            }
+            assert_eq!(reactor.inner.pollables.borrow().interest.len(), 1);
+            drop(waits);
+            assert!(reactor.pending_pollables_is_empty());
+        })
+    }
+
+    #[test]
+    fn wake_multiple_waiters_once() {
+        crate::runtime::block_on(async {
+            let reactor = Reactor::current();
+            let now = wasip2::clocks::monotonic_clock::subscribe_duration(0);
+            let now = reactor.schedule(now);
+            let key = now.0.key;
+
+            let wakers = (0..3).map(|_| CountingWaker::new()).collect::<Vec<_>>();
+            let mut waiters = vec![None; wakers.len()];
+            for (waiter, waker) in waiters.iter_mut().zip(&wakers) {
+                reactor.register_waiter(key, waiter, &Waker::from(waker.clone()));
+            }
+            // A waiter removed before the pollable is ready is not woken.
+            reactor.deregister_waiter(key, waiters[2].take().unwrap());
+
+            reactor.nonblock_check_pollables();
+            assert_eq!(wakers[0].count(), 1);
+            assert_eq!(wakers[1].count(), 1);
+            assert_eq!(wakers[2].count(), 0);
+            // Once woken, the pollable leaves the interest list, so its
+            // waiters are not woken again.
+            assert!(reactor.pending_pollables_is_empty());
+            assert_interest_consistent(&reactor);
+
+            for waiter in waiters.iter_mut() {
+                if let Some(waiter) = waiter.take() {
+                    reactor.deregister_waiter(key, waiter);
+                }
+            }
+            assert!(
+                reactor.inner.pollables.borrow().slab[key.0]
+                    .waiters
+                    .is_empty()
+            );
+        })
+    }
+
+    #[test]
+    fn snapshot_tasks_and_pollables() {
+        crate::runtime::block_on(async {
+            let reactor = Reactor::current();
+            let sleeper = crate::task::Builder::new()
+                .name("sleeper")
+                .spawn(crate::task::sleep(crate::time::Duration::from_secs(60)));
+            futures_lite::future::yield_now().await;
+
+            let snapshot = reactor.snapshot();
+            assert_eq!(snapshot.tasks().len(), 2);
+            let task = snapshot
+                .tasks()
+                .iter()
+                .find(|t| t.name() == Some("sleeper"))
+                .expect("sleeper task is live");
+            assert_eq!(task.location().file(), file!());
+            assert_eq!(snapshot.pollables().len(), 1);
+            assert_eq!(snapshot.pollables()[0].waiters(), &[Some(task.id())]);
+            assert_eq!(snapshot.pollables()[0].location().file(), file!());
+
+            drop(sleeper);
+            let snapshot = reactor.snapshot();
+            assert_eq!(snapshot.tasks().len(), 1);
+            assert!(snapshot.pollables().is_empty());
+        })
+    }
+
     #[test]
     fn progresses_wasi_independent_futures() {
         crate::runtime::block_on(async {
@@ -456,4 +962,49 @@ mod test {
             assert_eq!(result, Some(false), "cpu_heavy task should have timed out");
         });
     }
+
+    #[test]
+    fn cooperative_concurrency_task_yield_now() {
+        crate::runtime::block_on(async {
+            let cpu_heavy = async move {
+                for _ in 0..10 {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    crate::task::yield_now().await;
+                }
+                true
+            };
+            let timeout = async move {
+                crate::time::Timer::after(crate::time::Duration::from_millis(200))
+                    .wait()
+                    .await;
+                false
+            };
+            let result = futures_lite::future::race(cpu_heavy, timeout).await;
+            assert!(!result, "cpu_heavy task should have timed out");
+        });
+    }
+
+    // A task which never yields explicitly, but only consumes its cooperative
+    // budget, as it would by performing I/O on streams which are always
+    // ready, must still let a timer fire.
+    #[test]
+    fn cooperative_budget() {
+        crate::runtime::block_on(async {
+            let io_heavy = async move {
+                for _ in 0..1000 {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                    crate::runtime::budget::proceed().await;
+                }
+                true
+            };
+            let timeout = async move {
+                crate::time::Timer::after(crate::time::Duration::from_millis(200))
+                    .wait()
+                    .await;
+                false
+            };
+            let result = futures_lite::future::race(io_heavy, timeout).await;
+            assert!(!result, "io_heavy task should have timed out");
+        });
+    }
 }
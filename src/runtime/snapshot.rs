@@ -0,0 +1,165 @@
+use crate::task::{TaskId, TaskInfo};
+use std::fmt;
+use std::panic::Location;
+
+/// The state of a [`Reactor`] at a point in time, returned by
+/// [`Reactor::snapshot`].
+///
+/// The `Display` impl gives a human readable dump of the whole snapshot.
+///
+/// [`Reactor`]: super::Reactor
+/// [`Reactor::snapshot`]: super::Reactor::snapshot
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    tasks: Vec<TaskSnapshot>,
+    pollables: Vec<PollableSnapshot>,
+    ready: usize,
+}
+
+impl Snapshot {
+    pub(crate) fn new(
+        tasks: Vec<TaskSnapshot>,
+        pollables: Vec<PollableSnapshot>,
+        ready: usize,
+    ) -> Self {
+        Self {
+            tasks,
+            pollables,
+            ready,
+        }
+    }
+
+    /// Every task which has been spawned on the `Reactor` and has not yet
+    /// completed or been cancelled.
+    pub fn tasks(&self) -> &[TaskSnapshot] {
+        &self.tasks
+    }
+
+    /// Every pollable registered with the `Reactor`.
+    pub fn pollables(&self) -> &[PollableSnapshot] {
+        &self.pollables
+    }
+
+    /// The number of tasks on the ready list, waiting to be run.
+    pub fn ready_list_len(&self) -> usize {
+        self.ready
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "wstd runtime: {} live task(s), {} registered pollable(s), {} task(s) ready to run",
+            self.tasks.len(),
+            self.pollables.len(),
+            self.ready
+        )?;
+        for task in self.tasks.iter() {
+            writeln!(f, "  {task}")?;
+        }
+        for pollable in self.pollables.iter() {
+            writeln!(f, "  {pollable}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A live task, as captured by a [`Snapshot`].
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    info: TaskInfo,
+}
+
+impl TaskSnapshot {
+    pub(crate) fn new(info: TaskInfo) -> Self {
+        Self { info }
+    }
+
+    /// The identifier of the task.
+    pub fn id(&self) -> TaskId {
+        self.info.id()
+    }
+
+    /// The name of the task, if one was given.
+    pub fn name(&self) -> Option<&str> {
+        self.info.name()
+    }
+
+    /// The source location at which the task was spawned.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.info.location()
+    }
+}
+
+impl fmt::Display for TaskSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.id())?;
+        if let Some(name) = self.name() {
+            write!(f, " {name:?}")?;
+        }
+        write!(f, " spawned at {}", self.location())
+    }
+}
+
+/// A pollable registered with the `Reactor`, as captured by a [`Snapshot`].
+#[derive(Debug, Clone)]
+pub struct PollableSnapshot {
+    location: &'static Location<'static>,
+    waiters: Vec<Option<TaskId>>,
+    pending: bool,
+}
+
+impl PollableSnapshot {
+    pub(crate) fn new(
+        location: &'static Location<'static>,
+        waiters: Vec<Option<TaskId>>,
+        pending: bool,
+    ) -> Self {
+        Self {
+            location,
+            waiters,
+            pending,
+        }
+    }
+
+    /// The source location at which the pollable was scheduled with the
+    /// `Reactor`, which identifies what it waits on.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// The tasks which are waiting on the pollable. A waiter is `None` if
+    /// it was polled from outside of a task.
+    pub fn waiters(&self) -> &[Option<TaskId>] {
+        &self.waiters
+    }
+
+    /// Whether the pollable will be included in the next call to
+    /// `wasi:io/poll`. This is false if there are no waiters, or if the
+    /// waiters have been woken and have not yet polled again.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+}
+
+impl fmt::Display for PollableSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pollable scheduled at {}", self.location)?;
+        if self.waiters.is_empty() {
+            return write!(f, ", no waiters");
+        }
+        write!(f, ", waited on by")?;
+        for (i, waiter) in self.waiters.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            match waiter {
+                Some(id) => write!(f, "{sep}task {id}")?,
+                None => write!(f, "{sep}<no task>")?,
+            }
+        }
+        if !self.pending {
+            write!(f, " (woken)")?;
+        }
+        Ok(())
+    }
+}
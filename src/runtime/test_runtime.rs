@@ -0,0 +1,372 @@
+//! A deterministic runtime for tests, with a virtual clock.
+//!
+//! Under [`block_on`], or a `#[wstd::test(virtual_time)]`, time does not pass
+//! on its own. [`Instant::now`] reads a virtual monotonic clock, and every
+//! [`Timer`], [`Interval`], [`sleep`] and [`FutureExt::timeout`] waits on
+//! that clock rather than on the host. The clock moves forward when
+//! [`advance`] is called, or, unless disabled with [`Builder::auto_advance`],
+//! automatically to the next timer's deadline whenever no task is able to
+//! run. A test which sleeps for an hour therefore completes immediately, and
+//! always observes the same sequence of events.
+//!
+//! The virtual clock starts at a fixed, arbitrary `Instant`, and is never
+//! related to the host's clock. Pollables from other WASI interfaces, such as
+//! sockets and HTTP, still wait on the host as usual.
+//!
+//! By default, tasks are run in the order they are woken. With
+//! [`Builder::seed`], or the `WSTD_TEST_SEED` environment variable, the
+//! next task to run is instead chosen pseudo-randomly from every task which
+//! is ready, so that tests may explore different interleavings of tasks,
+//! while any failure can be reproduced by reusing the seed.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use wstd::time::{Duration, Instant};
+//!
+//! #[wstd::test(virtual_time)]
+//! async fn sleeps_instantly() {
+//!     let start = Instant::now();
+//!     wstd::task::sleep(Duration::from_secs(3600)).await;
+//!     assert_eq!(start.elapsed(), Duration::from_secs(3600));
+//! }
+//! ```
+//!
+//! [`Instant::now`]: crate::time::Instant::now
+//! [`Timer`]: crate::time::Timer
+//! [`Interval`]: crate::time::Interval
+//! [`sleep`]: crate::task::sleep
+//! [`FutureExt::timeout`]: crate::future::FutureExt::timeout
+
+use super::{Deadlock, Reactor};
+use crate::time::{Duration, Instant};
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::task::Waker;
+
+/// The environment variable which [`Builder::from_env`] reads a seed from.
+const SEED_VAR: &str = "WSTD_TEST_SEED";
+
+/// The reading of the virtual clock when a test runtime starts.
+const START: u64 = 1 << 40;
+
+/// Configure and run a deterministic test runtime.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    seed: Option<u64>,
+    auto_advance: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            auto_advance: true,
+        }
+    }
+}
+
+impl Builder {
+    /// Create a new `Builder`, which runs tasks in the order they are woken
+    /// and advances the clock automatically.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new `Builder`, with the seed given by the `WSTD_TEST_SEED`
+    /// environment variable, if it is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `WSTD_TEST_SEED` is set, but is not a `u64`.
+    pub fn from_env() -> Self {
+        let mut builder = Self::new();
+        if let Ok(seed) = std::env::var(SEED_VAR) {
+            let seed = seed
+                .parse()
+                .unwrap_or_else(|_| panic!("{SEED_VAR} must be a u64, got {seed:?}"));
+            builder = builder.seed(seed);
+        }
+        builder
+    }
+
+    /// Choose the next task to run pseudo-randomly, from every task which is
+    /// ready, using the given seed.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Set whether the virtual clock advances to the next timer's deadline
+    /// when no task is able to run. By default, it does. When it does not,
+    /// time only passes when [`advance`] is called.
+    pub fn auto_advance(mut self, auto_advance: bool) -> Self {
+        self.auto_advance = auto_advance;
+        self
+    }
+
+    /// Run the future to completion on a test runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime deadlocks: see [`Builder::try_block_on`].
+    #[track_caller]
+    pub fn block_on<F: Future>(self, fut: F) -> F::Output {
+        let seed = self.seed;
+        match self.try_block_on(fut) {
+            Ok(res) => res,
+            Err(deadlock) => match seed {
+                Some(seed) => panic!("{deadlock}(with {SEED_VAR}={seed})"),
+                None => panic!("{deadlock}"),
+            },
+        }
+    }
+
+    /// Run the future to completion on a test runtime, or fail with a
+    /// [`Deadlock`] if it never can be. With a virtual clock, a deadlock
+    /// also occurs if tasks are only waiting on timers, and the clock does
+    /// not advance automatically.
+    #[track_caller]
+    pub fn try_block_on<F: Future>(self, fut: F) -> Result<F::Output, Deadlock> {
+        let state = VirtualState {
+            now: START,
+            timers: BTreeMap::new(),
+            next_timer: 0,
+            auto_advance: self.auto_advance,
+            rng: self.seed.map(SplitMix64),
+        };
+        super::block_on::run(Reactor::with_virtual_state(state), fut)
+    }
+}
+
+/// Run the future to completion on a test runtime, configured by
+/// [`Builder::from_env`]. This is what `#[wstd::test(virtual_time)]` uses.
+///
+/// # Panics
+///
+/// Panics if the runtime deadlocks. See [`Builder::try_block_on`].
+#[track_caller]
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    Builder::from_env().block_on(fut)
+}
+
+/// Advance the virtual clock by `duration`, waking every task waiting on a
+/// timer which has expired. The woken tasks run once the caller next yields.
+///
+/// # Panics
+///
+/// Panics if called from outside of a test runtime.
+#[track_caller]
+pub fn advance(duration: Duration) {
+    Reactor::current()
+        .advance_virtual_clock(duration)
+        .expect("wstd::runtime::test_runtime::advance must be called within a test runtime")
+}
+
+/// Whether the current runtime is a test runtime, with a virtual clock.
+pub fn is_virtual() -> bool {
+    super::REACTOR.with(|r| r.borrow().as_ref().is_some_and(Reactor::is_virtual))
+}
+
+/// A key for a timer registered with the virtual clock. Timers are ordered
+/// by deadline, and then by registration.
+pub(crate) type TimerKey = (u64, u64);
+
+/// The state of a test runtime: its virtual clock, the timers waiting on it,
+/// and how to choose the next task to run.
+#[derive(Debug)]
+pub(crate) struct VirtualState {
+    now: u64,
+    timers: BTreeMap<TimerKey, Waker>,
+    next_timer: u64,
+    auto_advance: bool,
+    rng: Option<SplitMix64>,
+}
+
+impl VirtualState {
+    pub(crate) fn now(&self) -> Instant {
+        Instant(self.now)
+    }
+
+    /// Register, or update the waker of, a timer for `deadline`.
+    pub(crate) fn register_timer(
+        &mut self,
+        deadline: Instant,
+        key: &mut Option<TimerKey>,
+        waker: &Waker,
+    ) {
+        match key.and_then(|key| self.timers.get_mut(&key)) {
+            Some(existing) => {
+                if !existing.will_wake(waker) {
+                    existing.clone_from(waker);
+                }
+            }
+            None => {
+                let new = (deadline.0, self.next_timer);
+                self.next_timer += 1;
+                self.timers.insert(new, waker.clone());
+                *key = Some(new);
+            }
+        }
+    }
+
+    pub(crate) fn deregister_timer(&mut self, key: TimerKey) -> Option<Waker> {
+        self.timers.remove(&key)
+    }
+
+    /// Whether the clock may be advanced to a timer's deadline because no
+    /// task is able to run.
+    pub(crate) fn can_auto_advance(&self) -> bool {
+        self.auto_advance && !self.timers.is_empty()
+    }
+
+    /// Move the clock to the earliest timer's deadline, returning the wakers
+    /// of every timer which has expired.
+    pub(crate) fn auto_advance(&mut self) -> Vec<Waker> {
+        match self.timers.keys().next() {
+            Some(&(deadline, _)) => self.advance_to(deadline.max(self.now)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Move the clock forward by `duration`, returning the wakers of every
+    /// timer which has expired.
+    pub(crate) fn advance(&mut self, duration: Duration) -> Vec<Waker> {
+        self.advance_to(self.now + duration.0)
+    }
+
+    fn advance_to(&mut self, now: u64) -> Vec<Waker> {
+        self.now = now;
+        let pending = self.timers.split_off(&(now + 1, 0));
+        std::mem::replace(&mut self.timers, pending)
+            .into_values()
+            .collect()
+    }
+
+    /// Choose which of `len` ready tasks to run next.
+    pub(crate) fn choose(&mut self, len: usize) -> usize {
+        match &mut self.rng {
+            Some(rng) if len > 1 => (rng.next() % len as u64) as usize,
+            _ => 0,
+        }
+    }
+}
+
+/// The SplitMix64 generator: small, fast, and entirely determined by its
+/// seed, which is all a test scheduler needs.
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time::{Timer, interval};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sleep_advances_clock() {
+        block_on(async {
+            let start = Instant::now();
+            crate::task::sleep(Duration::from_secs(3600)).await;
+            assert_eq!(start.elapsed(), Duration::from_secs(3600));
+        })
+    }
+
+    #[test]
+    fn timers_fire_in_deadline_order() {
+        let order = Builder::new().block_on(async {
+            let order = Rc::new(RefCell::new(Vec::new()));
+            let tasks = [30, 10, 20]
+                .into_iter()
+                .map(|ms| {
+                    let order = order.clone();
+                    crate::runtime::spawn(async move {
+                        crate::task::sleep(Duration::from_millis(ms)).await;
+                        order.borrow_mut().push(ms);
+                    })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                task.await;
+            }
+            order.take()
+        });
+        assert_eq!(order, [10, 20, 30]);
+    }
+
+    #[test]
+    fn interval_and_timeout() {
+        use crate::future::FutureExt;
+        use crate::iter::AsyncIterator;
+
+        block_on(async {
+            let start = Instant::now();
+            let mut ticks = interval(Duration::from_millis(100));
+            for _ in 0..5 {
+                ticks.next().await;
+            }
+            assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+            let res = Timer::never().wait().timeout(Duration::from_secs(1)).await;
+            assert!(res.is_err());
+            assert_eq!(start.elapsed(), Duration::from_millis(1500));
+        })
+    }
+
+    #[test]
+    fn manual_advance() {
+        Builder::new().auto_advance(false).block_on(async {
+            let start = Instant::now();
+            let sleeper = crate::runtime::spawn(async move {
+                crate::task::sleep(Duration::from_secs(5)).await;
+                start.elapsed()
+            });
+            crate::task::yield_now().await;
+            advance(Duration::from_secs(2));
+            crate::task::yield_now().await;
+            advance(Duration::from_secs(3));
+            assert_eq!(sleeper.await, Duration::from_secs(5));
+        })
+    }
+
+    #[test]
+    fn no_auto_advance_is_deadlock() {
+        let res = Builder::new()
+            .auto_advance(false)
+            .try_block_on(async { crate::task::sleep(Duration::from_secs(1)).await });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn seeded_order_is_reproducible() {
+        fn run(seed: u64) -> Vec<u32> {
+            Builder::new().seed(seed).block_on(async {
+                let order = Rc::new(RefCell::new(Vec::new()));
+                let tasks = (0..8)
+                    .map(|n| {
+                        let order = order.clone();
+                        crate::runtime::spawn(async move { order.borrow_mut().push(n) })
+                    })
+                    .collect::<Vec<_>>();
+                for task in tasks {
+                    task.await;
+                }
+                order.take()
+            })
+        }
+        assert_eq!(run(7), run(7));
+        assert!((0..16).any(|seed| run(seed) != run(7)));
+    }
+}
@@ -0,0 +1,119 @@
+use super::{AsyncPollable, WaitFor};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+impl AsyncPollable {
+    /// Create a Future that waits until any one of the pollables is ready,
+    /// giving its index. If several are ready, the lowest index is given.
+    ///
+    /// If `pollables` is empty, the future never completes.
+    pub fn wait_any<'a>(pollables: impl IntoIterator<Item = &'a AsyncPollable>) -> WaitAny {
+        WaitAny {
+            waits: pollables.into_iter().map(AsyncPollable::wait_for).collect(),
+        }
+    }
+
+    /// Create a Future that waits until every one of the pollables is ready.
+    ///
+    /// If `pollables` is empty, the future completes immediately.
+    pub fn wait_all<'a>(pollables: impl IntoIterator<Item = &'a AsyncPollable>) -> WaitAll {
+        WaitAll {
+            waits: pollables.into_iter().map(|p| Some(p.wait_for())).collect(),
+        }
+    }
+}
+
+/// A Future that waits until any one of a set of pollables is ready.
+///
+/// Created by [`AsyncPollable::wait_any`].
+#[must_use = "futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct WaitAny {
+    waits: Vec<WaitFor>,
+}
+
+impl Future for WaitAny {
+    type Output = usize;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        for (index, wait) in self.waits.iter_mut().enumerate() {
+            if Pin::new(wait).poll(cx).is_ready() {
+                // Deregister from the others, which are still pending.
+                self.waits.clear();
+                return Poll::Ready(index);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// A Future that waits until every one of a set of pollables is ready.
+///
+/// Created by [`AsyncPollable::wait_all`].
+#[must_use = "futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct WaitAll {
+    /// Each `WaitFor` is dropped once it has completed.
+    waits: Vec<Option<WaitFor>>,
+}
+
+impl Future for WaitAll {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut pending = false;
+        for slot in self.waits.iter_mut() {
+            if let Some(wait) = slot {
+                if Pin::new(wait).poll(cx).is_ready() {
+                    *slot = None;
+                } else {
+                    pending = true;
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Reactor, block_on};
+    use super::*;
+    use wasip2::clocks::monotonic_clock::subscribe_duration;
+
+    #[test]
+    fn wait_any_gives_first_ready() {
+        block_on(async {
+            let reactor = Reactor::current();
+            let later = reactor.schedule(subscribe_duration(1_000_000_000));
+            let soon = reactor.schedule(subscribe_duration(10_000_000));
+            let index = AsyncPollable::wait_any([&later, &soon]).await;
+            assert_eq!(index, 1);
+            assert!(!later.is_ready());
+        })
+    }
+
+    #[test]
+    fn wait_all_waits_for_last() {
+        block_on(async {
+            let reactor = Reactor::current();
+            let soon = reactor.schedule(subscribe_duration(10_000_000));
+            let later = reactor.schedule(subscribe_duration(40_000_000));
+            AsyncPollable::wait_all([&soon, &later]).await;
+            assert!(soon.is_ready() && later.is_ready());
+            AsyncPollable::wait_all([]).await;
+        })
+    }
+
+    #[test]
+    fn spawn_on_ready_runs_callback() {
+        block_on(async {
+            let task = Reactor::current().spawn_on_ready(subscribe_duration(10_000_000), || 42);
+            assert_eq!(task.await, 42);
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use super::local::Locals;
+use crate::runtime::{Reactor, Task, TaskRegistration, budget};
+
+/// An opaque identifier for a task, unique for the lifetime of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(NonZeroU64);
+
+impl TaskId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        Self(NonZeroU64::new(id).expect("task ids exhausted"))
+    }
+
+    /// The numeric value of this identifier.
+    pub fn as_u64(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The identity of a task: its [`TaskId`], the name given to it by
+/// [`Builder::name`], if any, and where it was spawned.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    id: TaskId,
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+}
+
+impl TaskInfo {
+    #[track_caller]
+    pub(crate) fn new(name: Option<Arc<str>>) -> Self {
+        Self {
+            id: TaskId::next(),
+            name,
+            location: Location::caller(),
+        }
+    }
+
+    /// The identifier of the task.
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// The name of the task, if one was given.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The source location at which the task was spawned.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+std::thread_local! {
+    static CURRENT: RefCell<Option<TaskInfo>> = const { RefCell::new(None) };
+}
+
+/// Get the identifier of the currently running task.
+///
+/// # Panics
+///
+/// Panics if called from outside of a task run by `wstd::runtime::block_on`.
+#[track_caller]
+pub fn id() -> TaskId {
+    try_id().expect("wstd::task::id must be called from within a task")
+}
+
+/// Get the identifier of the currently running task, or `None` if called
+/// from outside of a task.
+pub fn try_id() -> Option<TaskId> {
+    CURRENT.with(|current| current.borrow().as_ref().map(TaskInfo::id))
+}
+
+/// Get the identity of the currently running task, or `None` if called from
+/// outside of a task.
+pub fn current() -> Option<TaskInfo> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Configure and spawn a task on the current `Reactor`.
+///
+/// # Examples
+///
+/// ```no_run
+/// wstd::task_local! {
+///     static REQUEST_ID: u64;
+/// }
+///
+/// #[wstd::main]
+/// async fn main() {
+///     REQUEST_ID
+///         .scope(1234, async {
+///             let task = wstd::task::Builder::new()
+///                 .name("child")
+///                 .inherit_locals(true)
+///                 .spawn(async { REQUEST_ID.get() });
+///             assert_eq!(task.await, 1234);
+///         })
+///         .await;
+/// }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Builder {
+    name: Option<Arc<str>>,
+    inherit_locals: bool,
+}
+
+impl Builder {
+    /// Create a new task `Builder`, for an unnamed task which does not
+    /// inherit task-locals.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the task.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into().into());
+        self
+    }
+
+    /// Set whether the task inherits the values of task-locals which are in
+    /// scope at the point `spawn` is called. By default, they are not.
+    pub fn inherit_locals(mut self, inherit: bool) -> Self {
+        self.inherit_locals = inherit;
+        self
+    }
+
+    /// Spawn a `Future` as a `Task` on the current `Reactor`.
+    ///
+    /// Panics if called from outside `block_on`.
+    #[track_caller]
+    pub fn spawn<F, T>(self, fut: F) -> Task<T>
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let locals = if self.inherit_locals {
+            Locals::capture()
+        } else {
+            Locals::default()
+        };
+        Reactor::current().spawn_with(TaskInfo::new(self.name), locals, fut)
+    }
+}
+
+pin_project! {
+    /// Wraps the future of every task spawned on the `Reactor`, so that the
+    /// task's identity and task-locals are visible while it is polled.
+    pub(crate) struct Instrumented<F> {
+        info: TaskInfo,
+        locals: Locals,
+        // Keeps the task registered with the `Reactor` until it completes or
+        // is cancelled.
+        _registration: TaskRegistration,
+        #[pin]
+        fut: F,
+    }
+}
+
+impl<F> Instrumented<F> {
+    pub(crate) fn new(
+        info: TaskInfo,
+        locals: Locals,
+        registration: TaskRegistration,
+        fut: F,
+    ) -> Self {
+        Self {
+            info,
+            locals,
+            _registration: registration,
+            fut,
+        }
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        struct Restore(Option<TaskInfo>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+            }
+        }
+
+        let this = self.project();
+        let prev = CURRENT.with(|current| current.replace(Some(this.info.clone())));
+        let _restore = Restore(prev);
+        let fut = this.fut;
+        budget::with_budget(|| this.locals.enter(|| fut.poll(cx)))
+    }
+}
@@ -0,0 +1,338 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use pin_project_lite::pin_project;
+use slab::Slab;
+
+use crate::time::{Duration, Instant, Timer, Wait};
+
+/// A token which signals cancellation to any number of tasks.
+///
+/// Tokens form a tree: a [child token] is cancelled whenever its parent is,
+/// but cancelling a child does not affect its parent. A token may also have a
+/// deadline, after which it is considered cancelled. A child token's deadline
+/// is never later than its parent's.
+///
+/// Cloning a token gives another handle to the same token.
+///
+/// Tasks can wait for cancellation with [`CancellationToken::cancelled`], or
+/// run a future until it is either complete or cancelled with
+/// [`CancellationToken::run_until_cancelled`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use wstd::task::CancellationToken;
+/// use wstd::time::Duration;
+///
+/// #[wstd::main]
+/// async fn main() {
+///     // Cancel all work for this request after one second.
+///     let request = CancellationToken::new().child_with_timeout(Duration::from_secs(1));
+///
+///     let subtask = wstd::runtime::spawn({
+///         let token = request.child_token();
+///         async move {
+///             token
+///                 .run_until_cancelled(wstd::task::sleep(Duration::from_secs(10)))
+///                 .await
+///         }
+///     });
+///
+///     assert!(subtask.await.is_none(), "subtask was cancelled");
+///     assert!(request.is_cancelled());
+/// }
+/// ```
+///
+/// [child token]: CancellationToken::child_token
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    deadline: Option<Instant>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cancelled: bool,
+    wakers: Slab<Waker>,
+    children: Vec<Weak<Node>>,
+}
+
+impl Node {
+    fn cancel(&self) {
+        let (wakers, children) = {
+            let mut state = self.state.lock().unwrap();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            let wakers = state.wakers.drain().collect::<Vec<_>>();
+            let children = std::mem::take(&mut state.children);
+            (wakers, children)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Create a new token, which is not cancelled and has no deadline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a child token, which is cancelled when this token is cancelled.
+    pub fn child_token(&self) -> Self {
+        self.child(self.node.deadline)
+    }
+
+    /// Create a child token, which is cancelled when this token is
+    /// cancelled, or once `deadline` has passed.
+    pub fn child_with_deadline(&self, deadline: Instant) -> Self {
+        let deadline = match self.node.deadline {
+            Some(parent) => parent.min(deadline),
+            None => deadline,
+        };
+        self.child(Some(deadline))
+    }
+
+    /// Create a child token, which is cancelled when this token is
+    /// cancelled, or once `timeout` has elapsed from now.
+    pub fn child_with_timeout(&self, timeout: Duration) -> Self {
+        self.child_with_deadline(Instant::now() + timeout)
+    }
+
+    fn child(&self, deadline: Option<Instant>) -> Self {
+        let child = Arc::new(Node {
+            deadline,
+            state: Mutex::default(),
+        });
+        let mut state = self.node.state.lock().unwrap();
+        if state.cancelled {
+            child.state.lock().unwrap().cancelled = true;
+        } else {
+            // Forget children which have since been dropped, so a long-lived
+            // parent does not accumulate them.
+            state.children.retain(|c| c.strong_count() > 0);
+            state.children.push(Arc::downgrade(&child));
+        }
+        Self { node: child }
+    }
+
+    /// The deadline after which this token is cancelled, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.node.deadline
+    }
+
+    /// Cancel this token and all of its descendants, waking every task
+    /// waiting on their cancellation.
+    pub fn cancel(&self) {
+        self.node.cancel()
+    }
+
+    /// Whether this token has been cancelled, either explicitly, by a
+    /// parent, or because its deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.node.state.lock().unwrap().cancelled
+            || self
+                .node
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// A future which is ready once this token is cancelled.
+    pub fn cancelled(&self) -> WaitForCancellation {
+        WaitForCancellation {
+            token: self.clone(),
+            waker: None,
+            deadline: None,
+        }
+    }
+
+    /// Run a future until it completes, giving `Some` of its output, or
+    /// until this token is cancelled, giving `None`. If the token is
+    /// cancelled, the future is dropped.
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        futures_lite::future::or(
+            async {
+                self.cancelled().await;
+                None
+            },
+            async { Some(fut.await) },
+        )
+        .await
+    }
+
+    /// Create a guard which cancels this token when dropped. This is useful
+    /// to cancel all subtasks when the task which owns them returns early,
+    /// such as on an error.
+    pub fn drop_guard(self) -> DropGuard {
+        DropGuard { token: Some(self) }
+    }
+}
+
+pin_project! {
+    /// Future created by [`CancellationToken::cancelled`].
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct WaitForCancellation {
+        token: CancellationToken,
+        waker: Option<usize>,
+        // Created on first poll, when the token has a deadline.
+        #[pin]
+        deadline: Option<Wait>,
+    }
+
+    impl PinnedDrop for WaitForCancellation {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(key) = this.waker.take() {
+                let mut state = this.token.node.state.lock().unwrap();
+                if !state.cancelled {
+                    state.wakers.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl Future for WaitForCancellation {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        {
+            let mut state = this.token.node.state.lock().unwrap();
+            if state.cancelled {
+                // Cancellation drained all wakers, including ours.
+                *this.waker = None;
+                return Poll::Ready(());
+            }
+            match *this.waker {
+                Some(key) => state.wakers[key].clone_from(cx.waker()),
+                None => *this.waker = Some(state.wakers.insert(cx.waker().clone())),
+            }
+        }
+        if let Some(deadline) = this.token.node.deadline {
+            if this.deadline.is_none() {
+                this.deadline.set(Some(Timer::at(deadline).wait()));
+            }
+            if let Some(wait) = this.deadline.as_pin_mut()
+                && wait.poll(cx).is_ready()
+            {
+                this.token.cancel();
+                *this.waker = None;
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl std::fmt::Debug for WaitForCancellation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitForCancellation")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A guard which cancels a [`CancellationToken`] when dropped.
+///
+/// Created by [`CancellationToken::drop_guard`].
+#[derive(Debug)]
+pub struct DropGuard {
+    token: Option<CancellationToken>,
+}
+
+impl DropGuard {
+    /// Give back the token without cancelling it.
+    pub fn disarm(mut self) -> CancellationToken {
+        self.token.take().expect("token is present until dropped")
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(token) = self.token.take() {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cancel_propagates_to_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grandchild = child.child_token();
+        let sibling = parent.child_token();
+
+        child.cancel();
+        assert!(!parent.is_cancelled());
+        assert!(!sibling.is_cancelled());
+        assert!(grandchild.is_cancelled());
+
+        parent.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(parent.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn cancel_wakes_waiting_tasks() {
+        crate::runtime::block_on(async {
+            let token = CancellationToken::new();
+            let waiters = (0..3)
+                .map(|_| {
+                    let token = token.child_token();
+                    crate::runtime::spawn(async move {
+                        token
+                            .run_until_cancelled(std::future::pending::<()>())
+                            .await
+                    })
+                })
+                .collect::<Vec<_>>();
+            crate::task::yield_now().await;
+
+            let guard = token.clone().drop_guard();
+            drop(guard);
+            for waiter in waiters {
+                assert_eq!(waiter.await, None);
+            }
+        })
+    }
+
+    #[test]
+    fn dropped_waiter_deregisters() {
+        let token = CancellationToken::new();
+        crate::runtime::block_on(async {
+            let ready = futures_lite::future::poll_once(token.cancelled()).await;
+            assert!(ready.is_none());
+        });
+        assert!(token.node.state.lock().unwrap().wakers.is_empty());
+    }
+
+    #[test]
+    fn deadline_cancels() {
+        crate::runtime::block_on(async {
+            let token = CancellationToken::new().child_with_timeout(Duration::from_millis(10));
+            let child = token.child_with_timeout(Duration::from_secs(60));
+            assert_eq!(child.deadline(), token.deadline());
+            child.cancelled().await;
+            assert!(token.is_cancelled());
+        })
+    }
+}
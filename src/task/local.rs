@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+/// Declare a new task-local storage key of type [`LocalKey`].
+///
+/// Values are set for the duration of a future with [`LocalKey::scope`], and
+/// are visible to all code run while that future is polled, across `.await`
+/// points. Tasks spawned with [`Builder::inherit_locals`] can also see the
+/// values which were in scope when they were spawned.
+///
+/// # Examples
+///
+/// ```no_run
+/// wstd::task_local! {
+///     static REQUEST_ID: String;
+/// }
+///
+/// #[wstd::main]
+/// async fn main() {
+///     REQUEST_ID
+///         .scope("req-1234".to_owned(), async {
+///             wstd::task::sleep(wstd::time::Duration::from_millis(10)).await;
+///             REQUEST_ID.with(|id| assert_eq!(id, "req-1234"));
+///         })
+///         .await;
+/// }
+/// ```
+///
+/// [`Builder::inherit_locals`]: crate::task::Builder::inherit_locals
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task::LocalKey<$t> = {
+            ::std::thread_local! {
+                static __WSTD_TASK_LOCAL: ::std::cell::RefCell<::std::option::Option<::std::sync::Arc<$t>>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+            $crate::task::LocalKey::__new(&__WSTD_TASK_LOCAL, ::std::stringify!($name))
+        };
+        $crate::task_local!($($rest)*);
+    };
+}
+
+type Slot<T> = std::thread::LocalKey<RefCell<Option<Arc<T>>>>;
+
+/// A key for task-local data.
+///
+/// Created with the [`task_local!`] macro. See its documentation for more.
+///
+/// [`task_local!`]: crate::task_local
+pub struct LocalKey<T: 'static> {
+    slot: &'static Slot<T>,
+    name: &'static str,
+}
+
+impl<T: 'static> LocalKey<T> {
+    /// This is used by the `task_local` macro.
+    #[doc(hidden)]
+    pub const fn __new(slot: &'static Slot<T>, name: &'static str) -> Self {
+        Self { slot, name }
+    }
+
+    /// Set the value of this task-local to `value` while `fut` is being
+    /// polled.
+    pub fn scope<F>(&'static self, value: T, fut: F) -> TaskLocalFuture<T, F>
+    where
+        F: Future,
+    {
+        TaskLocalFuture {
+            key: self,
+            value: Some(Arc::new(value)),
+            fut,
+        }
+    }
+
+    /// Set the value of this task-local to `value` while the closure `f` is
+    /// run.
+    pub fn sync_scope<F, R>(&'static self, value: T, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let mut value = Some(Arc::new(value));
+        let _guard = Entered::new(self, &mut value);
+        f()
+    }
+
+    /// Access the value of this task-local.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value has not been set by an enclosing
+    /// [`LocalKey::scope`].
+    #[track_caller]
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        match self.try_with(f) {
+            Ok(r) => r,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Access the value of this task-local, or fail with an [`AccessError`]
+    /// if the value has not been set by an enclosing [`LocalKey::scope`].
+    pub fn try_with<F, R>(&'static self, f: F) -> Result<R, AccessError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        // Clone the `Arc` out of the slot so that `f` may itself set or
+        // access task-locals.
+        let value = self.slot.with(|slot| slot.borrow().clone());
+        match value {
+            Some(value) => Ok(f(&value)),
+            None => Err(AccessError { name: self.name }),
+        }
+    }
+
+    /// Get a clone of the value of this task-local.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value has not been set by an enclosing
+    /// [`LocalKey::scope`].
+    #[track_caller]
+    pub fn get(&'static self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+
+    fn replace(&'static self, value: Option<Arc<T>>) -> Option<Arc<T>> {
+        self.slot.with(|slot| slot.replace(value))
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An error returned by [`LocalKey::try_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessError {
+    name: &'static str,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "task-local `{}` accessed outside of a `LocalKey::scope` which set it",
+            self.name
+        )
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+pin_project! {
+    /// A future which sets a task-local value while it is polled.
+    ///
+    /// This `struct` is created by the [`LocalKey::scope`] method. See its
+    /// documentation for more.
+    #[must_use = "futures do nothing unless polled or .awaited"]
+    pub struct TaskLocalFuture<T: 'static, F> {
+        key: &'static LocalKey<T>,
+        value: Option<Arc<T>>,
+        #[pin]
+        fut: F,
+    }
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = Entered::new(*this.key, this.value);
+        this.fut.poll(cx)
+    }
+}
+
+impl<T: 'static, F: fmt::Debug> fmt::Debug for TaskLocalFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskLocalFuture")
+            .field("key", self.key)
+            .field("fut", &self.fut)
+            .finish_non_exhaustive()
+    }
+}
+
+std::thread_local! {
+    /// The keys of every task-local which is currently set, innermost last.
+    /// Used to capture task-locals for inheritance by spawned tasks.
+    static ACTIVE: RefCell<Vec<&'static dyn ErasedKey>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Moves a value into a task-local slot for the lifetime of the guard, and
+/// moves it back out when dropped.
+struct Entered<'a, T: 'static> {
+    key: &'static LocalKey<T>,
+    value: &'a mut Option<Arc<T>>,
+}
+
+impl<'a, T: 'static> Entered<'a, T> {
+    fn new(key: &'static LocalKey<T>, value: &'a mut Option<Arc<T>>) -> Self {
+        *value = key.replace(value.take());
+        ACTIVE.with(|active| active.borrow_mut().push(key));
+        Self { key, value }
+    }
+}
+
+impl<T: 'static> Drop for Entered<'_, T> {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.borrow_mut().pop());
+        *self.value = self.key.replace(self.value.take());
+    }
+}
+
+/// A type-erased `LocalKey`, so that every active task-local can be captured
+/// regardless of its type.
+trait ErasedKey {
+    fn capture(&'static self) -> Option<Box<dyn CapturedLocal>>;
+}
+
+impl<T: 'static> ErasedKey for LocalKey<T> {
+    fn capture(&'static self) -> Option<Box<dyn CapturedLocal>> {
+        let value = self.slot.with(|slot| slot.borrow().clone())?;
+        Some(Box::new(Captured {
+            key: self,
+            value: Some(value),
+        }))
+    }
+}
+
+/// A task-local value which has been captured from the spawning task.
+trait CapturedLocal {
+    fn key_addr(&self) -> usize;
+    fn enter(&mut self);
+    fn exit(&mut self);
+}
+
+struct Captured<T: 'static> {
+    key: &'static LocalKey<T>,
+    value: Option<Arc<T>>,
+}
+
+impl<T: 'static> CapturedLocal for Captured<T> {
+    fn key_addr(&self) -> usize {
+        self.key as *const LocalKey<T> as usize
+    }
+    fn enter(&mut self) {
+        self.value = self.key.replace(self.value.take());
+        ACTIVE.with(|active| active.borrow_mut().push(self.key));
+    }
+    fn exit(&mut self) {
+        ACTIVE.with(|active| active.borrow_mut().pop());
+        self.value = self.key.replace(self.value.take());
+    }
+}
+
+/// A snapshot of every task-local set at the point it was captured.
+#[derive(Default)]
+pub(crate) struct Locals(Vec<Box<dyn CapturedLocal>>);
+
+impl Locals {
+    /// Capture the task-locals which are set in the current context.
+    pub(crate) fn capture() -> Self {
+        let keys = ACTIVE.with(|active| active.borrow().clone());
+        let mut captured: Vec<Box<dyn CapturedLocal>> = Vec::with_capacity(keys.len());
+        // A key may be set by several nested scopes, but only the innermost
+        // value is visible, and that is the one every capture of it will see.
+        for key in keys.into_iter().rev() {
+            if let Some(local) = key.capture()
+                && !captured.iter().any(|c| c.key_addr() == local.key_addr())
+            {
+                captured.push(local);
+            }
+        }
+        Self(captured)
+    }
+
+    /// Run `f` with the captured task-locals set.
+    pub(crate) fn enter<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        struct Guard<'a>(&'a mut Locals);
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                for local in self.0.0.iter_mut().rev() {
+                    local.exit();
+                }
+            }
+        }
+        for local in self.0.iter_mut() {
+            local.enter();
+        }
+        let _guard = Guard(self);
+        f()
+    }
+}
+
+impl fmt::Debug for Locals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Locals")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    crate::task_local! {
+        static NUMBER: u32;
+        static NAME: &'static str;
+    }
+
+    #[test]
+    fn sync_scope_nests() {
+        assert!(NUMBER.try_with(|_| ()).is_err());
+        NUMBER.sync_scope(1, || {
+            assert_eq!(NUMBER.get(), 1);
+            NUMBER.sync_scope(2, || assert_eq!(NUMBER.get(), 2));
+            assert_eq!(NUMBER.get(), 1);
+        });
+        assert!(NUMBER.try_with(|_| ()).is_err());
+    }
+
+    #[test]
+    fn capture_innermost() {
+        let mut locals = NUMBER.sync_scope(1, || {
+            NAME.sync_scope("outer", || NUMBER.sync_scope(2, Locals::capture))
+        });
+        assert_eq!(locals.0.len(), 2);
+        assert!(NUMBER.try_with(|_| ()).is_err());
+        locals.enter(|| {
+            assert_eq!(NUMBER.get(), 2);
+            assert_eq!(NAME.get(), "outer");
+        });
+        assert!(NAME.try_with(|_| ()).is_err());
+    }
+}
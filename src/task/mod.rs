@@ -0,0 +1,28 @@
+//! Types and Traits for working with asynchronous tasks.
+
+mod builder;
+mod cancellation;
+mod local;
+mod yield_now;
+
+pub use builder::{Builder, TaskId, TaskInfo, current, id, try_id};
+pub use cancellation::{CancellationToken, DropGuard, WaitForCancellation};
+pub use local::{AccessError, LocalKey, TaskLocalFuture};
+pub use yield_now::{YieldNow, yield_now};
+
+pub(crate) use builder::Instrumented;
+pub(crate) use local::Locals;
+
+use crate::time::{Duration, Instant, Timer, Wait};
+
+/// Sleeps for the specified amount of time.
+#[track_caller]
+pub fn sleep(dur: Duration) -> Wait {
+    Timer::after(dur).wait()
+}
+
+/// Sleeps until the specified instant.
+#[track_caller]
+pub fn sleep_until(deadline: Instant) -> Wait {
+    Timer::at(deadline).wait()
+}
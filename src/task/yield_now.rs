@@ -0,0 +1,34 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Yield execution back to the runtime, giving other tasks a chance to run.
+///
+/// The current task is woken immediately, and so is placed at the back of
+/// the ready list. Since pollables are checked whenever tasks are ready to
+/// run, this also gives any task waiting on a ready pollable a chance to make
+/// progress.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Future created by [`yield_now`].
+#[must_use = "futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
@@ -15,6 +15,9 @@ pub struct Instant(pub(crate) monotonic_clock::Instant);
 impl Instant {
     /// Returns an instant corresponding to "now".
     ///
+    /// Under a [`test_runtime`](crate::runtime::test_runtime), this is the
+    /// reading of its virtual clock.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -24,7 +27,9 @@ impl Instant {
     /// ```
     #[must_use]
     pub fn now() -> Self {
-        Instant(wasip2::clocks::monotonic_clock::now())
+        crate::runtime::REACTOR
+            .with(|r| r.borrow().as_ref().and_then(|r| r.virtual_now()))
+            .unwrap_or_else(|| Instant(wasip2::clocks::monotonic_clock::now()))
     }
 
     /// Returns the amount of time elapsed from another instant to this one, or zero duration if
@@ -18,7 +18,7 @@ use wasip2::clocks::{
 
 use crate::{
     iter::AsyncIterator,
-    runtime::{AsyncPollable, Reactor},
+    runtime::{AsyncPollable, Reactor, test_runtime::TimerKey},
 };
 
 /// A measurement of the system clock, useful for talking to external entities
@@ -63,26 +63,53 @@ impl AsyncIterator for Interval {
 }
 
 #[derive(Debug)]
-pub struct Timer(Option<AsyncPollable>);
+pub struct Timer(Option<Deadline>);
+
+/// What a [`Timer`] waits on: the host's clock, or the virtual clock of a
+/// [`test_runtime`](crate::runtime::test_runtime).
+#[derive(Debug)]
+enum Deadline {
+    Host(AsyncPollable),
+    Virtual(Instant),
+}
 
 impl Timer {
     pub fn never() -> Timer {
         Timer(None)
     }
+    #[track_caller]
     pub fn at(deadline: Instant) -> Timer {
-        let pollable = Reactor::current().schedule(subscribe_instant(deadline.0));
-        Timer(Some(pollable))
+        let reactor = Reactor::current();
+        if reactor.virtual_now().is_some() {
+            return Timer(Some(Deadline::Virtual(deadline)));
+        }
+        let pollable = reactor.schedule(subscribe_instant(deadline.0));
+        Timer(Some(Deadline::Host(pollable)))
     }
+    #[track_caller]
     pub fn after(duration: Duration) -> Timer {
-        let pollable = Reactor::current().schedule(subscribe_duration(duration.0));
-        Timer(Some(pollable))
+        let reactor = Reactor::current();
+        if let Some(now) = reactor.virtual_now() {
+            return Timer(Some(Deadline::Virtual(now + duration)));
+        }
+        let pollable = reactor.schedule(subscribe_duration(duration.0));
+        Timer(Some(Deadline::Host(pollable)))
     }
+    #[track_caller]
     pub fn set_after(&mut self, duration: Duration) {
         *self = Self::after(duration);
     }
     pub fn wait(&self) -> Wait {
-        let wait_for = self.0.as_ref().map(AsyncPollable::wait_for);
-        Wait { wait_for }
+        let (wait_for, deadline) = match &self.0 {
+            None => (None, None),
+            Some(Deadline::Host(pollable)) => (Some(pollable.wait_for()), None),
+            Some(Deadline::Virtual(deadline)) => (None, Some(*deadline)),
+        };
+        Wait {
+            wait_for,
+            deadline,
+            timer: None,
+        }
     }
 }
 
@@ -91,7 +118,24 @@ pin_project! {
     #[must_use = "futures do nothing unless polled or .awaited"]
     pub struct Wait {
         #[pin]
-        wait_for: Option<crate::runtime::WaitFor>
+        wait_for: Option<crate::runtime::WaitFor>,
+        // The deadline on the virtual clock, and the timer registered for
+        // it once polled, under a test runtime.
+        deadline: Option<Instant>,
+        timer: Option<TimerKey>,
+    }
+
+    impl PinnedDrop for Wait {
+        fn drop(this: Pin<&mut Self>) {
+            // The runtime may already be gone, taking its timers with it.
+            if let Some(timer) = this.project().timer.take() {
+                crate::runtime::REACTOR.with(|r| {
+                    if let Some(reactor) = r.borrow().as_ref() {
+                        reactor.deregister_timer(timer);
+                    }
+                });
+            }
+        }
     }
 }
 
@@ -100,6 +144,20 @@ impl Future for Wait {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
+        if let Some(deadline) = *this.deadline {
+            let reactor = Reactor::current();
+            let now = reactor
+                .virtual_now()
+                .expect("virtual timer must be polled within its test runtime");
+            if now >= deadline {
+                if let Some(timer) = this.timer.take() {
+                    reactor.deregister_timer(timer);
+                }
+                return Poll::Ready(now);
+            }
+            reactor.register_timer(deadline, this.timer, cx.waker());
+            return Poll::Pending;
+        }
         match this.wait_for.as_pin_mut() {
             None => Poll::Pending,
             Some(f) => match f.poll(cx) {
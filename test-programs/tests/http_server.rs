@@ -85,6 +85,24 @@ fn http_server() -> Result<()> {
     }
     assert!(duration >= Duration::from_millis(500));
 
+    // TEST /events http_events
+    // Sends 3 Server-Sent Events as a streaming `text/event-stream` body,
+    // without a Content-Length.
+    let mut response = ureq::get("http://127.0.0.1:8081/events").call()?;
+    assert_eq!(
+        response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok()),
+        Some("text/event-stream")
+    );
+    assert!(response.headers().get("content-length").is_none());
+    let body: String = response.body_mut().read_to_string()?;
+    assert_eq!(
+        body,
+        "id: 0\ndata: tick 0\n\nid: 1\ndata: tick 1\n\nid: 2\ndata: tick 2\n\n"
+    );
+
     // TEST /echo htto_echo
     // Send a request body, see that we got the same back in response body.
     const MESSAGE: &[u8] = b"hello, echoserver!\n";
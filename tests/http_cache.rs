@@ -0,0 +1,41 @@
+use wstd::http::cache::{Cache, CacheStatus};
+use wstd::http::{Client, ResponseExt};
+
+#[wstd::test]
+async fn http_cache() -> Result<(), Box<dyn std::error::Error>> {
+    // The server echoes the query as response headers, so the response is
+    // fresh for a minute.
+    let uri = "https://postman-echo.com/response-headers?Cache-Control=max-age%3D60";
+    let mut client = Client::new();
+    client.set_cache(Cache::in_memory(10));
+
+    let mut response = client.get(uri).send().await?.error_for_status()?;
+    assert_eq!(
+        response.extensions().get::<CacheStatus>(),
+        Some(&CacheStatus::Miss)
+    );
+    let first = response.body_mut().str_contents().await?.to_owned();
+
+    // Once the body has been read, the response is stored.
+    let mut response = client.get(uri).send().await?;
+    assert_eq!(
+        response.extensions().get::<CacheStatus>(),
+        Some(&CacheStatus::Hit)
+    );
+    assert!(response.headers().contains_key("age"));
+    let second = response.body_mut().str_contents().await?;
+    assert_eq!(first, second);
+
+    // A request with no-cache goes to the server.
+    let response = client
+        .get(uri)
+        .header("cache-control", "no-cache")
+        .send()
+        .await?;
+    assert_ne!(
+        response.extensions().get::<CacheStatus>(),
+        Some(&CacheStatus::Hit)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "gzip")]
+
+use serde_json::Value;
+use wstd::http::{Client, ResponseExt};
+
+#[wstd::test]
+async fn http_gzip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut response = Client::new()
+        .get("https://postman-echo.com/gzip")
+        .send()
+        .await?
+        .error_for_status()?;
+    assert!(response.headers().get("content-encoding").is_none());
+
+    // The response body is decompressed as it is read.
+    let body: Value = response.body_mut().json().await?;
+    assert_eq!(body["gzipped"], true);
+
+    Ok(())
+}
+
+#[wstd::test]
+async fn http_gzip_accept_encoding_set() -> Result<(), Box<dyn std::error::Error>> {
+    // A request which sets its own `Accept-Encoding` gets the body as sent.
+    let response = Client::new()
+        .get("https://postman-echo.com/gzip")
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await?
+        .error_for_status()?;
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+
+    Ok(())
+}
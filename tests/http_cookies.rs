@@ -0,0 +1,45 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use wstd::http::cookie::CookieJar;
+use wstd::http::{Client, ResponseExt};
+
+#[derive(Deserialize)]
+struct Cookies {
+    cookies: HashMap<String, String>,
+}
+
+#[wstd::test]
+async fn http_cookies() -> Result<(), Box<dyn std::error::Error>> {
+    let jar = CookieJar::new();
+    let mut client = Client::new();
+    client.set_cookie_jar(jar.clone());
+
+    // The response sets the cookie, and redirects to `/cookies`.
+    client
+        .get("https://postman-echo.com/cookies/set?flavor=oatmeal")
+        .send()
+        .await?;
+    assert!(jar.cookies().iter().any(|c| c.name() == "flavor"));
+
+    let mut response = client
+        .get("https://postman-echo.com/cookies")
+        .send()
+        .await?
+        .error_for_status()?;
+    let echoed: Cookies = response.body_mut().json().await?;
+    assert_eq!(
+        echoed.cookies.get("flavor").map(String::as_str),
+        Some("oatmeal")
+    );
+
+    // The jar can be saved, and loaded by a later invocation.
+    let loaded = CookieJar::from_json(&jar.to_json()?)?;
+    let names = |jar: &CookieJar| {
+        let mut names: Vec<_> = jar.cookies().iter().map(|c| c.name().to_owned()).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(&loaded), names(&jar));
+
+    Ok(())
+}
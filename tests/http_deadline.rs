@@ -0,0 +1,31 @@
+use wstd::http::{Client, ResponseExt, deadline};
+use wstd::time::Duration;
+
+#[wstd::test]
+async fn http_deadline() -> Result<(), Box<dyn std::error::Error>> {
+    // The server waits 1 second before responding, which is past the
+    // client's timeout.
+    let mut client = Client::new();
+    client.set_timeout(Duration::from_millis(500));
+    let error = client
+        .get("https://postman-echo.com/delay/1")
+        .send()
+        .await
+        .unwrap_err();
+    assert!(error.is_timeout(), "expected a timeout, got: {error:?}");
+
+    // A deadline given for the request is sent to the server in a header.
+    let mut client = Client::new();
+    client.set_deadline_header(deadline::Header::GrpcTimeout);
+    let mut response = client
+        .get("https://postman-echo.com/headers")
+        .deadline(deadline::Deadline::after(Duration::from_secs(10)))
+        .send()
+        .await?
+        .error_for_status()?;
+    let body: serde_json::Value = response.body_mut().json().await?;
+    let timeout = body["headers"]["grpc-timeout"].as_str().unwrap();
+    assert!(timeout.ends_with('u'), "unexpected grpc-timeout {timeout}");
+
+    Ok(())
+}
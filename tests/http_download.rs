@@ -0,0 +1,32 @@
+use wstd::http::Client;
+use wstd::io::Cursor;
+
+#[wstd::test]
+async fn http_download() -> Result<(), Box<dyn std::error::Error>> {
+    let mut updates = 0;
+    let mut body = Cursor::new(Vec::new());
+    let progress = Client::new()
+        .download("https://postman-echo.com/get?download=1")
+        .header("X-Wstd-Test", "download")
+        .progress(|_| updates += 1)
+        .write_to(&mut body)
+        .await?;
+    let body = body.into_inner();
+    assert!(updates > 0);
+    assert_eq!(progress.downloaded(), body.len() as u64);
+    let json: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(json["args"]["download"], "1");
+    assert_eq!(json["headers"]["x-wstd-test"], "download");
+
+    // A parallel download falls back to a single request when the server
+    // doesn't support ranges, or has no strong ETag to validate them with.
+    let mut parallel = Cursor::new(Vec::new());
+    let progress = Client::new()
+        .download("https://postman-echo.com/get?download=1")
+        .parallel(4, 64)
+        .write_to(&mut parallel)
+        .await?;
+    assert_eq!(progress.downloaded(), parallel.into_inner().len() as u64);
+
+    Ok(())
+}
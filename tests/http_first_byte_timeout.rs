@@ -13,10 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     assert!(result.is_err(), "response should be an error");
     let error = result.unwrap_err();
     assert!(
-        matches!(
-            error.downcast_ref::<ErrorCode>(),
-            Some(ErrorCode::ConnectionReadTimeout)
-        ),
+        matches!(error.error_code(), Some(ErrorCode::ConnectionReadTimeout)),
         "expected ConnectionReadTimeout error, got: {error:?>}"
     );
 
@@ -1,4 +1,4 @@
-use wstd::http::{Body, Client, Request, error::ErrorCode};
+use wstd::http::{Body, Client, Request, error::ErrorKind};
 
 /// Test that `outgoing_handler::handle` errors are properly propagated.
 #[wstd::test]
@@ -13,7 +13,7 @@ async fn handle_returns_error_code() -> Result<(), Box<dyn std::error::Error>> {
     );
     let error = result.unwrap_err();
     assert!(
-        error.downcast_ref::<ErrorCode>().is_some(),
+        matches!(error.kind(), ErrorKind::ErrorCode(_)),
         "expected an ErrorCode, got: {error:?}"
     );
 
@@ -0,0 +1,35 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use wstd::http::multipart::{Form, Part};
+use wstd::http::{Client, ResponseExt};
+
+#[derive(Deserialize)]
+struct Echo {
+    form: HashMap<String, String>,
+    files: HashMap<String, String>,
+}
+
+#[wstd::test]
+async fn http_multipart() -> Result<(), Box<dyn std::error::Error>> {
+    let form = Form::new().text("flavor", "oatmeal").part(
+        "recipe",
+        Part::text("oats, raisins")
+            .file_name("recipe.txt")
+            .content_type("text/plain")?,
+    );
+
+    let mut response = Client::new()
+        .post("https://postman-echo.com/post")
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?;
+    let echoed: Echo = response.body_mut().json().await?;
+    assert_eq!(
+        echoed.form.get("flavor").map(String::as_str),
+        Some("oatmeal")
+    );
+    assert!(echoed.files.contains_key("recipe.txt"));
+
+    Ok(())
+}
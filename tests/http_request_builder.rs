@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use wstd::http::{Client, ResponseExt, StatusCode, error::ErrorKind};
+
+#[derive(Serialize)]
+struct TestData {
+    test: String,
+}
+
+#[derive(Deserialize)]
+struct Echo {
+    url: String,
+    headers: std::collections::HashMap<String, String>,
+}
+
+#[wstd::test]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let client = Client::new();
+    let mut response = client
+        .post("https://postman-echo.com/post")
+        .header("X-Wstd-Test", "builder")
+        .bearer_auth("token")
+        .json(&TestData {
+            test: "data".to_string(),
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let Echo { url, headers } = response.body_mut().json::<Echo>().await?;
+    assert!(
+        url.contains("postman-echo.com/post"),
+        "expected body url to contain the authority and path, got: {url}"
+    );
+    assert_eq!(headers["x-wstd-test"], "builder");
+    assert_eq!(headers["authorization"], "Bearer token");
+    assert_eq!(headers["content-type"], "application/json");
+
+    let err = client
+        .get("https://postman-echo.com/status/404")
+        .send()
+        .await?
+        .error_for_status()
+        .unwrap_err();
+    match err.kind() {
+        ErrorKind::Status(e) => assert_eq!(e.status(), StatusCode::NOT_FOUND),
+        kind => panic!("expected a status error, got {kind:?}"),
+    }
+
+    Ok(())
+}
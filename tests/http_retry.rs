@@ -0,0 +1,48 @@
+use std::sync::{Arc, Mutex};
+use wstd::http::retry::Classification;
+use wstd::http::{Body, Client, Method, Request, StatusCode, retry};
+use wstd::time::Duration;
+
+#[wstd::test]
+async fn http_retry() -> Result<(), Box<dyn std::error::Error>> {
+    // The server always responds with 503 Service Unavailable, so every
+    // attempt is retried until the policy stops, and the last response is
+    // returned. The policy records the outcome of each attempt, and stops
+    // after the third rather than at `max_attempts`, so that the last
+    // attempt is recorded too.
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let seen = outcomes.clone();
+    let mut client = Client::new();
+    client.set_retry_policy(
+        retry::Policy::new()
+            .max_attempts(10)
+            .backoff(Duration::from_millis(10), Duration::from_millis(50))
+            .classify(move |outcome| {
+                seen.lock()
+                    .unwrap()
+                    .push((outcome.method().clone(), outcome.attempt()));
+                if outcome.attempt() < 3 {
+                    outcome.classify_default()
+                } else {
+                    Classification::Stop
+                }
+            }),
+    );
+    let request = Request::get("https://postman-echo.com/status/503").body(Body::empty())?;
+    let response = client.send(request).await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [(Method::GET, 1), (Method::GET, 2), (Method::GET, 3)]
+    );
+
+    // A non-idempotent request is sent only once, and its response returned
+    // without consulting the policy.
+    outcomes.lock().unwrap().clear();
+    let request = Request::post("https://postman-echo.com/status/503").body("data")?;
+    let response = client.send(request).await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(*outcomes.lock().unwrap(), []);
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use wstd::task::{Builder, sleep};
+use wstd::time::Duration;
+
+wstd::task_local! {
+    static REQUEST_ID: String;
+}
+
+#[wstd::test]
+async fn scope_across_await() {
+    REQUEST_ID
+        .scope("outer".to_owned(), async {
+            sleep(Duration::from_millis(10)).await;
+            assert_eq!(REQUEST_ID.get(), "outer");
+        })
+        .await;
+    assert!(REQUEST_ID.try_with(|_| ()).is_err());
+}
+
+#[wstd::test]
+async fn spawned_tasks_inherit_when_asked() {
+    REQUEST_ID
+        .scope("parent".to_owned(), async {
+            let parent = wstd::task::id();
+
+            let inherits = Builder::new()
+                .name("inherits")
+                .inherit_locals(true)
+                .spawn(async move {
+                    sleep(Duration::from_millis(10)).await;
+                    let current = wstd::task::current().unwrap();
+                    assert_ne!(current.id(), parent);
+                    assert_eq!(current.name(), Some("inherits"));
+                    REQUEST_ID.get()
+                });
+            let isolated = wstd::runtime::spawn(async { REQUEST_ID.try_with(|_| ()).is_err() });
+
+            assert_eq!(inherits.await, "parent");
+            assert!(isolated.await, "task-locals are not inherited by default");
+        })
+        .await;
+}
@@ -0,0 +1,26 @@
+use std::error::Error;
+use wstd::future::FutureExt;
+use wstd::runtime::test_runtime;
+use wstd::task::sleep;
+use wstd::time::{Duration, Instant, Timer};
+
+#[wstd::test(virtual_time)]
+async fn sleep_for_a_day() -> Result<(), Box<dyn Error>> {
+    assert!(test_runtime::is_virtual());
+    let start = Instant::now();
+    sleep(Duration::from_secs(24 * 60 * 60)).await;
+    assert_eq!(start.elapsed(), Duration::from_secs(24 * 60 * 60));
+    Ok(())
+}
+
+#[wstd::test(virtual_time)]
+async fn timeout_is_exact() -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let res = Timer::never()
+        .wait()
+        .timeout(Duration::from_millis(250))
+        .await;
+    assert!(res.is_err());
+    assert_eq!(start.elapsed(), Duration::from_millis(250));
+    Ok(())
+}